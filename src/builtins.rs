@@ -0,0 +1,1893 @@
+use crate::interpreter::{
+    call_function, compare_values, print_values, to_key, unquoted, InterpreterResult, RuntimeError,
+};
+use crate::semantic_analysis::{
+    check_comparable, function_signature, resolve_type, types_compatible, CompileError,
+};
+use crate::sequence::Sequence;
+use crate::symboltable::SymbolTable;
+use crate::syntax::{DataType, Expr, KeyData, LiteralData};
+use crate::value::Value;
+use indexmap::{IndexMap, IndexSet};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+// A function supplied by the interpreter rather than defined by the program. A program's
+// own definitions take precedence.
+pub trait Builtin {
+    fn name(&self) -> &str;
+
+    // The type a call gives back for arguments of these types, or why they don't fit.
+    // 'args' are the argument expressions themselves, for builtins taking a function whose
+    // signature matters.
+    fn signature(
+        &self,
+        args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError>;
+
+    // Runs a call on its already evaluated arguments.
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult;
+
+    // Whether the builtin reaches outside the interpreter (randomness, the clock, the
+    // environment). Programs prepared without system access can't call these.
+    fn needs_system_access(&self) -> bool {
+        false
+    }
+}
+
+// The builtins a program can call, by name. Analysis turns calls to these into builtin
+// calls unless the program defines the name itself.
+#[derive(Clone)]
+pub struct BuiltinRegistry {
+    builtins: IndexMap<String, Rc<dyn Builtin>>,
+}
+
+impl Default for BuiltinRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuiltinRegistry {
+    // Starts out with the standard builtins.
+    pub fn new() -> Self {
+        let mut registry = BuiltinRegistry {
+            builtins: IndexMap::new(),
+        };
+        registry.register(Sort);
+        registry.register(Reverse);
+        registry.register(Len);
+        registry.register(Upper);
+        registry.register(Format);
+        registry.register(MinMax("min"));
+        registry.register(MinMax("max"));
+        registry.register(Reduce("sum"));
+        registry.register(Reduce("product"));
+        registry.register(Reduce("avg"));
+        registry.register(AnyAll("any"));
+        registry.register(AnyAll("all"));
+        registry.register(MapLookup("contains_key"));
+        registry.register(MapLookup("get_or"));
+        registry.register(ToList);
+        registry.register(ToSet);
+        registry.register(Parse("parse_int"));
+        registry.register(Parse("parse_flt"));
+        registry.register(IntMath("gcd"));
+        registry.register(IntMath("lcm"));
+        registry.register(IntMath("factorial"));
+        registry.register(FltMath("sin"));
+        registry.register(FltMath("cos"));
+        registry.register(FltMath("tan"));
+        registry.register(FltMath("exp"));
+        registry.register(FltMath("ln"));
+        registry.register(FltMath("log10"));
+        registry.register(FltMath("log"));
+        registry.register(Clamp);
+        registry.register(Lerp);
+        registry.register(Random("random"));
+        registry.register(Random("random_int"));
+        registry.register(Random("seed"));
+        registry.register(Clock("now"));
+        registry.register(Clock("monotonic"));
+        registry.register(Env);
+        registry.register(Args);
+        registry.register(Tap);
+        registry.register(Flush);
+        registry.register(AssertEq);
+        registry.register(Optionals("some"));
+        registry.register(Optionals("none"));
+        registry.register(Optionals("is_some"));
+        registry.register(Optionals("unwrap"));
+        registry.register(Sequences("range"));
+        registry.register(Sequences("iterate"));
+        registry.register(Sequences("map"));
+        registry.register(Sequences("filter"));
+        registry.register(Sequences("take"));
+        registry.register(Fold);
+        registry
+    }
+
+    // Replaces any builtin already registered under the same name.
+    pub fn register(&mut self, builtin: impl Builtin + 'static) {
+        self.builtins
+            .insert(builtin.name().to_string(), Rc::new(builtin));
+    }
+
+    // Makes a Rust closure callable from Lift. Calls get checked against 'signature', a
+    // function type like the one 'function(Int): Int' names, and the closure sees its
+    // arguments and gives its result as plain Values. An Err it returns becomes a runtime
+    // error with that message. Panics if 'signature' isn't a function type.
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        signature: DataType,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let DataType::Function {
+            params,
+            return_type,
+        } = signature
+        else {
+            panic!(
+                "register_native() for '{}' needs a function type, got '{}'",
+                name, signature
+            );
+        };
+        self.register(NativeFunction {
+            name: name.to_string(),
+            params,
+            return_type: *return_type,
+            function: Box::new(function),
+        });
+    }
+
+    // Callers get their own handle so the builtin can be given the symbol table it lives in.
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Builtin>> {
+        self.builtins.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.builtins.contains_key(name)
+    }
+
+    // Whether 'name' is a builtin that can't be called without system access.
+    pub fn needs_system_access(&self, name: &str) -> bool {
+        self.builtins
+            .get(name)
+            .is_some_and(|builtin| builtin.needs_system_access())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.builtins.keys().map(|name| name.as_str())
+    }
+}
+
+// What a builtin's eval() gives for arguments its signature should have kept out.
+fn cant_apply(name: &str, args: &[Expr]) -> InterpreterResult {
+    let msg = format!("Builtin function '{}' can't be applied to {:?}", name, args);
+    Err(RuntimeError::new(&msg, None, None).into())
+}
+
+fn check_arg_count(
+    name: &str,
+    expected: usize,
+    arg_types: &[DataType],
+) -> Result<(), CompileError> {
+    if arg_types.len() == expected {
+        return Ok(());
+    }
+    let msg = match expected {
+        0 => format!("{}() takes no arguments, got {}", name, arg_types.len()),
+        1 => format!("{}() takes one argument, got {}", name, arg_types.len()),
+        _ => format!(
+            "{}() takes {} arguments, got {}",
+            name,
+            expected,
+            arg_types.len()
+        ),
+    };
+    Err(CompileError::structure(&msg, (0, 0)))
+}
+
+struct Sort;
+
+impl Builtin for Sort {
+    fn name(&self) -> &str {
+        "sort"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::List { element_type } => {
+                check_comparable(&element_type, symbols, current_scope_id)?;
+                Ok(arg_types[0].clone())
+            }
+            DataType::Unsolved => Ok(DataType::Unsolved),
+            other => {
+                let msg = format!("sort() expects a List, got '{}'", other);
+                Err(CompileError::typecheck(&msg, (0, 0)))
+            }
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        match args {
+            [Expr::RuntimeList { data_type, data }] => {
+                let mut sorted = data.clone();
+                sorted.sort_by(sort_order);
+                Ok(Expr::RuntimeList {
+                    data_type: data_type.clone(),
+                    data: sorted,
+                })
+            }
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// The order sort() puts values in. NaN can't be compared, so it goes after every other
+// Flt, and the sort being stable keeps values that don't compare in their original order.
+fn sort_order(left: &Expr, right: &Expr) -> Ordering {
+    let is_nan = |value: &Expr| matches!(value, Expr::Literal(LiteralData::Flt(f)) if f.is_nan());
+    match (is_nan(left), is_nan(right)) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => compare_values(left, right).unwrap_or(Ordering::Equal),
+    }
+}
+
+struct Reverse;
+
+impl Builtin for Reverse {
+    fn name(&self) -> &str {
+        "reverse"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::List { .. } => Ok(arg_types[0].clone()),
+            DataType::Unsolved => Ok(DataType::Unsolved),
+            other => {
+                let msg = format!("reverse() expects a List, got '{}'", other);
+                Err(CompileError::typecheck(&msg, (0, 0)))
+            }
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        match args {
+            [Expr::RuntimeList { data_type, data }] => Ok(Expr::RuntimeList {
+                data_type: data_type.clone(),
+                data: data.iter().rev().cloned().collect(),
+            }),
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// The number of characters in a Str, or of entries in a collection.
+struct Len;
+
+impl Builtin for Len {
+    fn name(&self) -> &str {
+        "len"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::Str
+            | DataType::List { .. }
+            | DataType::Map { .. }
+            | DataType::Set(_)
+            | DataType::Unsolved => Ok(DataType::Int),
+            other => {
+                let msg = format!("len() expects a Str, List, Map or Set, got '{}'", other);
+                Err(CompileError::typecheck(&msg, (0, 0)))
+            }
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let length = match args {
+            [Expr::Literal(LiteralData::Str(s))] => unquoted(s).chars().count(),
+            [Expr::RuntimeList { data, .. }] => data.len(),
+            [Expr::RuntimeMap { data, .. }] => data.len(),
+            [Expr::RuntimeSet { data, .. }] => data.len(),
+            _ => return cant_apply(self.name(), args),
+        };
+        Ok(Expr::Literal(LiteralData::Int(length as i64)))
+    }
+}
+
+struct Upper;
+
+impl Builtin for Upper {
+    fn name(&self) -> &str {
+        "upper"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        if !types_compatible(&DataType::Str, &arg_types[0]) {
+            let msg = format!("upper() expects a Str, got '{}'", arg_types[0]);
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        Ok(DataType::Str)
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        match args {
+            [Expr::Literal(LiteralData::Str(s))] => Ok(Expr::Literal(LiteralData::Str(
+                format!("'{}'", unquoted(s).to_uppercase()).into(),
+            ))),
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// min/max take either two values, or a list whose smallest/largest element is returned as
+// an Optional since the list might be empty.
+struct MinMax(&'static str);
+
+impl Builtin for MinMax {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        match arg_types {
+            [list_type] => match resolve_type(list_type, symbols, current_scope_id) {
+                DataType::List { element_type } => {
+                    check_comparable(&element_type, symbols, current_scope_id)?;
+                    Ok(DataType::Optional(element_type))
+                }
+                DataType::Unsolved => Ok(DataType::Unsolved),
+                other => {
+                    let msg = format!(
+                        "{}() of a single value expects a List, got '{}'",
+                        self.0, other
+                    );
+                    Err(CompileError::typecheck(&msg, (0, 0)))
+                }
+            },
+            [left_type, right_type] => {
+                if !types_compatible(left_type, right_type) {
+                    let msg = format!(
+                        "{}() arguments must have the same type, got '{}' and '{}'",
+                        self.0, left_type, right_type
+                    );
+                    return Err(CompileError::typecheck(&msg, (0, 0)));
+                }
+                check_comparable(left_type, symbols, current_scope_id)?;
+                if matches!(left_type, DataType::Unsolved) {
+                    Ok(right_type.clone())
+                } else {
+                    Ok(left_type.clone())
+                }
+            }
+            _ => {
+                let msg = format!(
+                    "{}() takes a List or two values, got {} arguments",
+                    self.0,
+                    arg_types.len()
+                );
+                Err(CompileError::structure(&msg, (0, 0)))
+            }
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let is_min = self.0 == "min";
+        match args {
+            [Expr::RuntimeList { data, .. }] => {
+                let wanted = if is_min {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+                let mut best: Option<&Expr> = None;
+                for item in data {
+                    match best {
+                        Some(current) if compare_values(item, current) != Some(wanted) => (),
+                        _ => best = Some(item),
+                    }
+                }
+                Ok(Expr::RuntimeOptional(best.map(|b| Box::new(b.clone()))))
+            }
+            [left, right] => {
+                let wanted = if is_min {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                };
+                if compare_values(left, right) == Some(wanted) {
+                    Ok(right.clone())
+                } else {
+                    Ok(left.clone())
+                }
+            }
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// sum, product and avg of a list of numbers.
+struct Reduce(&'static str);
+
+impl Builtin for Reduce {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        let element_type = match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::List { element_type } => *element_type,
+            DataType::Unsolved => DataType::Unsolved,
+            other => {
+                let msg = format!("{}() expects a List, got '{}'", self.0, other);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        };
+        if !matches!(
+            element_type,
+            DataType::Int | DataType::Flt | DataType::Unsolved
+        ) {
+            let msg = format!(
+                "{}() expects a List of Int or Flt, got a List of '{}'",
+                self.0, element_type
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        if self.0 == "avg" {
+            Ok(DataType::Flt)
+        } else {
+            Ok(element_type)
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let [Expr::RuntimeList { data_type, data }] = args else {
+            return cant_apply(self.name(), args);
+        };
+        if self.0 != "avg" {
+            return reduce_numbers(self.0, data_type, data);
+        }
+        if data.is_empty() {
+            let msg = "avg() of an empty list";
+            return Err(RuntimeError::new(msg, None, None).into());
+        }
+        let total = match reduce_numbers("sum", data_type, data)? {
+            Expr::Literal(LiteralData::Int(i)) => i as f64,
+            Expr::Literal(LiteralData::Flt(f)) => f,
+            other => panic!("Interpreter error: sum() produced {:?}", other),
+        };
+        Ok(Expr::Literal(LiteralData::Flt(total / data.len() as f64)))
+    }
+}
+
+// Adds or multiplies a list of numbers. Int overflow is an error rather than wrapping around.
+// An empty list gives the identity value, typed by the list's element type when it's known.
+fn reduce_numbers(fn_name: &str, data_type: &DataType, data: &[Expr]) -> InterpreterResult {
+    let is_sum = fn_name == "sum";
+    let mut total = match (data.first(), data_type) {
+        (Some(Expr::Literal(LiteralData::Flt(_))), _) | (None, DataType::Flt) => {
+            LiteralData::Flt(if is_sum { 0.0 } else { 1.0 })
+        }
+        _ => LiteralData::Int(if is_sum { 0 } else { 1 }),
+    };
+    for item in data {
+        total = match (&total, item) {
+            (LiteralData::Int(t), Expr::Literal(LiteralData::Int(i))) => {
+                let result = if is_sum {
+                    t.checked_add(*i)
+                } else {
+                    t.checked_mul(*i)
+                };
+                match result {
+                    Some(result) => LiteralData::Int(result),
+                    None => {
+                        let msg = format!("Integer overflow in {}()", fn_name);
+                        return Err(RuntimeError::new(&msg, None, None).into());
+                    }
+                }
+            }
+            (LiteralData::Flt(t), Expr::Literal(LiteralData::Flt(f))) => {
+                LiteralData::Flt(if is_sum { t + f } else { t * f })
+            }
+            _ => {
+                let msg = format!("{}() can't add {} to {}", fn_name, item, total);
+                return Err(RuntimeError::new(&msg, None, None).into());
+            }
+        };
+    }
+    Ok(Expr::Literal(total))
+}
+
+struct AnyAll(&'static str);
+
+impl Builtin for AnyAll {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        if arg_types.len() != 2 {
+            let msg = format!(
+                "{}() takes a List and a predicate, got {} arguments",
+                self.0,
+                arg_types.len()
+            );
+            return Err(CompileError::structure(&msg, (0, 0)));
+        }
+        let element_type = match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::List { element_type } => *element_type,
+            DataType::Unsolved => DataType::Unsolved,
+            other => {
+                let msg = format!("{}() expects a List, got '{}'", self.0, other);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        };
+        if let Some((param_types, return_type)) = function_signature(&args[1], symbols) {
+            let param_ok = matches!(param_types.as_slice(), [param_type] if types_compatible(param_type, &element_type));
+            if !param_ok || !types_compatible(&DataType::Bool, &return_type) {
+                let msg = format!(
+                    "{}() predicate must take one '{}' and return Bool",
+                    self.0, element_type
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        }
+        Ok(DataType::Bool)
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        let [Expr::RuntimeList { data, .. }, Expr::Lambda {
+            value,
+            environment,
+            captured,
+        }] = args
+        else {
+            return cant_apply(self.name(), args);
+        };
+        // Stop at the first element that settles the answer.
+        let stop_at = self.0 == "any";
+        for item in data {
+            let result = call_function(symbols, value, *environment, captured, vec![item.clone()])?;
+            if result.has_value(&LiteralData::Bool(stop_at)) {
+                return Ok(Expr::Literal(LiteralData::Bool(stop_at)));
+            }
+        }
+        Ok(Expr::Literal(LiteralData::Bool(!stop_at)))
+    }
+}
+
+// contains_key(map, key) and get_or(map, key, default).
+struct MapLookup(&'static str);
+
+impl Builtin for MapLookup {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        let is_get_or = self.0 == "get_or";
+        check_arg_count(self.name(), if is_get_or { 3 } else { 2 }, arg_types)?;
+        let (key_type, value_type) = match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::Map {
+                key_type,
+                value_type,
+            } => (*key_type, *value_type),
+            DataType::Unsolved => (DataType::Unsolved, DataType::Unsolved),
+            other => {
+                let msg = format!("{}() expects a Map, got '{}'", self.0, other);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        };
+        if !types_compatible(&key_type, &arg_types[1]) {
+            let msg = format!(
+                "{}() key must be '{}', got '{}'",
+                self.0, key_type, arg_types[1]
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        if !is_get_or {
+            return Ok(DataType::Bool);
+        }
+        if !types_compatible(&value_type, &arg_types[2]) {
+            let msg = format!(
+                "get_or() default must be '{}', got '{}'",
+                value_type, arg_types[2]
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        if matches!(value_type, DataType::Unsolved) {
+            Ok(arg_types[2].clone())
+        } else {
+            Ok(value_type)
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        match args {
+            [Expr::RuntimeMap { data, .. }, key] if self.0 == "contains_key" => Ok(Expr::Literal(
+                LiteralData::Bool(data.contains_key(&to_key(key)?)),
+            )),
+            [Expr::RuntimeMap { data, .. }, key, default] => {
+                Ok(data.get(&to_key(key)?).unwrap_or(default).clone())
+            }
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// Sets become lists in their insertion order; maps contribute their keys.
+struct ToList;
+
+impl Builtin for ToList {
+    fn name(&self) -> &str {
+        "to_list"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::Set(element_type)
+            | DataType::Seq(element_type)
+            | DataType::Map {
+                key_type: element_type,
+                ..
+            } => Ok(DataType::List { element_type }),
+            DataType::Unsolved => Ok(DataType::Unsolved),
+            other => {
+                let msg = format!("to_list() expects a Set, Map or Seq, got '{}'", other);
+                Err(CompileError::typecheck(&msg, (0, 0)))
+            }
+        }
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        match args {
+            [Expr::RuntimeSet { data_type, data }] => Ok(keys_to_list(data_type, data.iter())),
+            [Expr::RuntimeMap { key_type, data, .. }] => Ok(keys_to_list(key_type, data.keys())),
+            // Never finishes for an endless sequence; take() is the way to get part of one.
+            [Expr::RuntimeSeq(sequence)] => pull_items(sequence, None, symbols),
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+fn keys_to_list<'a>(data_type: &DataType, keys: impl Iterator<Item = &'a KeyData>) -> Expr {
+    Expr::RuntimeList {
+        data_type: data_type.clone(),
+        data: keys.map(|k| Expr::Literal(k.clone().into())).collect(),
+    }
+}
+
+struct ToSet;
+
+impl Builtin for ToSet {
+    fn name(&self) -> &str {
+        "to_set"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::List { element_type } => {
+                match resolve_type(&element_type, symbols, current_scope_id) {
+                    DataType::Int | DataType::Str | DataType::Bool | DataType::Unsolved => {
+                        Ok(DataType::Set(element_type))
+                    }
+                    other => {
+                        let msg = format!("Can't make a Set of '{}'", other);
+                        Err(CompileError::typecheck(&msg, (0, 0)))
+                    }
+                }
+            }
+            DataType::Unsolved => Ok(DataType::Unsolved),
+            other => {
+                let msg = format!("to_set() expects a List, got '{}'", other);
+                Err(CompileError::typecheck(&msg, (0, 0)))
+            }
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let [Expr::RuntimeList { data_type, data }] = args else {
+            return cant_apply(self.name(), args);
+        };
+        let mut items = IndexSet::with_capacity(data.len());
+        for item in data {
+            items.insert(to_key(item)?);
+        }
+        Ok(Expr::RuntimeSet {
+            data_type: data_type.clone(),
+            data: items,
+        })
+    }
+}
+
+// Bad input gives 'none' rather than an error, so callers can handle it.
+struct Parse(&'static str);
+
+impl Builtin for Parse {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        if !types_compatible(&DataType::Str, &arg_types[0]) {
+            let msg = format!("{}() expects a Str, got '{}'", self.0, arg_types[0]);
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        let parsed_type = if self.0 == "parse_int" {
+            DataType::Int
+        } else {
+            DataType::Flt
+        };
+        Ok(DataType::Optional(Box::new(parsed_type)))
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let [Expr::Literal(LiteralData::Str(text))] = args else {
+            return cant_apply(self.name(), args);
+        };
+        let text = unquoted(text);
+        let parsed = if self.0 == "parse_int" {
+            text.parse::<i64>().ok().map(LiteralData::Int)
+        } else {
+            text.parse::<f64>().ok().map(LiteralData::Flt)
+        };
+        Ok(Expr::RuntimeOptional(
+            parsed.map(|p| Box::new(Expr::Literal(p))),
+        ))
+    }
+}
+
+// gcd, lcm and factorial, which fail rather than overflow.
+struct IntMath(&'static str);
+
+impl Builtin for IntMath {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        let expected_args = if self.0 == "factorial" { 1 } else { 2 };
+        check_int_args(self.name(), expected_args, arg_types)?;
+        Ok(DataType::Int)
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        match (self.0, args) {
+            ("gcd", [Expr::Literal(LiteralData::Int(a)), Expr::Literal(LiteralData::Int(b))]) => {
+                let result = i64::try_from(gcd(a.unsigned_abs(), b.unsigned_abs()));
+                checked_int(self.0, result.ok())
+            }
+            ("lcm", [Expr::Literal(LiteralData::Int(a)), Expr::Literal(LiteralData::Int(b))]) => {
+                // lcm with zero is zero, which is also where the gcd is zero.
+                let divisor = gcd(a.unsigned_abs(), b.unsigned_abs());
+                let result = match a.unsigned_abs().checked_div(divisor) {
+                    None => Some(0),
+                    Some(quotient) => quotient
+                        .checked_mul(b.unsigned_abs())
+                        .and_then(|r| i64::try_from(r).ok()),
+                };
+                checked_int(self.0, result)
+            }
+            ("factorial", [Expr::Literal(LiteralData::Int(n))]) => {
+                if *n < 0 {
+                    let msg = format!("factorial() of a negative number: {}", n);
+                    return Err(RuntimeError::new(&msg, None, None).into());
+                }
+                let result = (2..=*n).try_fold(1i64, |product, i| product.checked_mul(i));
+                checked_int(self.0, result)
+            }
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// sin, cos, tan, exp, ln, log10 and log(base, x) of Flts, or of Ints taken as Flts. Like
+// the operators they don't fail outside their domain but give NaN or an infinity, so
+// ln(0 - 1) is NaN and ln(0) is -inf.
+struct FltMath(&'static str);
+
+impl Builtin for FltMath {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        let expected_args = if self.0 == "log" { 2 } else { 1 };
+        check_arg_count(self.name(), expected_args, arg_types)?;
+        if let Some(bad_type) = arg_types
+            .iter()
+            .map(|t| resolve_type(t, symbols, current_scope_id))
+            .find(|t| !matches!(t, DataType::Int | DataType::Flt | DataType::Unsolved))
+        {
+            let msg = format!("{}() expects Flt arguments, got '{}'", self.0, bad_type);
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        Ok(DataType::Flt)
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let as_flt = |arg: &Expr| match arg {
+            Expr::Literal(LiteralData::Int(n)) => Some(*n as f64),
+            Expr::Literal(LiteralData::Flt(f)) => Some(*f),
+            _ => None,
+        };
+        let Some(values) = args.iter().map(as_flt).collect::<Option<Vec<f64>>>() else {
+            return cant_apply(self.name(), args);
+        };
+        let result = match (self.0, values.as_slice()) {
+            ("sin", [x]) => x.sin(),
+            ("cos", [x]) => x.cos(),
+            ("tan", [x]) => x.tan(),
+            ("exp", [x]) => x.exp(),
+            ("ln", [x]) => x.ln(),
+            ("log10", [x]) => x.log10(),
+            ("log", [base, x]) => x.log(*base),
+            _ => return cant_apply(self.name(), args),
+        };
+        Ok(Expr::Literal(LiteralData::Flt(result)))
+    }
+}
+
+fn check_int_args(name: &str, expected: usize, arg_types: &[DataType]) -> Result<(), CompileError> {
+    check_arg_count(name, expected, arg_types)?;
+    if let Some(bad_type) = arg_types
+        .iter()
+        .find(|t| !types_compatible(&DataType::Int, t))
+    {
+        let msg = format!("{}() expects Int arguments, got '{}'", name, bad_type);
+        return Err(CompileError::typecheck(&msg, (0, 0)));
+    }
+    Ok(())
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Wraps the result of checked Int arithmetic, where None means it overflowed.
+fn checked_int(fn_name: &str, result: Option<i64>) -> InterpreterResult {
+    match result {
+        Some(result) => Ok(Expr::Literal(LiteralData::Int(result))),
+        None => {
+            let msg = format!("Integer overflow in {}()", fn_name);
+            Err(RuntimeError::new(&msg, None, None).into())
+        }
+    }
+}
+
+// clamp(x, lo, hi) keeps x within the bounds, which all have x's type.
+struct Clamp;
+
+impl Builtin for Clamp {
+    fn name(&self) -> &str {
+        "clamp"
+    }
+
+    fn signature(
+        &self,
+        args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 3, arg_types)?;
+        let resolved: Vec<DataType> = arg_types
+            .iter()
+            .map(|t| resolve_type(t, symbols, current_scope_id))
+            .collect();
+        let value_type = resolved
+            .iter()
+            .find(|t| !matches!(t, DataType::Unsolved))
+            .cloned()
+            .unwrap_or(DataType::Unsolved);
+        if !matches!(
+            value_type,
+            DataType::Int | DataType::Flt | DataType::Unsolved
+        ) {
+            let msg = format!("clamp() expects an Int or Flt, got '{}'", value_type);
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        if let Some(bad_type) = resolved.iter().find(|t| !types_compatible(&value_type, t)) {
+            let msg = format!(
+                "clamp() arguments must have the same type, got '{}' and '{}'",
+                value_type, bad_type
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        // Bounds written out as literals can be checked now rather than when it runs.
+        if let [_, Expr::Literal(lo), Expr::Literal(hi)] = args {
+            if compare_values(&Expr::Literal(lo.clone()), &Expr::Literal(hi.clone()))
+                == Some(Ordering::Greater)
+            {
+                let msg = format!("clamp() lower bound {} is above upper bound {}", lo, hi);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        }
+        Ok(value_type)
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let [value, lo, hi] = args else {
+            return cant_apply(self.name(), args);
+        };
+        if compare_values(lo, hi) == Some(Ordering::Greater) {
+            let msg = format!(
+                "clamp() lower bound {} is above upper bound {}",
+                lo.pretty(),
+                hi.pretty()
+            );
+            return Err(RuntimeError::new(&msg, None, None).into());
+        }
+        if compare_values(value, lo) == Some(Ordering::Less) {
+            Ok(lo.clone())
+        } else if compare_values(value, hi) == Some(Ordering::Greater) {
+            Ok(hi.clone())
+        } else {
+            Ok(value.clone())
+        }
+    }
+}
+
+// lerp(a, b, t) is the point a fraction t of the way from a to b, always as a Flt.
+struct Lerp;
+
+impl Builtin for Lerp {
+    fn name(&self) -> &str {
+        "lerp"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 3, arg_types)?;
+        if let Some(bad_type) = arg_types
+            .iter()
+            .map(|t| resolve_type(t, symbols, current_scope_id))
+            .find(|t| !matches!(t, DataType::Int | DataType::Flt | DataType::Unsolved))
+        {
+            let msg = format!("lerp() expects Int or Flt arguments, got '{}'", bad_type);
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        Ok(DataType::Flt)
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let as_flt = |arg: &Expr| match arg {
+            Expr::Literal(LiteralData::Int(n)) => Some(*n as f64),
+            Expr::Literal(LiteralData::Flt(f)) => Some(*f),
+            _ => None,
+        };
+        match args
+            .iter()
+            .map(as_flt)
+            .collect::<Option<Vec<f64>>>()
+            .as_deref()
+        {
+            Some([a, b, t]) => Ok(Expr::Literal(LiteralData::Flt(a + (b - a) * t))),
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// random() gives a Flt in [0, 1), random_int(lo, hi) an Int with both bounds included, and
+// seed(n) makes the numbers after it repeatable.
+struct Random(&'static str);
+
+impl Builtin for Random {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        let expected_args = match self.0 {
+            "random" => 0,
+            "seed" => 1,
+            _ => 2,
+        };
+        check_int_args(self.name(), expected_args, arg_types)?;
+        match self.0 {
+            "random" => Ok(DataType::Flt),
+            "seed" => Ok(DataType::Unsolved),
+            _ => Ok(DataType::Int),
+        }
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        symbols.host.check_access(self.0)?;
+        match (self.0, args) {
+            ("random", []) => Ok(Expr::Literal(LiteralData::Flt(
+                symbols.host.next_random_flt(),
+            ))),
+            (
+                "random_int",
+                [Expr::Literal(LiteralData::Int(lo)), Expr::Literal(LiteralData::Int(hi))],
+            ) => {
+                if lo > hi {
+                    let msg = format!("random_int() range is empty: {} to {}", lo, hi);
+                    return Err(RuntimeError::new(&msg, None, None).into());
+                }
+                let span = (*hi as i128 - *lo as i128 + 1) as u128;
+                let offset = symbols.host.next_random() as u128 % span;
+                Ok(Expr::Literal(LiteralData::Int(
+                    (*lo as i128 + offset as i128) as i64,
+                )))
+            }
+            ("seed", [Expr::Literal(LiteralData::Int(n))]) => {
+                symbols.host.seed(*n);
+                Ok(Expr::Unit)
+            }
+            _ => cant_apply(self.name(), args),
+        }
+    }
+
+    fn needs_system_access(&self) -> bool {
+        true
+    }
+}
+
+// now() is wall clock time and monotonic() only ever goes up; both are Ints.
+struct Clock(&'static str);
+
+impl Builtin for Clock {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 0, arg_types)?;
+        Ok(DataType::Int)
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        if !args.is_empty() {
+            return cant_apply(self.name(), args);
+        }
+        symbols.host.check_access(self.0)?;
+        let time = if self.0 == "now" {
+            symbols.host.now()
+        } else {
+            symbols.host.monotonic()
+        };
+        Ok(Expr::Literal(LiteralData::Int(time)))
+    }
+
+    fn needs_system_access(&self) -> bool {
+        true
+    }
+}
+
+struct Env;
+
+impl Builtin for Env {
+    fn name(&self) -> &str {
+        "env"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        if !types_compatible(&DataType::Str, &arg_types[0]) {
+            let msg = format!("env() expects a Str, got '{}'", arg_types[0]);
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        Ok(DataType::Optional(Box::new(DataType::Str)))
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        let [Expr::Literal(LiteralData::Str(name))] = args else {
+            return cant_apply(self.name(), args);
+        };
+        symbols.host.check_access(self.name())?;
+        let value = symbols.host.env_var(unquoted(name));
+        Ok(Expr::RuntimeOptional(value.map(|v| {
+            Box::new(Expr::Literal(LiteralData::Str(format!("'{}'", v).into())))
+        })))
+    }
+
+    fn needs_system_access(&self) -> bool {
+        true
+    }
+}
+
+struct Args;
+
+impl Builtin for Args {
+    fn name(&self) -> &str {
+        "args"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 0, arg_types)?;
+        Ok(DataType::List {
+            element_type: Box::new(DataType::Str),
+        })
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        if !args.is_empty() {
+            return cant_apply(self.name(), args);
+        }
+        symbols.host.check_access(self.name())?;
+        Ok(Expr::RuntimeList {
+            data_type: DataType::Str,
+            data: symbols
+                .host
+                .program_args
+                .iter()
+                .map(|a| Expr::Literal(LiteralData::Str(format!("'{}'", a).into())))
+                .collect(),
+        })
+    }
+
+    fn needs_system_access(&self) -> bool {
+        true
+    }
+}
+
+// Prints its argument like output() does and gives it back, for looking at the values
+// passing through the middle of an expression.
+struct Tap;
+
+impl Builtin for Tap {
+    fn name(&self) -> &str {
+        "tap"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 1, arg_types)?;
+        Ok(arg_types[0].clone())
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        let [value] = args else {
+            return cant_apply(self.name(), args);
+        };
+        print_values(symbols, args);
+        Ok(value.clone())
+    }
+}
+
+// Writes out what output() and tap() have printed but the host's buffering is still
+// holding (see host::Buffering).
+struct Flush;
+
+impl Builtin for Flush {
+    fn name(&self) -> &str {
+        "flush"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 0, arg_types)?;
+        Ok(DataType::Unsolved)
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        if !args.is_empty() {
+            return cant_apply(self.name(), args);
+        }
+        symbols.host.flush();
+        Ok(Expr::Unit)
+    }
+}
+
+// Fails unless its two arguments are equal, saying where inside them they first differ
+// (which index, field or key) and what's there on each side.
+struct AssertEq;
+
+impl Builtin for AssertEq {
+    fn name(&self) -> &str {
+        "assert_eq"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 2, arg_types)?;
+        let resolve = |t: &DataType| resolve_type(t, symbols, current_scope_id);
+        if !types_compatible(&resolve(&arg_types[0]), &resolve(&arg_types[1])) {
+            let msg = format!(
+                "assert_eq() arguments must have the same type, got '{}' and '{}'",
+                arg_types[0], arg_types[1]
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        Ok(DataType::Unsolved)
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let [left, right] = args else {
+            return cant_apply(self.name(), args);
+        };
+        let msg = match first_difference(left, right) {
+            None => return Ok(Expr::Unit),
+            Some((path, _, _)) if path.is_empty() => {
+                format!("assert_eq(): {} isn't equal to {}", left, right)
+            }
+            Some((path, left_part, right_part)) => format!(
+                "assert_eq(): {} and {} differ at {}: {} vs {}",
+                left,
+                right,
+                path.join(", "),
+                left_part,
+                right_part
+            ),
+        };
+        Err(RuntimeError::new(&msg, None, None).into())
+    }
+}
+
+// Where two values first differ, as the steps down to it ('index 2', 'field 'x'', 'key
+// 'a'') and the parts found there on each side, or None if they're equal. A part only one
+// side has shows as 'nothing' on the other.
+fn first_difference(left: &Expr, right: &Expr) -> Option<(Vec<String>, String, String)> {
+    let within = |step: String, left: &Expr, right: &Expr| {
+        first_difference(left, right).map(|(mut path, left_part, right_part)| {
+            path.insert(0, step);
+            (path, left_part, right_part)
+        })
+    };
+    let one_sided = |step: String, left: Option<&Expr>, right: Option<&Expr>| {
+        let show = |part: Option<&Expr>| part.map_or("nothing".to_string(), |p| p.to_string());
+        Some((vec![step], show(left), show(right)))
+    };
+    match (left, right) {
+        (Expr::RuntimeList { data: l_data, .. }, Expr::RuntimeList { data: r_data, .. }) => {
+            for i in 0..l_data.len().max(r_data.len()) {
+                let step = format!("index {}", i);
+                match (l_data.get(i), r_data.get(i)) {
+                    (Some(l_value), Some(r_value)) => {
+                        if let Some(difference) = within(step, l_value, r_value) {
+                            return Some(difference);
+                        }
+                    }
+                    (l_value, r_value) => return one_sided(step, l_value, r_value),
+                }
+            }
+            None
+        }
+        (
+            Expr::RuntimeStruct {
+                type_name: l_type,
+                fields: l_fields,
+            },
+            Expr::RuntimeStruct {
+                type_name: r_type,
+                fields: r_fields,
+            },
+        ) if l_type == r_type => {
+            l_fields
+                .iter()
+                .zip(r_fields)
+                .find_map(|((name, l_value), (_, r_value))| {
+                    within(format!("field '{}'", name), l_value, r_value)
+                })
+        }
+        (Expr::RuntimeMap { data: l_data, .. }, Expr::RuntimeMap { data: r_data, .. }) => {
+            let step = |key: &KeyData| format!("key {}", Expr::Literal(key.clone().into()));
+            for (key, l_value) in l_data {
+                let difference = match r_data.get(key) {
+                    Some(r_value) => within(step(key), l_value, r_value),
+                    None => one_sided(step(key), Some(l_value), None),
+                };
+                if difference.is_some() {
+                    return difference;
+                }
+            }
+            r_data
+                .iter()
+                .find(|(key, _)| !l_data.contains_key(*key))
+                .and_then(|(key, r_value)| one_sided(step(key), None, Some(r_value)))
+        }
+        (Expr::Literal(_), Expr::Literal(_)) | (Expr::EnumValue { .. }, Expr::EnumValue { .. })
+            if compare_values(left, right) == Some(Ordering::Equal) =>
+        {
+            None
+        }
+        (Expr::RuntimeSet { data: l_data, .. }, Expr::RuntimeSet { data: r_data, .. })
+            if l_data == r_data =>
+        {
+            None
+        }
+        (Expr::RuntimeOptional(None), Expr::RuntimeOptional(None)) => None,
+        // Differing optionals show whole unless the difference is further in.
+        (Expr::RuntimeOptional(Some(l_value)), Expr::RuntimeOptional(Some(r_value))) => {
+            match first_difference(l_value, r_value)? {
+                (path, _, _) if path.is_empty() => {
+                    Some((path, left.to_string(), right.to_string()))
+                }
+                difference => Some(difference),
+            }
+        }
+        _ => Some((Vec::new(), left.to_string(), right.to_string())),
+    }
+}
+
+// Making and taking apart Optional values: some(x) and none() make them, is_some() tells
+// which one a value is and unwrap() gets at what's inside, failing on none.
+struct Optionals(&'static str);
+
+impl Builtin for Optionals {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        if self.0 == "none" {
+            // Fits any Optional; what it would hold is left to where it ends up.
+            check_arg_count(self.name(), 0, arg_types)?;
+            return Ok(DataType::Optional(Box::new(DataType::Unsolved)));
+        }
+        check_arg_count(self.name(), 1, arg_types)?;
+        if self.0 == "some" {
+            return Ok(DataType::Optional(Box::new(arg_types[0].clone())));
+        }
+        let inner_type = match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::Optional(inner_type) => *inner_type,
+            DataType::Unsolved => DataType::Unsolved,
+            other => {
+                let msg = format!("{}() expects an Optional, got '{}'", self.0, other);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        };
+        if self.0 == "is_some" {
+            Ok(DataType::Bool)
+        } else {
+            Ok(inner_type)
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        match (self.0, args) {
+            ("none", []) => Ok(Expr::RuntimeOptional(None)),
+            ("some", [value]) => Ok(Expr::RuntimeOptional(Some(Box::new(value.clone())))),
+            ("is_some", [Expr::RuntimeOptional(inner)]) => {
+                Ok(Expr::Literal(LiteralData::Bool(inner.is_some())))
+            }
+            ("unwrap", [Expr::RuntimeOptional(Some(inner))]) => Ok(inner.as_ref().clone()),
+            ("unwrap", [Expr::RuntimeOptional(None)]) => {
+                Err(RuntimeError::new("unwrap() of none", None, None).into())
+            }
+            _ => cant_apply(self.name(), args),
+        }
+    }
+}
+
+// Lazy sequences (see sequence.rs). range(a, b) counts from a to b inclusive and
+// iterate(x, f) gives x, f(x), f(f(x)) and so on without end. map() and filter() take a
+// List or Seq and give a Seq, computing nothing until take(seq, n) asks for the first n
+// elements as a List.
+struct Sequences(&'static str);
+
+impl Builtin for Sequences {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn signature(
+        &self,
+        args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        if self.0 == "range" {
+            check_int_args(self.name(), 2, arg_types)?;
+            return Ok(DataType::Seq(Box::new(DataType::Int)));
+        }
+        check_arg_count(self.name(), 2, arg_types)?;
+        let element_type = if self.0 == "iterate" {
+            arg_types[0].clone()
+        } else {
+            match resolve_type(&arg_types[0], symbols, current_scope_id) {
+                DataType::List { element_type } | DataType::Seq(element_type) => *element_type,
+                DataType::Unsolved => DataType::Unsolved,
+                other => {
+                    let msg = format!("{}() expects a List or Seq, got '{}'", self.0, other);
+                    return Err(CompileError::typecheck(&msg, (0, 0)));
+                }
+            }
+        };
+        if self.0 == "take" {
+            if !types_compatible(&DataType::Int, &arg_types[1]) {
+                let msg = format!("take() expects an Int count, got '{}'", arg_types[1]);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+            return Ok(DataType::List {
+                element_type: Box::new(element_type),
+            });
+        }
+        let Some((param_types, return_type)) = function_signature(&args[1], symbols) else {
+            return Ok(DataType::Seq(Box::new(DataType::Unsolved)));
+        };
+        let expected_return = match self.0 {
+            "filter" => DataType::Bool,
+            "iterate" => element_type.clone(),
+            _ => DataType::Unsolved,
+        };
+        let param_ok = matches!(param_types.as_slice(), [param_type] if types_compatible(param_type, &element_type));
+        if !param_ok || !types_compatible(&expected_return, &return_type) {
+            let msg = match self.0 {
+                "filter" => format!(
+                    "filter() predicate must take one '{}' and return Bool",
+                    element_type
+                ),
+                _ => format!(
+                    "{}() function must take one '{}'{}",
+                    self.0,
+                    element_type,
+                    if self.0 == "iterate" {
+                        " and return the same type"
+                    } else {
+                        ""
+                    }
+                ),
+            };
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        Ok(DataType::Seq(Box::new(match self.0 {
+            "map" => return_type,
+            _ => element_type,
+        })))
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        let sequence = match (self.0, args) {
+            (
+                "range",
+                [Expr::Literal(LiteralData::Int(start)), Expr::Literal(LiteralData::Int(end))],
+            ) => Sequence::Count {
+                start: *start,
+                end: Some(*end),
+            },
+            ("iterate", [start, step @ Expr::Lambda { .. }]) => Sequence::Iterate {
+                start: Box::new(start.clone()),
+                step: Box::new(step.clone()),
+            },
+            ("map", [source, function @ Expr::Lambda { .. }]) => Sequence::Map {
+                source: Box::new(as_sequence(self.name(), source)?),
+                function: Box::new(function.clone()),
+            },
+            ("filter", [source, predicate @ Expr::Lambda { .. }]) => Sequence::Filter {
+                source: Box::new(as_sequence(self.name(), source)?),
+                predicate: Box::new(predicate.clone()),
+            },
+            ("take", [source, Expr::Literal(LiteralData::Int(count))]) => {
+                let Ok(count) = usize::try_from(*count) else {
+                    let msg = format!("take() can't take {} elements", count);
+                    return Err(RuntimeError::new(&msg, None, None).into());
+                };
+                return pull_items(&as_sequence(self.name(), source)?, Some(count), symbols);
+            }
+            _ => return cant_apply(self.name(), args),
+        };
+        Ok(Expr::RuntimeSeq(sequence))
+    }
+}
+
+fn as_sequence(name: &str, value: &Expr) -> Result<Sequence, Box<dyn std::error::Error>> {
+    match value {
+        Expr::RuntimeSeq(sequence) => Ok(sequence.clone()),
+        Expr::RuntimeList { data, .. } => Ok(Sequence::Items(data.clone())),
+        _ => {
+            let msg = format!("{}() can't make a sequence of {}", name, value);
+            Err(RuntimeError::new(&msg, None, None).into())
+        }
+    }
+}
+
+// The first 'count' elements of a sequence, or all of them, as a List.
+fn pull_items(
+    sequence: &Sequence,
+    count: Option<usize>,
+    symbols: &mut SymbolTable,
+) -> InterpreterResult {
+    let mut cursor = sequence.cursor();
+    let mut data = Vec::new();
+    while count.is_none_or(|count| data.len() < count) {
+        match cursor.next(symbols)? {
+            Some(item) => data.push(item),
+            None => break,
+        }
+    }
+    Ok(Expr::RuntimeList {
+        data_type: DataType::Unsolved,
+        data,
+    })
+}
+
+// reduce(xs, start, f) combines the elements of a List or Seq in order, from f(start, first)
+// on, as in 'reduce(xs, 0, (+))'.
+struct Fold;
+
+impl Builtin for Fold {
+    fn name(&self) -> &str {
+        "reduce"
+    }
+
+    fn signature(
+        &self,
+        args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), 3, arg_types)?;
+        let element_type = match resolve_type(&arg_types[0], symbols, current_scope_id) {
+            DataType::List { element_type } | DataType::Seq(element_type) => *element_type,
+            DataType::Unsolved => DataType::Unsolved,
+            other => {
+                let msg = format!("reduce() expects a List or Seq, got '{}'", other);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        };
+        let start_type = &arg_types[1];
+        let Some((param_types, return_type)) = function_signature(&args[2], symbols) else {
+            return Ok(start_type.clone());
+        };
+        let params_ok = matches!(param_types.as_slice(),
+            [total_type, item_type] if types_compatible(total_type, start_type)
+                && types_compatible(item_type, &element_type));
+        if !params_ok || !types_compatible(start_type, &return_type) {
+            let msg = format!(
+                "reduce() function must take a '{}' and a '{}' and return a '{}'",
+                start_type, element_type, start_type
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+        if matches!(start_type, DataType::Unsolved) {
+            Ok(return_type)
+        } else {
+            Ok(start_type.clone())
+        }
+    }
+
+    fn eval(&self, args: &[Expr], symbols: &mut SymbolTable) -> InterpreterResult {
+        let [source, start, Expr::Lambda {
+            value,
+            environment,
+            captured,
+        }] = args
+        else {
+            return cant_apply(self.name(), args);
+        };
+        let mut cursor = as_sequence(self.name(), source)?.cursor();
+        let mut total = start.clone();
+        while let Some(item) = cursor.next(symbols)? {
+            total = call_function(symbols, value, *environment, captured, vec![total, item])?;
+        }
+        Ok(total)
+    }
+}
+
+struct Format;
+
+impl Builtin for Format {
+    fn name(&self) -> &str {
+        "format"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        match arg_types.first() {
+            None => {
+                let msg = "format() needs a template string".to_string();
+                return Err(CompileError::structure(&msg, (0, 0)));
+            }
+            Some(template_type) if !types_compatible(&DataType::Str, template_type) => {
+                let msg = format!("format() template must be a Str, got '{}'", template_type);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+            _ => (),
+        }
+        Ok(DataType::Str)
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let [Expr::Literal(LiteralData::Str(template)), values @ ..] = args else {
+            return cant_apply(self.name(), args);
+        };
+        match format_template(unquoted(template), values) {
+            Ok(formatted) => Ok(Expr::Literal(LiteralData::Str(
+                format!("'{}'", formatted).into(),
+            ))),
+            Err(msg) => Err(RuntimeError::new(&msg, None, None).into()),
+        }
+    }
+}
+
+// The text a value contributes when placed inside a string. Strings don't bring their quotes.
+fn to_str(value: &Expr) -> String {
+    match value {
+        Expr::Literal(LiteralData::Str(s)) => unquoted(s).to_string(),
+        _ => value.to_string(),
+    }
+}
+
+// Replaces each '{}' in 'template' with the next of 'values'; '{{' and '}}' stand for
+// literal braces. A placeholder may hold a spec after a colon, like '{:.2}' (see
+// format_value).
+fn format_template(template: &str, values: &[Expr]) -> Result<String, String> {
+    let mut formatted = String::new();
+    let mut next_value = values.iter();
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                formatted.push(c);
+            }
+            ('{', Some('}' | ':')) => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(spec_char) => spec.push(spec_char),
+                        None => {
+                            return Err(format!("Unmatched '{{' in format template '{}'", template))
+                        }
+                    }
+                }
+                placeholders += 1;
+                if let Some(value) = next_value.next() {
+                    let spec = spec.strip_prefix(':').unwrap_or_default();
+                    formatted.push_str(&format_value(value, spec)?);
+                }
+            }
+            ('{', _) | ('}', _) => {
+                return Err(format!(
+                    "Unmatched '{}' in format template '{}'; use '{}{}' for a literal brace",
+                    c, template, c, c
+                ))
+            }
+            _ => formatted.push(c),
+        }
+    }
+    if placeholders != values.len() {
+        return Err(format!(
+            "Format template '{}' has {} placeholders but {} values were given",
+            template,
+            placeholders,
+            values.len()
+        ));
+    }
+    Ok(formatted)
+}
+
+// Formats one value by a placeholder spec of the form '[0][width][.precision]': the width
+// pads to that many characters (with zeros for '0', which only numbers take) and the
+// precision gives a Flt that many decimal places.
+fn format_value(value: &Expr, spec: &str) -> Result<String, String> {
+    let unsupported = || format!("Unsupported format spec '{{:{}}}' for {}", spec, value);
+    let (width_spec, precision) = match spec.split_once('.') {
+        Some((width_spec, precision)) => (
+            width_spec,
+            Some(precision.parse::<usize>().map_err(|_| unsupported())?),
+        ),
+        None => (spec, None),
+    };
+    let zero_pad = width_spec.len() > 1 && width_spec.starts_with('0');
+    let width = match width_spec {
+        "" => 0,
+        _ => width_spec.parse::<usize>().map_err(|_| unsupported())?,
+    };
+    match (value, precision) {
+        (Expr::Literal(LiteralData::Flt(f)), Some(precision)) if zero_pad => {
+            Ok(format!("{:0width$.precision$}", f))
+        }
+        (Expr::Literal(LiteralData::Flt(f)), Some(precision)) => {
+            Ok(format!("{:width$.precision$}", f))
+        }
+        (Expr::Literal(LiteralData::Flt(f)), None) if zero_pad => Ok(format!("{:0width$}", f)),
+        (Expr::Literal(LiteralData::Int(i)), None) if zero_pad => Ok(format!("{:0width$}", i)),
+        (Expr::Literal(LiteralData::Int(i)), None) => Ok(format!("{:width$}", i)),
+        (_, None) if !zero_pad => Ok(format!("{:width$}", to_str(value))),
+        _ => Err(unsupported()),
+    }
+}
+
+// What register_native() takes: plain Values in, a Value or an error message out.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+struct NativeFunction {
+    name: String,
+    params: Vec<DataType>,
+    return_type: DataType,
+    function: Box<NativeFn>,
+}
+
+impl Builtin for NativeFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        symbols: &SymbolTable,
+        current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        check_arg_count(self.name(), self.params.len(), arg_types)?;
+        for (position, (param_type, arg_type)) in self.params.iter().zip(arg_types).enumerate() {
+            let found = resolve_type(arg_type, symbols, current_scope_id);
+            if !types_compatible(param_type, &found) {
+                let msg = format!(
+                    "Argument {} of '{}' should be '{}', got '{}'",
+                    position + 1,
+                    self.name,
+                    param_type,
+                    arg_type
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+        }
+        Ok(self.return_type.clone())
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        let mut values = Vec::with_capacity(args.len());
+        for a in args {
+            values.push(Value::try_from(a)?);
+        }
+        match (self.function)(&values) {
+            Ok(result) => Ok(result.to_runtime(&self.return_type)?),
+            Err(msg) => {
+                let msg = format!("{}(): {}", self.name, msg);
+                Err(RuntimeError::new(&msg, None, None).into())
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::interpreter::RuntimeError;
+use std::error::Error;
+use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// How much fully buffered output is held before it's written anyway.
+pub const OUTPUT_BUFFER_SIZE: usize = 8 * 1024;
+
+// When what the program prints actually gets written. However it's buffered, output is
+// flushed by flush(), when the program finishes and when it fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Buffering {
+    // Written as soon as it's printed.
+    Unbuffered,
+    // Written a line at a time.
+    #[default]
+    Line,
+    // Held until OUTPUT_BUFFER_SIZE bytes build up, for programs that print a lot.
+    Full,
+}
+
+// Builtins that reach outside the running program (randomness, the clock, environment
+// variables) go through here. Embeddings wanting deterministic, sandboxed runs turn off
+// system access.
+#[derive(Clone, Debug)]
+pub struct Host {
+    pub allow_system_access: bool,
+    // Command-line arguments following the program file, as seen by args().
+    pub program_args: Vec<String>,
+    // State of the xorshift generator behind random(); seeded from the clock on first
+    // use unless seed() was called.
+    rng_state: Option<u64>,
+    // The reference point for monotonic().
+    started: Instant,
+    // What the program prints, when it's being kept instead of going to stdout.
+    captured_output: Option<String>,
+    pub buffering: Buffering,
+    // Printed but not yet written out.
+    pending_output: String,
+    // Report every step the interpreter takes (see trace()).
+    pub trace: bool,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Self {
+            allow_system_access: true,
+            program_args: Vec::new(),
+            rng_state: None,
+            started: Instant::now(),
+            captured_output: None,
+            buffering: Buffering::default(),
+            pending_output: String::new(),
+            trace: false,
+        }
+    }
+
+    pub fn check_access(&self, fn_name: &str) -> Result<(), Box<dyn Error>> {
+        if self.allow_system_access {
+            Ok(())
+        } else {
+            let msg = format!("{}() isn't available: system access is disabled", fn_name);
+            Err(RuntimeError::new(&msg, None, None).into())
+        }
+    }
+
+    // Everything output() and tap() print goes to stdout unless it's being captured, once
+    // the buffering lets it through.
+    pub fn print(&mut self, text: &str) {
+        self.pending_output.push_str(text);
+        let ready = match self.buffering {
+            Buffering::Unbuffered => self.pending_output.len(),
+            Buffering::Line => self.pending_output.rfind('\n').map_or(0, |i| i + 1),
+            Buffering::Full if self.pending_output.len() >= OUTPUT_BUFFER_SIZE => {
+                self.pending_output.len()
+            }
+            Buffering::Full => 0,
+        };
+        if ready > 0 {
+            let text: String = self.pending_output.drain(..ready).collect();
+            self.write_output(&text);
+        }
+    }
+
+    // Writes out whatever has been printed and is still being held.
+    pub fn flush(&mut self) {
+        if !self.pending_output.is_empty() {
+            let text = std::mem::take(&mut self.pending_output);
+            self.write_output(&text);
+        }
+    }
+
+    fn write_output(&mut self, text: &str) {
+        match self.captured_output {
+            Some(ref mut captured) => captured.push_str(text),
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                // Like print!(), there's nothing better to do when stdout has gone away.
+                let _ = stdout
+                    .write_all(text.as_bytes())
+                    .and_then(|_| stdout.flush());
+            }
+        }
+    }
+
+    // A line of the interpreter's trace. It goes to stderr to stay out of the program's
+    // own output, unless output is being captured, where it's kept in order with it.
+    pub fn trace(&mut self, line: &str) {
+        self.flush();
+        match self.captured_output {
+            Some(ref mut captured) => {
+                captured.push_str(line);
+                captured.push('\n');
+            }
+            None => eprintln!("{}", line),
+        }
+    }
+
+    // Keeps what the program prints from here on, for take_output().
+    pub fn capture_output(&mut self) {
+        self.captured_output.get_or_insert_with(String::new);
+    }
+
+    // What was printed since capturing started or the last take_output().
+    pub fn take_output(&mut self) -> String {
+        self.captured_output
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn seed(&mut self, seed: i64) {
+        // Xorshift gets stuck on zero, so mix the seed with a constant first.
+        let state = (seed as u64) ^ 0x9E37_79B9_7F4A_7C15;
+        self.rng_state = Some(if state == 0 { 1 } else { state });
+    }
+
+    pub fn next_random(&mut self) -> u64 {
+        if self.rng_state.is_none() {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as i64)
+                .unwrap_or(0);
+            self.seed(nanos);
+        }
+        let mut x = self.rng_state.unwrap_or(1);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = Some(x);
+        x
+    }
+
+    // Milliseconds since the Unix epoch.
+    pub fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0)
+    }
+
+    // Milliseconds since the program started; unlike now() this never goes backwards,
+    // so it's the one to use for timing.
+    pub fn monotonic(&self) -> i64 {
+        self.started.elapsed().as_millis() as i64
+    }
+
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+
+    // A float in [0, 1) built from the top 53 bits of the next random number.
+    pub fn next_random_flt(&mut self) -> f64 {
+        (self.next_random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,11 +1,19 @@
 use crate::semantic_analysis::*;
+use crate::sequence::{Cursor, Sequence};
+use crate::source_map::SourceMap;
+use crate::symboltable::Frame;
 use crate::symboltable::SymbolTable;
+use crate::symboltable::MAX_NESTING_DEPTH;
 use crate::syntax::DataType;
 use crate::syntax::Expr;
 use crate::syntax::Function;
+use crate::syntax::KeyData;
 use crate::syntax::KeywordArg;
 use crate::syntax::LiteralData;
+use crate::syntax::MatchArm;
 use crate::syntax::Operator;
+use indexmap::IndexMap;
+use std::cmp::Ordering;
 use std::error;
 use std::error::Error;
 
@@ -14,7 +22,11 @@ use std::error::Error;
 pub struct RuntimeError {
     stack: Option<Vec<String>>, // should be able to unwind the stack
     location: Option<(usize, usize)>,
+    // Byte offsets of the expression that failed, turned into 'location' by locate().
+    offsets: Option<(usize, usize)>,
     pub msg: String,
+    // The source line at 'location', once the error has been given the source.
+    snippet: Option<String>,
 }
 
 impl std::fmt::Display for RuntimeError {
@@ -26,10 +38,14 @@ impl std::fmt::Display for RuntimeError {
         };
 
         if let Some((line, column)) = self.location {
-            write!(f, "{}{}, {}: {}", &stack_trace, line, column, self.msg)
+            write!(f, "{}{}, {}: {}", &stack_trace, line, column, self.msg)?;
         } else {
-            write!(f, "{}{}", &stack_trace, self.msg)
+            write!(f, "{}{}", &stack_trace, self.msg)?;
         }
+        if let Some(ref snippet) = self.snippet {
+            write!(f, "\n{}", snippet)?;
+        }
+        Ok(())
     }
 }
 impl RuntimeError {
@@ -37,9 +53,40 @@ impl RuntimeError {
         Self {
             msg: msg.to_string(),
             location,
+            offsets: None,
             stack,
+            snippet: None,
         }
     }
+
+    // Shows the line the error happened on, if it's known where that was.
+    pub fn locate(&mut self, src: &str) {
+        let span = match (self.location, self.offsets) {
+            (Some(location), _) => Span::from(location),
+            (None, Some((start, end))) => Span::from_offsets(src, start, end),
+            (None, None) => return,
+        };
+        self.location = Some(span.start);
+        self.snippet = SourceMap::new(src).snippet(span);
+    }
+}
+
+// Runtime errors come back boxed along with other errors; this locates the ones that are.
+pub fn locate_runtime_error(e: &mut Box<dyn Error>, src: &str) {
+    if let Some(runtime_error) = e.downcast_mut::<RuntimeError>() {
+        runtime_error.locate(src);
+    }
+}
+
+// Places a runtime error at 'offsets' unless something it came from already placed it, so
+// it's reported at the innermost expression that knew where it was.
+fn located_at(mut e: Box<dyn Error>, offsets: (usize, usize)) -> Box<dyn Error> {
+    if let Some(runtime_error) = e.downcast_mut::<RuntimeError>() {
+        if runtime_error.offsets.is_none() && offsets.1 > offsets.0 {
+            runtime_error.offsets = Some(offsets);
+        }
+    }
+    e
 }
 
 impl Error for RuntimeError {
@@ -48,20 +95,49 @@ impl Error for RuntimeError {
     }
 }
 
+// 'break' and 'continue' unwind to their loop the same way errors do. Type checking keeps
+// them inside loops, so one never gets past the loop it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopControl {
+    Break,
+    Continue,
+}
+
+impl std::fmt::Display for LoopControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoopControl::Break => write!(f, "'break' outside a loop"),
+            LoopControl::Continue => write!(f, "'continue' outside a loop"),
+        }
+    }
+}
+
+impl Error for LoopControl {}
+
 pub type InterpreterResult = Result<Expr, Box<dyn error::Error>>;
 
 impl Expr {
     pub fn prepare(&mut self, symbols: &mut SymbolTable) -> Result<(), Vec<CompileError>> {
         let mut errors = Vec::new();
+        symbols.warnings.clear();
+        symbols.later_errors.clear();
+        symbols.let_bindings.clear();
+        symbols.read_symbols.clear();
 
         // Analyze  parse tree to index symbols across scopes.
-        let result = add_symbols(self, symbols, 0);
-        if let Err(ref msg) = result {
-            eprintln!("Error indexing variable and function names: {}", msg);
-            errors.push(msg.clone());
+        if let Err(msg) = add_symbols(self, symbols, 0) {
+            errors.push(msg);
+        } else if let Err(msg) = typecheck(self, symbols, 0) {
+            errors.push(msg);
+            errors.append(&mut symbols.later_errors);
+        } else {
+            if symbols.strictness.unused_variables {
+                errors.extend(check_unused_variables(symbols));
+            }
+            prune_dead_branches(self, symbols);
         }
-        // Collect other errors...
 
+        symbols.omitted_errors = cap_errors(&mut errors, symbols.max_errors);
         if errors.is_empty() {
             Ok(())
         } else {
@@ -69,12 +145,149 @@ impl Expr {
         }
     }
 
+    // Runs a whole program: its top level, which defines what main() needs, and then main()
+    // if there is one, whose result stands in for the top level's.
+    // Whatever output is still buffered gets written once the program finishes, whether
+    // or not it succeeded.
+    pub fn interpret_program(&self, symbols: &mut SymbolTable) -> InterpreterResult {
+        let result = self.run_program(symbols);
+        symbols.host.flush();
+        result
+    }
+
+    fn run_program(&self, symbols: &mut SymbolTable) -> InterpreterResult {
+        let result = self.interpret(symbols, 0)?;
+        match self.main_function() {
+            // main() is defined in the program's own scope, which is where it gets called.
+            Some((index, _)) => Expr::Call {
+                fn_name: "main".to_string(),
+                index,
+                args: Vec::new(),
+                method: false,
+                span: (0, 0),
+            }
+            .interpret(symbols, index.0),
+            None => Ok(result),
+        }
+    }
+
+    // Runs a program's top level and then each of its test functions, giving every test's
+    // name and result. A test passes unless running it fails.
+    pub fn run_tests(
+        &self,
+        symbols: &mut SymbolTable,
+    ) -> Result<Vec<(String, InterpreterResult)>, Box<dyn Error>> {
+        self.interpret(symbols, 0)?;
+        let results = self
+            .test_functions()
+            .into_iter()
+            .map(|(fn_name, index)| {
+                let call = Expr::Call {
+                    fn_name: fn_name.clone(),
+                    index,
+                    args: Vec::new(),
+                    method: false,
+                    span: (0, 0),
+                };
+                let result = call.interpret(symbols, index.0);
+                (fn_name, result)
+            })
+            .collect();
+        Ok(results)
+    }
+
     // Receives a "prepared" parse tree and symbol table.
     pub fn interpret(&self, symbols: &mut SymbolTable, current_scope: usize) -> InterpreterResult {
+        if !symbols.enter_nested() {
+            let msg = format!(
+                "Expression too deeply nested or recursion too deep (more than {} levels)",
+                MAX_NESTING_DEPTH
+            );
+            return Err(RuntimeError::new(&msg, None, None).into());
+        }
+        let result = self.interpret_node(symbols, current_scope);
+        if symbols.host.trace {
+            trace_step(self, &result, symbols);
+        }
+        symbols.leave_nested();
+        result
+    }
+
+    fn interpret_node(&self, symbols: &mut SymbolTable, current_scope: usize) -> InterpreterResult {
         match self {
             Expr::Output { data } => interpret_output(symbols, data, current_scope),
-            Expr::Literal(_) => Ok(self.clone()),
+            Expr::Literal(_) | Expr::EnumValue { .. } => Ok(self.clone()),
             Expr::RuntimeData(_) => Ok(self.clone()),
+            Expr::RuntimeList { .. }
+            | Expr::RuntimeMap { .. }
+            | Expr::RuntimeSet { .. }
+            | Expr::RuntimeStruct { .. }
+            | Expr::RuntimeOptional(_)
+            | Expr::RuntimeSeq(_) => Ok(self.clone()),
+            Expr::MapLiteral {
+                ref key_type,
+                ref value_type,
+                ref data,
+                ..
+            } => interpret_map_literal(symbols, key_type, value_type, data, current_scope),
+            Expr::ListLiteral {
+                ref data_type,
+                ref data,
+                ..
+            } => interpret_list_literal(symbols, data_type, data, current_scope),
+            Expr::StructLiteral {
+                ref type_name,
+                ref index,
+                ref fields,
+            } => interpret_struct_literal(symbols, type_name, index, fields, current_scope),
+            Expr::FieldAccess {
+                ref target,
+                ref field,
+            } => interpret_field_access(symbols, target, field, current_scope),
+            Expr::OptionalFieldAccess {
+                ref target,
+                ref field,
+            } => match target.interpret(symbols, current_scope)? {
+                Expr::RuntimeOptional(Some(value)) => {
+                    match interpret_field_access(symbols, &value, field, current_scope)? {
+                        optional @ Expr::RuntimeOptional(_) => Ok(optional),
+                        value => Ok(Expr::RuntimeOptional(Some(Box::new(value)))),
+                    }
+                }
+                Expr::RuntimeOptional(None) => Ok(Expr::RuntimeOptional(None)),
+                other => {
+                    let msg = format!("Can't use '?.' on {}, which isn't optional", other);
+                    Err(RuntimeError::new(&msg, None, None).into())
+                }
+            },
+            // Newtypes only matter to the type checker; narrow widths are range checked.
+            Expr::Cast {
+                ref value,
+                ref data_type,
+                ..
+            } => {
+                let result = value.interpret(symbols, current_scope)?;
+                narrow_number(result, data_type, symbols, current_scope)
+            }
+            Expr::TypeAssertion {
+                ref value,
+                ref data_type,
+                ..
+            } => {
+                let result = value.interpret(symbols, current_scope)?;
+                if has_runtime_type(&result, data_type, symbols, current_scope) {
+                    Ok(result)
+                } else {
+                    let msg = format!("assert_type(): {} isn't of type {}", result, data_type);
+                    Err(RuntimeError::new(&msg, None, None).into())
+                }
+            }
+            Expr::DefaultValue { ref data_type, .. } => {
+                default_value(data_type, symbols, current_scope).ok_or_else(|| {
+                    let msg = format!("default(): {} has no default value", data_type);
+                    RuntimeError::new(&msg, None, None).into()
+                })
+            }
             Expr::Program {
                 ref body,
                 ref environment,
@@ -82,17 +295,29 @@ impl Expr {
             Expr::Block {
                 ref body,
                 ref environment,
+                ..
             } => interpret_block(symbols, body, *environment),
             Expr::Let {
                 ref var_name,
                 ref value,
                 ref index,
                 ref data_type,
+                ..
             } => interpret_let(symbols, var_name, data_type, value, index),
+            Expr::Assign {
+                ref value,
+                ref index,
+                ..
+            } => interpret_assign(symbols, value, index, current_scope),
+            Expr::MultipleAssign {
+                ref targets,
+                ref values,
+            } => interpret_multiple_assign(symbols, targets, values, current_scope),
             Expr::BinaryExpr {
                 ref left,
                 op,
                 ref right,
+                ..
             } => interpret_binary(symbols, left, op, right, current_scope),
             Expr::Variable {
                 ref name,
@@ -102,20 +327,69 @@ impl Expr {
                 ref cond,
                 ref then,
                 ref final_else,
+                ..
             } => interpret_if(symbols, cond, then, final_else, current_scope),
-            Expr::While { ref cond, ref body } => {
-                interpret_while(symbols, current_scope, cond, body)
-            }
+            Expr::Match {
+                ref cond,
+                ref against,
+            } => interpret_match(symbols, current_scope, cond, against),
+            Expr::While {
+                ref cond,
+                ref body,
+                ref step,
+            } => interpret_while(symbols, current_scope, cond, body, step.as_deref()),
+            Expr::Break => Err(Box::new(LoopControl::Break)),
+            Expr::Continue => Err(Box::new(LoopControl::Continue)),
+            Expr::ForIn {
+                ref index,
+                ref source,
+                ref body,
+                ref environment,
+                ..
+            } => interpret_for_in(symbols, current_scope, index, source, body, *environment),
+            Expr::Comprehension {
+                ref index,
+                ref source,
+                ref key,
+                ref element,
+                ref filter,
+                ref environment,
+                ..
+            } => interpret_comprehension(
+                symbols,
+                current_scope,
+                index,
+                source,
+                key.as_deref(),
+                element,
+                filter.as_deref(),
+                *environment,
+            ),
             Expr::Call {
                 ref fn_name,
                 ref index,
                 ref args,
+                ..
             } => interpret_call(symbols, current_scope, fn_name, *index, args),
-            Expr::Lambda {
-                ref value,
-                environment,
-            } => interpret_lambda(symbols, value, *environment),
+            Expr::BuiltinCall {
+                ref fn_name,
+                ref args,
+                span,
+            } => interpret_builtin(symbols, current_scope, fn_name, args)
+                .map_err(|e| located_at(e, *span)),
+            // A lambda evaluates to itself; its body runs when it's called.
+            Expr::Lambda { .. } => Ok(self.clone()),
+            // Runs the module's top level, setting up its variables.
+            Expr::Import {
+                module: Some(ref module),
+                ..
+            } => module.interpret(symbols, current_scope).map(|_| Expr::Unit),
+            Expr::Import { ref path, .. } => {
+                let msg = format!("'{}' was never loaded", path);
+                Err(RuntimeError::new(&msg, None, None).into())
+            }
             Expr::DefineFunction { .. } => Ok(Expr::Unit), // The function got assigned in an earlier compiler pass
+            Expr::DefineType { .. } => Ok(Expr::Unit), // Types are only needed by the compiler passes
             _ => panic!(
                 "Interpreter error: interpret() not implemented for '{:?}'",
                 self
@@ -133,14 +407,25 @@ fn interpret_output(
     data: &Vec<Expr>,
     current_scope: usize,
 ) -> InterpreterResult {
+    let mut values = Vec::with_capacity(data.len());
     for e in data {
-        let r = e.interpret(symbols, current_scope)?;
-        print!("{} ", r);
+        values.push(e.interpret(symbols, current_scope)?);
     }
-    println!();
+    print_values(symbols, &values);
     Ok(Expr::Unit)
 }
 
+// Prints values the way output() does: each followed by a space, then a newline.
+pub(crate) fn print_values(symbols: &mut SymbolTable, values: &[Expr]) {
+    let mut line = String::new();
+    for value in values {
+        line.push_str(&value.pretty());
+        line.push(' ');
+    }
+    line.push('\n');
+    symbols.host.print(&line);
+}
+
 fn interpret_block(symbols: &mut SymbolTable, body: &Vec<Expr>, env: usize) -> InterpreterResult {
     interpret_body_or_block(symbols, body, env)
 }
@@ -154,7 +439,9 @@ fn interpret_body_or_block(
     for exp in body {
         tmp_expr_result = exp.interpret(symbols, env);
         if let Err(ref err) = tmp_expr_result {
-            eprintln!("Runtime error: {}", err);
+            if !err.is::<LoopControl>() {
+                eprintln!("Runtime error: {}", err);
+            }
             return tmp_expr_result;
         }
     }
@@ -163,8 +450,8 @@ fn interpret_body_or_block(
 
 fn interpret_let(
     symbols: &mut SymbolTable,
-    var_name: &str,
-    data_type: &DataType,
+    _var_name: &str,
+    _data_type: &DataType,
     value: &Expr,
     index: &(usize, usize),
 ) -> InterpreterResult {
@@ -173,10 +460,205 @@ fn interpret_let(
     // evaluate the right-hand side.
     let current_scope = index.0;
     let result = value.interpret(symbols, current_scope)?;
-    symbols.update_runtime_value(result, index);
+    store_value(symbols, result, index)?;
+    Ok(Expr::Unit)
+}
+
+fn interpret_assign(
+    symbols: &mut SymbolTable,
+    value: &Expr,
+    index: &(usize, usize),
+    current_scope: usize,
+) -> InterpreterResult {
+    let result = value.interpret(symbols, current_scope)?;
+    store_value(symbols, result, index)?;
+    Ok(Expr::Unit)
+}
+
+// Sets a variable, narrowing numbers to its declared width.
+fn store_value(
+    symbols: &mut SymbolTable,
+    value: Expr,
+    index: &(usize, usize),
+) -> Result<(), Box<dyn Error>> {
+    let stored = match symbols.get_symbol_type(index) {
+        Some(data_type) => narrow_number(value, &data_type, symbols, index.0)?,
+        None => value,
+    };
+    symbols.update_runtime_value(stored, index);
+    Ok(())
+}
+
+// A number as the given type would hold it: an I32 that doesn't fit is an error and an F32
+// loses precision the way it would in memory. Other values are unchanged.
+fn narrow_number(
+    value: Expr,
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    scope_id: usize,
+) -> InterpreterResult {
+    let width = match resolve_type(data_type, symbols, scope_id) {
+        DataType::Newtype { base, .. } => *base,
+        resolved => resolved,
+    };
+    match (width, value) {
+        (DataType::I32, Expr::Literal(LiteralData::Int(i))) => match i32::try_from(i) {
+            Ok(_) => Ok(Expr::Literal(LiteralData::Int(i))),
+            Err(_) => {
+                let msg = format!("{} doesn't fit in an I32", i);
+                Err(RuntimeError::new(&msg, None, None).into())
+            }
+        },
+        (DataType::F32, Expr::Literal(LiteralData::Flt(f))) => {
+            Ok(Expr::Literal(LiteralData::Flt(f as f32 as f64)))
+        }
+        (_, value) => Ok(value),
+    }
+}
+
+fn interpret_multiple_assign(
+    symbols: &mut SymbolTable,
+    targets: &[(String, (usize, usize))],
+    values: &[Expr],
+    current_scope: usize,
+) -> InterpreterResult {
+    // Evaluate everything first so 'a, b := b, a' sees the old values on the right.
+    let mut results = Vec::with_capacity(values.len());
+    for value in values {
+        results.push(value.interpret(symbols, current_scope)?);
+    }
+    for ((_, index), result) in targets.iter().zip(results) {
+        store_value(symbols, result, index)?;
+    }
     Ok(Expr::Unit)
 }
 
+fn interpret_list_literal(
+    symbols: &mut SymbolTable,
+    data_type: &DataType,
+    data: &[Expr],
+    current_scope: usize,
+) -> InterpreterResult {
+    let mut items = Vec::with_capacity(data.len());
+    for item in data {
+        items.push(item.interpret(symbols, current_scope)?);
+    }
+    Ok(Expr::RuntimeList {
+        data_type: data_type.clone(),
+        data: items,
+    })
+}
+
+fn interpret_map_literal(
+    symbols: &mut SymbolTable,
+    key_type: &DataType,
+    value_type: &DataType,
+    data: &[(KeyData, Expr)],
+    current_scope: usize,
+) -> InterpreterResult {
+    let mut items = IndexMap::with_capacity(data.len());
+    for (key, value) in data {
+        items.insert(key.clone(), value.interpret(symbols, current_scope)?);
+    }
+    Ok(Expr::RuntimeMap {
+        key_type: key_type.clone(),
+        value_type: value_type.clone(),
+        data: items,
+    })
+}
+
+// Map keys are literal values other than floats.
+pub(crate) fn to_key(value: &Expr) -> Result<KeyData, Box<dyn Error>> {
+    match value {
+        Expr::Literal(LiteralData::Int(i)) => Ok(KeyData::Int(*i)),
+        Expr::Literal(LiteralData::Str(s)) => Ok(KeyData::Str(s.clone())),
+        Expr::Literal(LiteralData::Bool(b)) => Ok(KeyData::Bool(*b)),
+        _ => {
+            let msg = format!("{} can't be used as a map key", value);
+            Err(RuntimeError::new(&msg, None, None).into())
+        }
+    }
+}
+
+fn interpret_struct_literal(
+    symbols: &mut SymbolTable,
+    type_name: &str,
+    index: &(usize, usize),
+    fields: &[KeywordArg],
+    current_scope: usize,
+) -> InterpreterResult {
+    let Some(DataType::Struct(params)) = symbols.get_type_definition(index) else {
+        let msg = format!("'{}' isn't a struct type", type_name);
+        return Err(RuntimeError::new(&msg, None, None).into());
+    };
+
+    // Store the fields in declaration order no matter how the arguments were ordered.
+    let mut values = Vec::with_capacity(params.len());
+    for p in &params {
+        let Some(field) = fields.iter().find(|f| f.name == p.name) else {
+            let msg = format!("Missing field '{}' constructing '{}'", p.name, type_name);
+            return Err(RuntimeError::new(&msg, None, None).into());
+        };
+        values.push((
+            p.name.clone(),
+            field.value.interpret(symbols, current_scope)?,
+        ));
+    }
+    Ok(Expr::RuntimeStruct {
+        type_name: type_name.to_string(),
+        fields: values,
+    })
+}
+
+fn interpret_field_access(
+    symbols: &mut SymbolTable,
+    target: &Expr,
+    field: &str,
+    current_scope: usize,
+) -> InterpreterResult {
+    match target.interpret(symbols, current_scope)? {
+        Expr::RuntimeStruct { type_name, fields } => {
+            match fields.into_iter().find(|(name, _)| name == field) {
+                Some((_, value)) => Ok(value),
+                None => {
+                    let msg = format!("'{}' has no field '{}'", type_name, field);
+                    Err(RuntimeError::new(&msg, None, None).into())
+                }
+            }
+        }
+        other => {
+            let msg = format!("Can't access field '{}' on {}", field, other);
+            Err(RuntimeError::new(&msg, None, None).into())
+        }
+    }
+}
+
+fn interpret_builtin(
+    symbols: &mut SymbolTable,
+    current_scope: usize,
+    fn_name: &str,
+    args: &[Expr],
+) -> InterpreterResult {
+    let mut arg_values = Vec::with_capacity(args.len());
+    for a in args {
+        arg_values.push(a.interpret(symbols, current_scope)?);
+    }
+    match symbols.builtins.get(fn_name) {
+        Some(builtin) => builtin.eval(&arg_values, symbols),
+        None => {
+            let msg = format!("No builtin function named '{}'", fn_name);
+            Err(RuntimeError::new(&msg, None, None).into())
+        }
+    }
+}
+
+// String literals keep their quotes from the source; this gives the text between them.
+pub(crate) fn unquoted(s: &str) -> &str {
+    s.strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .unwrap_or(s)
+}
+
 fn interpret_call(
     symbols: &mut SymbolTable,
     current_scope: usize,
@@ -184,8 +666,12 @@ fn interpret_call(
     index: (usize, usize),
     args: &[KeywordArg],
 ) -> InterpreterResult {
-    // Get the lambda for this function
-    let maybe_lambda = symbols.get_compiletime_value(&index);
+    // Get the lambda for this function. A variable holding a function value (a parameter, say)
+    // only has its lambda at runtime, so that takes precedence.
+    let maybe_lambda = match symbols.get_runtime_value(&index) {
+        Some(runtime_value @ Expr::Lambda { .. }) => Some(runtime_value),
+        _ => symbols.get_compiletime_value(&index),
+    };
     if maybe_lambda.is_none() {
         symbols.print_debug();
         panic!(
@@ -201,7 +687,11 @@ fn interpret_call(
     // If the call has no arguments, the expression bound to this "function" doesn't need to be a lambda;
     // we just evaluate it in the function's captured scope (the index).
     match lm {
-        Expr::Lambda { value, environment } => {
+        Expr::Lambda {
+            value,
+            environment,
+            captured,
+        } => {
             if args.len() != value.params.len() {
                 // TODO this should be in the compile pass
                 panic!(
@@ -210,21 +700,28 @@ fn interpret_call(
                 );
             }
 
-            for a in args {
+            let mut arg_values = vec![Expr::Unit; args.len()];
+            for (position, a) in args.iter().enumerate() {
                 let arg_value = a.value.interpret(symbols, current_scope)?;
 
-                // TODO this part should be done in a compiler pass, it's sort of slow this way.
-                if let Some(assign_to_index) = symbols.get_index_in_scope(&a.name, environment) {
-                    symbols.update_runtime_value(arg_value, &(environment, assign_to_index));
+                // Positional arguments go to the parameter in the same position.
+                let param_position = if a.name.is_empty() {
+                    Some(position)
+                } else {
+                    value.params.iter().position(|p| p.name == a.name)
+                };
+
+                if let Some(param_position) = param_position {
+                    arg_values[param_position] = arg_value;
                 } else {
                     panic!("Interpreter error: Keyword arg names must match the function definition parameters.");
                 }
             }
 
-            interpret_lambda(symbols, &value, environment)
+            call_function(symbols, &value, environment, &captured, arg_values)
         }
         _ => {
-            if args.len() > 0 {
+            if !args.is_empty() {
                 // TODO this should really be in the compile pass
                 panic!("Interpreter error: function {} called with {} args but it is a simple expression not a lambda. The type checking pass should have caught this.",fn_name, args.len());
             }
@@ -233,6 +730,51 @@ fn interpret_call(
     }
 }
 
+// Binds already evaluated arguments to the function's parameters, in order, and runs it.
+// A closure's captured values stand in for those of its enclosing scopes during the call.
+pub(crate) fn call_function(
+    symbols: &mut SymbolTable,
+    value: &Function,
+    environment: usize,
+    captured: &Frame,
+    arg_values: Vec<Expr>,
+) -> InterpreterResult {
+    let caller_frame = symbols.save_frame(environment);
+    let enclosing_frame = symbols.swap_frame(captured.clone());
+    let mut result = Ok(Expr::Unit);
+    for (param, arg_value) in value.params.iter().zip(arg_values) {
+        if let Err(e) = store_value(symbols, arg_value, &param.index) {
+            result = Err(e);
+            break;
+        }
+    }
+    if result.is_ok() {
+        result = interpret_lambda(symbols, value, environment)
+            .and_then(|r| narrow_number(r, &value.return_type, symbols, environment))
+            .map(|r| close_over(symbols, r, environment, captured));
+    }
+    symbols.restore_frame(enclosing_frame);
+    symbols.restore_frame(caller_frame);
+    result
+}
+
+// A lambda made during a call would lose the values it refers to when the call's frame
+// gets restored, so it takes a copy of them with it.
+fn close_over(symbols: &SymbolTable, result: Expr, environment: usize, captured: &Frame) -> Expr {
+    match result {
+        Expr::Lambda {
+            value,
+            environment: lambda_environment,
+            ..
+        } if symbols.is_within(lambda_environment, environment) => Expr::Lambda {
+            value,
+            environment: lambda_environment,
+            captured: symbols.capture_frame(captured, environment),
+        },
+        other => other,
+    }
+}
+
 fn interpret_lambda(
     symbols: &mut SymbolTable,
     value: &Function,
@@ -263,6 +805,34 @@ fn interpret_var(
     }
 }
 
+// Runs the first arm whose pattern equals the value of 'cond' and whose guard holds.
+fn interpret_match(
+    symbols: &mut SymbolTable,
+    current_scope: usize,
+    cond: &Expr,
+    against: &[MatchArm],
+) -> InterpreterResult {
+    let value = cond.interpret(symbols, current_scope)?;
+    for arm in against {
+        let mut matched = match arm.pattern {
+            Expr::Wildcard => true,
+            _ => {
+                let pattern_value = arm.pattern.interpret(symbols, current_scope)?;
+                compare_values(&value, &pattern_value) == Some(Ordering::Equal)
+            }
+        };
+        // A false guard sends the value on to the next arm.
+        if let (true, Some(guard)) = (matched, &arm.guard) {
+            matched = interprets_as_true(symbols, current_scope, guard)?;
+        }
+        if matched {
+            return arm.value.interpret(symbols, current_scope);
+        }
+    }
+    let msg = format!("No match arm for the value {}", value);
+    Err(RuntimeError::new(&msg, None, None).into())
+}
+
 // Given scopes in 'symbols', evaluate 'cond' within scope 'current_scope' as true or false
 fn interprets_as_true(
     symbols: &mut SymbolTable,
@@ -295,28 +865,151 @@ fn interpret_while(
     current_scope: usize,
     cond: &Expr,
     body: &Expr,
+    step: Option<&Expr>,
 ) -> InterpreterResult {
     while interprets_as_true(symbols, current_scope, cond)? {
-        body.interpret(symbols, current_scope)?;
+        if loop_control(body.interpret(symbols, current_scope))? == Some(LoopControl::Break) {
+            break;
+        }
+        if let Some(step) = step {
+            step.interpret(symbols, current_scope)?;
+        }
+    }
+    Ok(Expr::Unit)
+}
+
+// What a pass through a loop's body asks the loop to do next, if anything; any other
+// error is passed on.
+fn loop_control(result: InterpreterResult) -> Result<Option<LoopControl>, Box<dyn Error>> {
+    match result {
+        Ok(_) => Ok(None),
+        Err(e) => match e.downcast_ref::<LoopControl>() {
+            Some(control) => Ok(Some(*control)),
+            None => Err(e),
+        },
+    }
+}
+
+// Sets the variable to each item of the source in turn, keeping the element (and key)
+// for those the filter lets through.
+#[allow(clippy::too_many_arguments)]
+fn interpret_comprehension(
+    symbols: &mut SymbolTable,
+    current_scope: usize,
+    index: &(usize, usize),
+    source: &Expr,
+    key: Option<&Expr>,
+    element: &Expr,
+    filter: Option<&Expr>,
+    environment: usize,
+) -> InterpreterResult {
+    let mut cursor = iterate(source.interpret(symbols, current_scope)?)?;
+    let mut list = Vec::new();
+    let mut map = IndexMap::new();
+    while let Some(item) = cursor.next(symbols)? {
+        store_value(symbols, item, index)?;
+        if let Some(filter) = filter {
+            if !interprets_as_true(symbols, environment, filter)? {
+                continue;
+            }
+        }
+        let value = element.interpret(symbols, environment)?;
+        match key {
+            None => list.push(value),
+            Some(key) => {
+                let key = to_key(&key.interpret(symbols, environment)?)?;
+                map.insert(key, value);
+            }
+        }
+    }
+    Ok(match key {
+        None => Expr::RuntimeList {
+            data_type: DataType::Unsolved,
+            data: list,
+        },
+        Some(_) => Expr::RuntimeMap {
+            key_type: DataType::Unsolved,
+            value_type: DataType::Unsolved,
+            data: map,
+        },
+    })
+}
+
+// Runs the body with the variable set to each item of the source in turn.
+fn interpret_for_in(
+    symbols: &mut SymbolTable,
+    current_scope: usize,
+    index: &(usize, usize),
+    source: &Expr,
+    body: &Expr,
+    environment: usize,
+) -> InterpreterResult {
+    let mut cursor = iterate(source.interpret(symbols, current_scope)?)?;
+    while let Some(item) = cursor.next(symbols)? {
+        store_value(symbols, item, index)?;
+        if loop_control(body.interpret(symbols, environment))? == Some(LoopControl::Break) {
+            break;
+        }
     }
     Ok(Expr::Unit)
 }
 
+// Goes through a collection's elements, or a Map's keys, one at a time.
+fn iterate(source: Expr) -> Result<Cursor, Box<dyn Error>> {
+    match source {
+        Expr::RuntimeList { data, .. } => Ok(Sequence::Items(data).cursor()),
+        Expr::RuntimeSet { data, .. } => Ok(keys_to_items(data.iter()).cursor()),
+        Expr::RuntimeMap { data, .. } => Ok(keys_to_items(data.keys()).cursor()),
+        Expr::RuntimeSeq(sequence) => Ok(sequence.cursor()),
+        other => {
+            let msg = format!("Can't iterate over {}", other);
+            Err(RuntimeError::new(&msg, None, None).into())
+        }
+    }
+}
+
+fn keys_to_items<'a>(keys: impl Iterator<Item = &'a KeyData>) -> Sequence {
+    Sequence::Items(keys.map(|k| Expr::Literal(k.clone().into())).collect())
+}
+
 impl LiteralData {
     fn apply_binary_operator(&self, rhs: &LiteralData, op: &Operator) -> InterpreterResult {
         use LiteralData::*;
         use Operator::*;
 
+        // Outside strict mode an Int mixed with a Flt in arithmetic counts as a Flt.
+        if let (Add | Sub | Mul | Div, Int(l), Flt(_)) = (op, self, rhs) {
+            return Flt(*l as f64).apply_binary_operator(rhs, op);
+        }
+        if let (Add | Sub | Mul | Div, Flt(_), Int(r)) = (op, self, rhs) {
+            return self.apply_binary_operator(&Flt(*r as f64), op);
+        }
         let result = match (op, self, rhs) {
-            (Add, Int(l), Int(r)) => Int(l + r),
+            (Add, Int(l), Int(r)) => Int(checked(l.checked_add(*r), l, "+", r)?),
             (Add, Flt(l), Flt(r)) => Flt(l + r),
-            (Add, Str(l), Str(r)) => LiteralData::Str((l.to_string() + &r).into()),
-            (Sub, Int(l), Int(r)) => Int(l - r),
+            // Joining the text inside the quotes, which each string keeps from the source.
+            (Add, Str(l), Str(r)) => Str(format!("'{}{}'", unquoted(l), unquoted(r)).into()),
+            (Sub, Int(l), Int(r)) => Int(checked(l.checked_sub(*r), l, "-", r)?),
             (Sub, Flt(l), Flt(r)) => Flt(l - r),
-            (Mul, Int(l), Int(r)) => Int(l * r),
+            (Mul, Int(l), Int(r)) => Int(checked(l.checked_mul(*r), l, "*", r)?),
             (Mul, Flt(l), Flt(r)) => Flt(l * r),
-            (Div, Int(l), Int(r)) => Int(l / r),
+            (Div, Int(_), Int(0)) => {
+                return Err(RuntimeError::new("Division by zero", None, None).into());
+            }
+            (Div, Int(l), Int(r)) => match l.checked_div(*r) {
+                Some(quotient) => Int(quotient),
+                None => {
+                    let msg = format!("Integer overflow in {} / {}", l, r);
+                    return Err(RuntimeError::new(&msg, None, None).into());
+                }
+            },
             (Div, Flt(l), Flt(r)) => Flt(l / r),
+            (Modulo, Int(_), Int(0)) => {
+                return Err(RuntimeError::new("Division by zero", None, None).into());
+            }
+            (Modulo, Int(l), Int(r)) => Int(checked(l.checked_rem(*r), l, "%", r)?),
+            (WrappingAdd, Int(l), Int(r)) => Int(l.wrapping_add(*r)),
+            (WrappingMul, Int(l), Int(r)) => Int(l.wrapping_mul(*r)),
 
             (Gt, Int(l), Int(r)) => Bool(l > r),
             (Gt, Flt(l), Flt(r)) => Bool(l > r),
@@ -339,6 +1032,9 @@ impl LiteralData {
             (Neq, Flt(l), Flt(r)) => Bool(l != r),
             (Neq, Bool(l), Bool(r)) => Bool(l != r),
             (Neq, Str(l), Str(r)) => Bool(l != r),
+
+            (And, Bool(l), Bool(r)) => Bool(*l && *r),
+            (Or, Bool(l), Bool(r)) => Bool(*l || *r),
             _ => {
                 // The type checker and parser should have prevented us from
                 // reaching this point.
@@ -350,6 +1046,197 @@ impl LiteralData {
     }
 }
 
+// The result of checked Int arithmetic, where None means it overflowed.
+fn checked(result: Option<i64>, l: &i64, op: &str, r: &i64) -> Result<i64, Box<dyn Error>> {
+    result.ok_or_else(|| {
+        let msg = format!("Integer overflow in {} {} {}", l, op, r);
+        RuntimeError::new(&msg, None, None).into()
+    })
+}
+
+// Orders two runtime values the way the comparison operators do. Structs compare
+// field by field in declaration order and lists element by element. Returns None
+// for values that can't be compared, including NaN.
+pub fn compare_values(left: &Expr, right: &Expr) -> Option<Ordering> {
+    use LiteralData::*;
+    match (left, right) {
+        (Expr::Literal(l), Expr::Literal(r)) => match (l, r) {
+            (Int(l), Int(r)) => Some(l.cmp(r)),
+            (Flt(l), Flt(r)) => l.partial_cmp(r),
+            (Str(l), Str(r)) => Some(l.cmp(r)),
+            (Bool(l), Bool(r)) => Some(l.cmp(r)),
+            _ => None,
+        },
+        (
+            Expr::RuntimeStruct {
+                type_name: l_type,
+                fields: l_fields,
+            },
+            Expr::RuntimeStruct {
+                type_name: r_type,
+                fields: r_fields,
+            },
+        ) if l_type == r_type => {
+            for ((_, l_value), (_, r_value)) in l_fields.iter().zip(r_fields) {
+                match compare_values(l_value, r_value)? {
+                    Ordering::Equal => continue,
+                    unequal => return Some(unequal),
+                }
+            }
+            Some(Ordering::Equal)
+        }
+        (
+            Expr::EnumValue {
+                type_name: l_type,
+                variant: l_variant,
+            },
+            Expr::EnumValue {
+                type_name: r_type,
+                variant: r_variant,
+            },
+        ) if l_type == r_type => Some(l_variant.cmp(r_variant)),
+        (Expr::RuntimeList { data: l_data, .. }, Expr::RuntimeList { data: r_data, .. }) => {
+            for (l_value, r_value) in l_data.iter().zip(r_data) {
+                match compare_values(l_value, r_value)? {
+                    Ordering::Equal => continue,
+                    unequal => return Some(unequal),
+                }
+            }
+            Some(l_data.len().cmp(&r_data.len()))
+        }
+        _ => None,
+    }
+}
+
+// Comparison operators on values that aren't simple literals, like structs and lists.
+fn apply_structural_operator(left: &Expr, op: &Operator, right: &Expr) -> InterpreterResult {
+    let ordering = compare_values(left, right);
+    let result = match op {
+        Operator::Eq => ordering == Some(Ordering::Equal),
+        Operator::Neq => ordering != Some(Ordering::Equal),
+        Operator::Lt => ordering == Some(Ordering::Less),
+        Operator::Gt => ordering == Some(Ordering::Greater),
+        Operator::Lte => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        Operator::Gte => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        _ => {
+            let msg = format!(
+                "Expressions don't evaluate to anything applicable to a binary operator: {}, {}",
+                left, right
+            );
+            return Err(RuntimeError::new(&msg, None, None).into());
+        }
+    };
+    Ok(Expr::Literal(LiteralData::Bool(result)))
+}
+
+// Whether a running value is of the given type, looking inside collections and optionals.
+// Types that can't be told apart by the value alone, like a newtype and its base, match.
+fn has_runtime_type(
+    value: &Expr,
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope: usize,
+) -> bool {
+    let has_type = |value: &Expr, data_type: &DataType| {
+        has_runtime_type(value, data_type, symbols, current_scope)
+    };
+    match (value, data_type) {
+        (_, DataType::Any | DataType::Unsolved) => true,
+        (Expr::RuntimeStruct { type_name, .. }, DataType::TypeRef(name))
+        | (Expr::EnumValue { type_name, .. }, DataType::TypeRef(name)) => type_name == name,
+        (_, DataType::TypeRef(_)) => {
+            let resolved = resolve_type(data_type, symbols, current_scope);
+            !matches!(resolved, DataType::TypeRef(_)) && has_type(value, &resolved)
+        }
+        (_, DataType::Newtype { base, .. }) => has_type(value, base),
+        // Ranges are checked where they're stored, not here.
+        (Expr::Literal(_), DataType::Range(_)) => true,
+        (Expr::Literal(LiteralData::Int(i)), DataType::I32) => i32::try_from(*i).is_ok(),
+        (Expr::Literal(LiteralData::Int(_)), DataType::Int)
+        | (Expr::Literal(LiteralData::Flt(_)), DataType::Flt | DataType::F32)
+        | (Expr::Literal(LiteralData::Str(_)), DataType::Str)
+        | (Expr::Literal(LiteralData::Bool(_)), DataType::Bool) => true,
+        (Expr::RuntimeList { data, .. }, DataType::List { element_type }) => {
+            data.iter().all(|item| has_type(item, element_type))
+        }
+        (
+            Expr::RuntimeMap { data, .. },
+            DataType::Map {
+                key_type,
+                value_type,
+            },
+        ) => data.iter().all(|(key, item)| {
+            has_type(&Expr::Literal(key.clone().into()), key_type) && has_type(item, value_type)
+        }),
+        (Expr::RuntimeSet { data, .. }, DataType::Set(element_type)) => data
+            .iter()
+            .all(|key| has_type(&Expr::Literal(key.clone().into()), element_type)),
+        (Expr::RuntimeOptional(inner), DataType::Optional(inner_type)) => inner
+            .as_ref()
+            .is_none_or(|inner| has_type(inner, inner_type)),
+        (Expr::RuntimeSeq(_), DataType::Seq(_))
+        | (Expr::Lambda { .. }, DataType::Function { .. }) => true,
+        _ => false,
+    }
+}
+
+// Logs 'depth | step => value' once an expression has been evaluated, so the innermost
+// parts of an expression come before it. Values that are already evaluated aren't steps.
+fn trace_step(expr: &Expr, result: &InterpreterResult, symbols: &mut SymbolTable) {
+    let step = match expr {
+        Expr::RuntimeData(_)
+        | Expr::RuntimeList { .. }
+        | Expr::RuntimeMap { .. }
+        | Expr::RuntimeSet { .. }
+        | Expr::RuntimeStruct { .. }
+        | Expr::RuntimeOptional(_)
+        | Expr::RuntimeSeq(_) => return,
+        Expr::Variable { name, .. } => format!("Variable {}", name),
+        Expr::Call { fn_name, .. } | Expr::BuiltinCall { fn_name, .. } => {
+            format!("Call {}", fn_name)
+        }
+        Expr::Let { var_name, .. } => format!("Let {}", var_name),
+        Expr::Assign { name, .. } => format!("Assign {}", name),
+        Expr::DefineFunction { fn_name, .. } => format!("DefineFunction {}", fn_name),
+        Expr::DefineType { type_name, .. } => format!("DefineType {}", type_name),
+        Expr::FieldAccess { field, .. } => format!("FieldAccess {}", field),
+        Expr::OptionalFieldAccess { field, .. } => format!("OptionalFieldAccess {}", field),
+        Expr::BinaryExpr { op, .. } => format!("BinaryExpr {:?}", op),
+        Expr::Literal(_) => "Literal".to_string(),
+        Expr::Program { .. } => "Program".to_string(),
+        Expr::Block { .. } => "Block".to_string(),
+        Expr::Output { .. } => "Output".to_string(),
+        Expr::MapLiteral { .. } => "MapLiteral".to_string(),
+        Expr::ListLiteral { .. } => "ListLiteral".to_string(),
+        Expr::Range(..) => "Range".to_string(),
+        Expr::UnaryExpr { .. } => "UnaryExpr".to_string(),
+        Expr::MultipleAssign { .. } => "MultipleAssign".to_string(),
+        Expr::StructLiteral { type_name, .. } => format!("StructLiteral {}", type_name),
+        Expr::Cast { .. } => "Cast".to_string(),
+        Expr::TypeAssertion { .. } => "TypeAssertion".to_string(),
+        Expr::DefaultValue { data_type, .. } => format!("DefaultValue {}", data_type),
+        Expr::Lambda { .. } => "Lambda".to_string(),
+        Expr::If { .. } => "If".to_string(),
+        Expr::Match { .. } => "Match".to_string(),
+        Expr::Wildcard => "Wildcard".to_string(),
+        Expr::EnumValue { .. } => "EnumValue".to_string(),
+        Expr::While { .. } => "While".to_string(),
+        Expr::Break => "Break".to_string(),
+        Expr::Continue => "Continue".to_string(),
+        Expr::Comprehension { .. } => "Comprehension".to_string(),
+        Expr::ForIn { var_name, .. } => format!("ForIn {}", var_name),
+        Expr::Import { path, .. } => format!("Import {}", path),
+        Expr::Return(_) => "Return".to_string(),
+        Expr::Unit => "Unit".to_string(),
+    };
+    let value = match result {
+        Ok(value) => value.to_string(),
+        Err(e) => format!("error: {}", e),
+    };
+    let line = format!("{} | {} => {}", symbols.nesting_depth(), step, value);
+    symbols.host.trace(&line);
+}
+
 fn interpret_binary(
     symbols: &mut SymbolTable,
     left: &Expr,
@@ -357,55 +1244,39 @@ fn interpret_binary(
     right: &Expr,
     current_scope: usize,
 ) -> InterpreterResult {
-    let mut error: Option<RuntimeError> = None;
-    let mut result: InterpreterResult = Ok(Expr::Unit);
-
+    if let Operator::Coalesce = op {
+        return match left.interpret(symbols, current_scope)? {
+            Expr::RuntimeOptional(Some(value)) => Ok(*value),
+            Expr::RuntimeOptional(None) => right.interpret(symbols, current_scope),
+            other => {
+                let msg = format!("'?:' needs an optional value, not {}", other);
+                Err(RuntimeError::new(&msg, None, None).into())
+            }
+        };
+    }
     // This is repetaative because we are optimizing for the case where the expressions
     // are literal values (primary expressions) and don't need to be interpreted.
     // This saves a clone().
     match (left, right) {
         (Expr::Literal(l_value), Expr::Literal(r_value)) => {
-            result = l_value.apply_binary_operator(r_value, op)
-        }
-        (_, Expr::Literal(r_value)) => {
-            if let Expr::Literal(ref l_value) = left.interpret(symbols, current_scope)? {
-                result = l_value.apply_binary_operator(r_value, op);
-            } else {
-                let msg = format!(
-                    "Result of {:?} isn't a simple primary expression. Cannot apply {:?} to it.",
-                    left, op
-                );
-                error = Some(RuntimeError::new(&msg, None, None).into());
-            }
-        }
-        (Expr::Literal(l_value), _) => {
-            if let Expr::Literal(ref r_value) = right.interpret(symbols, current_scope)? {
-                result = l_value.apply_binary_operator(r_value, op);
-            } else {
-                let msg = format!(
-                    "Result of {:?} isn't a simple primary expression. Cannot apply {:?} to it.",
-                    right, op
-                );
-                error = Some(RuntimeError::new(&msg, None, None));
-            }
+            l_value.apply_binary_operator(r_value, op)
         }
+        (_, Expr::Literal(r_value)) => match left.interpret(symbols, current_scope)? {
+            Expr::Literal(ref l_value) => l_value.apply_binary_operator(r_value, op),
+            l_value => apply_structural_operator(&l_value, op, right),
+        },
+        (Expr::Literal(l_value), _) => match right.interpret(symbols, current_scope)? {
+            Expr::Literal(ref r_value) => l_value.apply_binary_operator(r_value, op),
+            r_value => apply_structural_operator(left, op, &r_value),
+        },
         (_, _) => {
             let l_value = left.interpret(symbols, current_scope)?;
             let r_value = right.interpret(symbols, current_scope)?;
-            if let (Expr::Literal(ref l_data), Expr::Literal(ref r_data)) = (l_value, r_value) {
-                result = l_data.apply_binary_operator(r_data, op);
+            if let (Expr::Literal(ref l_data), Expr::Literal(ref r_data)) = (&l_value, &r_value) {
+                l_data.apply_binary_operator(r_data, op)
             } else {
-                let msg = format!(
-                    "Expressions don't evaluate to anything applicable to a binary operator: {:?}, {:?}",
-                    &left, &right
-                );
-                error = Some(RuntimeError::new(&msg, None, None));
+                apply_structural_operator(&l_value, op, &r_value)
             }
         }
     }
-    if let Some(e) = error {
-        Err(e.into())
-    } else {
-        result
-    }
 }
@@ -1 +1,46 @@
+use lalrpop_util::lalrpop_mod;
+use std::error::Error;
 
+pub mod builtins;
+pub mod host;
+pub mod interpreter;
+pub mod modules;
+pub mod semantic_analysis;
+pub mod sequence;
+pub mod source_map;
+pub mod statements;
+pub mod symboltable;
+pub mod syntax;
+pub mod tokens;
+pub mod value;
+
+lalrpop_mod!(#[allow(clippy::all)] pub grammar); // synthesized by LALRPOP
+
+// Parses, checks and runs a whole program, giving back the value of its last expression,
+// or what its main() returns when it has one.
+pub fn run_str(src: &str) -> Result<value::Value, Box<dyn Error>> {
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut root_expr = parser.parse(src).map_err(|e| e.to_string())?;
+    let mut symbols = symboltable::SymbolTable::new();
+    let prepared = root_expr
+        .prepare(&mut symbols)
+        .and_then(|_| semantic_analysis::check_main(&root_expr, &symbols).map_err(|e| vec![e]));
+    if let Err(mut errors) = prepared {
+        let messages: Vec<String> = errors
+            .iter_mut()
+            .map(|e| {
+                e.locate(src);
+                e.to_string()
+            })
+            .chain(semantic_analysis::omitted_errors_summary(
+                symbols.omitted_errors,
+            ))
+            .collect();
+        return Err(messages.join("\n").into());
+    }
+    let result = root_expr.interpret_program(&mut symbols).map_err(|mut e| {
+        interpreter::locate_runtime_error(&mut e, src);
+        e
+    })?;
+    Ok(value::Value::try_from(&result)?)
+}
@@ -1,34 +1,217 @@
-mod interpreter;
-mod semantic_analysis;
-mod symboltable;
-mod syntax;
-use interpreter::InterpreterResult;
-use lalrpop_util::{lalrpop_mod, ParseError};
+use lalrpop_util::ParseError;
+use lift_lang::grammar;
+use lift_lang::host::Buffering;
+use lift_lang::interpreter::locate_runtime_error;
+#[cfg(test)]
+use lift_lang::interpreter::InterpreterResult;
+use lift_lang::semantic_analysis;
+use lift_lang::semantic_analysis::CompileError;
+#[cfg(test)]
+use lift_lang::symboltable;
+use lift_lang::symboltable::SymbolTable;
+use lift_lang::syntax::*;
 use std::error;
-use std::error::Error;
 use std::fs;
-use symboltable::SymbolTable;
-use syntax::*;
+use std::path::Path;
 
-use rustyline::completion::FilenameCompleter;
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor};
 
 use std::borrow::Cow::{self, Borrowed, Owned};
 
 use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
 use rustyline::hint::HistoryHinter;
-use rustyline::validate::MatchingBracketValidator;
-use rustyline::{Cmd, CompletionType, Config, EditMode, Editor, KeyEvent};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::{Completer, Helper, Hinter, Validator};
 
+// Words the REPL offers to complete besides the names a session defines.
+const KEYWORDS: &[&str] = &[
+    "let",
+    "if",
+    "else",
+    "while",
+    "times",
+    "break",
+    "continue",
+    "where",
+    "match",
+    "cond",
+    "function",
+    "Lambda",
+    "type",
+    "struct",
+    "output",
+    "and",
+    "or",
+    "true",
+    "false",
+    "Int",
+    "Flt",
+    "Str",
+    "Bool",
+    "Any",
+    "I32",
+    "I64",
+    "F32",
+    "F64",
+    "List",
+    "Map",
+    "Set",
+    "Seq",
+    "Optional",
+    "new",
+    "as",
+    "for",
+    "in",
+    "import",
+    "assert_type",
+    "default",
+];
+
+// Completes the word before the cursor from the keywords and 'names'. Gives the
+// position the word starts at along with the candidates, as rustyline wants.
+fn completions(line: &str, pos: usize, names: &[String]) -> (usize, Vec<String>) {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let start = line[..pos]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !is_name_char(*c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let prefix = &line[start..pos];
+    if prefix.is_empty() {
+        return (pos, Vec::new());
+    }
+    let mut candidates: Vec<String> = KEYWORDS
+        .iter()
+        .map(|word| word.to_string())
+        .chain(names.iter().cloned())
+        .filter(|word| word.starts_with(prefix))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+// The REPL refreshes 'names' from its symbol table after every input.
+#[derive(Default)]
+struct NameCompleter {
+    names: Vec<String>,
+}
+
+// The builtins and whatever the session has defined at the top level.
+fn session_names(symbols: &SymbolTable) -> Vec<String> {
+    symbols
+        .builtins
+        .names()
+        .map(|name| name.to_string())
+        .chain(symbols.exported_types(0).into_iter().map(|(name, _)| name))
+        .collect()
+}
+
+impl Completer for NameCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(completions(line, pos, &self.names))
+    }
+}
+
+// How the brackets in a REPL input stand. Brackets inside strings don't count.
+#[derive(Debug, PartialEq)]
+enum BracketBalance {
+    Balanced,
+    // Something is still open, so there's more input to come.
+    Open,
+    // A closing bracket that doesn't match what's open.
+    Unexpected(char),
+}
+
+fn bracket_balance(input: &str) -> BracketBalance {
+    let mut open = Vec::new();
+    let mut in_string = false;
+    for c in input.chars() {
+        match c {
+            '\'' => in_string = !in_string,
+            _ if in_string => (),
+            '(' | '[' | '{' => open.push(c),
+            ')' | ']' | '}' => {
+                let closes = match open.pop() {
+                    Some('(') => ')',
+                    Some('[') => ']',
+                    Some('{') => '}',
+                    _ => return BracketBalance::Unexpected(c),
+                };
+                if c != closes {
+                    return BracketBalance::Unexpected(c);
+                }
+            }
+            _ => (),
+        }
+    }
+    if open.is_empty() && !in_string {
+        BracketBalance::Balanced
+    } else {
+        BracketBalance::Open
+    }
+}
+
+// Holds back an input with unclosed brackets so Enter starts a new line of it instead.
+struct BracketValidator;
+
+impl Validator for BracketValidator {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(match bracket_balance(ctx.input()) {
+            BracketBalance::Balanced => ValidationResult::Valid(None),
+            BracketBalance::Open => ValidationResult::Incomplete,
+            BracketBalance::Unexpected(c) => {
+                ValidationResult::Invalid(Some(format!(" -- unexpected '{}'", c)))
+            }
+        })
+    }
+}
+
+// Shows keywords in bold blue, leaving strings alone.
+fn highlight_keywords(line: &str) -> String {
+    let mut highlighted = String::new();
+    let mut word = String::new();
+    let mut in_string = false;
+    let flush = |word: &mut String, highlighted: &mut String| {
+        if KEYWORDS.contains(&word.as_str()) {
+            highlighted.push_str(&format!("\x1b[1;34m{}\x1b[0m", word));
+        } else {
+            highlighted.push_str(word);
+        }
+        word.clear();
+    };
+    for c in line.chars() {
+        if !in_string && (c.is_alphanumeric() || c == '_') {
+            word.push(c);
+            continue;
+        }
+        flush(&mut word, &mut highlighted);
+        if c == '\'' {
+            in_string = !in_string;
+        }
+        highlighted.push(c);
+    }
+    flush(&mut word, &mut highlighted);
+    highlighted
+}
+
 #[derive(Helper, Completer, Hinter, Validator)]
 struct MyHelper {
     #[rustyline(Completer)]
-    completer: FilenameCompleter,
+    completer: NameCompleter,
     highlighter: MatchingBracketHighlighter,
     #[rustyline(Validator)]
-    validator: MatchingBracketValidator,
+    validator: BracketValidator,
     #[rustyline(Hinter)]
     hinter: HistoryHinter,
     colored_prompt: String,
@@ -51,17 +234,19 @@ impl Highlighter for MyHelper {
         Owned("\x1b[1m".to_owned() + hint + "\x1b[m")
     }
 
+    // The bracket highlighting goes first since it works from the cursor's position in
+    // the plain line.
     fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
-        self.highlighter.highlight(line, pos)
+        let with_brackets = self.highlighter.highlight(line, pos);
+        Owned(highlight_keywords(&with_brackets))
     }
 
-    fn highlight_char(&self, line: &str, pos: usize, forced: bool) -> bool {
-        self.highlighter.highlight_char(line, pos, forced)
+    // Any character can finish a keyword, so every change redraws the line.
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
     }
 }
 
-lalrpop_mod!(pub grammar); // synthesized by LALRPOP
-
 #[test]
 fn test_parse_numbers() {
     let src = "3";
@@ -125,6 +310,7 @@ fn test_binary_expression_parsing() {
         left: one.clone(),
         op: Operator::Add,
         right: two.clone(),
+        span: (0, 5),
     };
 
     match parse_result {
@@ -142,9 +328,11 @@ fn test_binary_expression_parsing() {
             left: one.clone(),
             op: Operator::Mul,
             right: two.clone(),
+            span: (1, 4),
         }),
         op: Operator::Sub,
         right: two,
+        span: (1, 7),
     };
 
     let parse_result = parser.parse(src);
@@ -231,7 +419,7 @@ fn test_interpret_conditionals() {
     let parse_result = parser.parse(src);
     match parse_result {
         Err(ref e) => eprintln!("Parse conditional failed: {:?}", &e),
-        Ok(ref r) => println!("Success parsing conditional."),
+        Ok(_) => println!("Success parsing conditional."),
     }
     assert!(parse_result.is_ok());
 
@@ -248,7 +436,7 @@ fn test_interpret_conditionals() {
     let parse_result = parser.parse(src);
     match parse_result {
         Err(ref e) => eprintln!("Parse conditional failed: {:?}", &e),
-        Ok(ref r) => println!("Success parsing conditional."),
+        Ok(_) => println!("Success parsing conditional."),
     }
     assert!(parse_result.is_ok());
     let mut symbols = SymbolTable::new();
@@ -263,7 +451,7 @@ fn test_variables() {
     let parse_result = parser.parse(src);
     match parse_result {
         Err(ref e) => eprintln!("Parse variable definition failed: {:?}", &e),
-        Ok(ref r) => println!("Success parsing variable definition 'let'."),
+        Ok(_) => println!("Success parsing variable definition 'let'."),
     }
     assert!(parse_result.is_ok());
     let mut root_expr = parse_result.unwrap();
@@ -295,7 +483,7 @@ fn test_functions() {
     let parse_result = parser.parse(src);
     match parse_result {
         Err(ref e) => eprintln!("Parse function definition : {:?}", &e),
-        Ok(ref r) => println!("Success parsing function definition."),
+        Ok(_) => println!("Success parsing function definition."),
     }
     assert!(parse_result.is_ok());
     let mut root_expr = parse_result.unwrap();
@@ -310,136 +498,3142 @@ fn test_functions() {
     assert!(s.is_ok());
 }
 
-// A test helper
-fn check_value(s: &InterpreterResult, value: LiteralData) -> bool {
-    if let Ok(ref e) = s {
-        return e.has_value(&value);
-    }
-    false
+#[test]
+fn test_struct_comparison() {
+    let src = "{type Point = struct (x: Int, y: Int);
+        let a = Point(x: 1, y: 2);
+        let b = Point(y: 3, x: 1);
+        if a < b and a <> b and a = Point(x: 1, y: 2) { a.y } else { 0 }
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(2)));
 }
 
-fn extract_value(r: InterpreterResult) -> LiteralData {
-    if let Ok(Expr::Literal(l)) = r {
-        return l;
-    }
-    panic!("Must pass an interpreter result that holds a literal data value.");
+#[test]
+fn test_empty_structs() {
+    let src = "{type Marker = struct();
+        let a = Marker();
+        let b: Marker = Marker();
+        output(a);
+        if a = b and a <= b and a >= b { 1 } else { 0 }
+    }";
+    let (root_expr, mut symbols) = prepare_source(src).unwrap();
+    symbols.host.capture_output();
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Int(1)));
+    assert_eq!("Marker() \n", symbols.host.take_output());
+
+    // One can be a field like any other struct.
+    let src = "{type Marker = struct();
+        type Tagged = struct (tag: Marker, n: Int);
+        Tagged(tag: Marker(), n: 1) = Tagged(n: 1, tag: Marker())
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    assert!(prepare_source("{type Marker = struct(); Marker(x: 1)}").is_err());
 }
 
-pub fn repl() {
-    let mut quit = false;
+#[test]
+fn test_sort_list_of_structs() {
+    let src = "{type Point = struct (x: Int, y: Int);
+        let points: List of Point = [Point(x: 3, y: 1), Point(x: 1, y: 5), Point(x: 1, y: 2)];
+        sort(points)
+    }";
+    let s = interpret_source(src);
+    assert_eq!(
+        s.unwrap().to_string(),
+        "[Point(x: 1, y: 2), Point(x: 1, y: 5), Point(x: 3, y: 1)]"
+    );
+}
+
+#[test]
+fn test_nan_comparisons() {
+    // NaN is unequal and unordered, even against itself.
+    let s =
+        interpret_source("{let nan = 0.0 / 0.0; [nan = nan, nan != nan, nan < 1.0, nan >= 1.0]}");
+    assert_eq!(s.unwrap().to_string(), "[false, true, false, false]");
+
+    let s = interpret_source("[2.5, 0.0 / 0.0, 1.0, 0.0 / 0.0, 0.5].sort()").unwrap();
+    let Expr::RuntimeList { data, .. } = s else {
+        panic!("sort() should give a List");
+    };
+    let sorted: Vec<f64> = data
+        .into_iter()
+        .map(|item| match extract_value(Ok(item)) {
+            LiteralData::Flt(f) => f,
+            other => panic!("Expected a Flt, got {}", other),
+        })
+        .collect();
+    assert_eq!(&sorted[..3], &[0.5, 1.0, 2.5]);
+    assert!(sorted[3].is_nan() && sorted[4].is_nan());
+}
+
+#[test]
+fn test_struct_comparison_requires_comparable_fields() {
+    let src = "{type Lookup = Map of Str to Int;
+        type Bag = struct (items: Lookup);
+        function smaller(a: Bag, b: Bag): Bool { a < b }
+    }";
+    let Err(errors) = prepare_source(src) else {
+        panic!("comparing structs with a Map field should fail the type check");
+    };
+    assert!(errors[0].to_string().contains("field 'items'"));
+}
+
+#[test]
+fn test_multiple_assignment_swaps() {
+    let src = "{let a = 1; let b = 2; a, b := b, a; a * 10 + b}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(21)));
+
+    let src = "{let a = 1; let b = 2; a := b; a}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(2)));
+}
+
+#[test]
+fn test_multiple_assignment_typecheck() {
+    let src = "{let a = 1; let b = 2; a, b := 3, 4, 5}";
+    assert!(prepare_source(src).is_err());
+
+    let src = "{let a = 1; let b = 'x'; a, b := b, a}";
+    let Err(errors) = prepare_source(src) else {
+        panic!("swapping an Int and a Str should fail the type check");
+    };
+    assert!(errors[0].to_string().contains("'a' of type 'Int'"));
+}
+
+#[test]
+fn test_format() {
+    let src = "{let a = 1; let b = 2; format('{} + {} = {} {{ok}}', a, b, a + b)}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::from("'1 + 2 = 3 {ok}'")));
+
+    let src = "format('{} and {}', 'salt', 'pepper')";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::from("'salt and pepper'")));
+}
+
+#[test]
+fn test_format_specs() {
+    let s = interpret_source("format('{:.2}', 3.14159)");
+    assert!(check_value(&s, LiteralData::from("'3.14'")));
+    let s = interpret_source("format('#{:04} of {:3}|{:6.1}', 7, 12, 2.25)");
+    assert!(check_value(&s, LiteralData::from("'#0007 of  12|   2.2'")));
+    let s = interpret_source("format('{:05}', 0 - 42)");
+    assert!(check_value(&s, LiteralData::from("'-0042'")));
+
+    // Precision only applies to floats and zero-padding only to numbers.
+    let Err(e) = interpret_source("format('{:.2}', 3)") else {
+        panic!("a precision on an Int should be rejected");
+    };
+    assert!(e.to_string().contains("Unsupported format spec '{:.2}'"));
+    assert!(interpret_source("format('{:04}', 'abc')").is_err());
+    assert!(interpret_source("format('{:x}', 255)").is_err());
+}
+
+#[test]
+fn test_format_arity_mismatch() {
+    let src = "format('{} + {}', 1)";
+    let s = interpret_source(src);
+    let Err(e) = s else {
+        panic!("a missing format value should be a runtime error");
+    };
+    assert!(e.to_string().contains("2 placeholders but 1 values"));
+}
+
+#[test]
+fn test_min_max_of_list() {
+    let s = interpret_source("min([4, 2, 9, 2])");
+    assert_eq!(s.unwrap().to_string(), "some(2)");
+    let s = interpret_source("max([4, 2, 9, 2])");
+    assert_eq!(s.unwrap().to_string(), "some(9)");
+    let s = interpret_source("max(3, 7)");
+    assert!(check_value(&s, LiteralData::Int(7)));
+
+    let s = interpret_source("{let nothing: List of Int = []; min(nothing)}");
+    assert_eq!(s.unwrap(), Expr::RuntimeOptional(None));
+}
+
+#[test]
+fn test_numeric_reductions() {
+    let s = interpret_source("sum([1, 2, 3, 4])");
+    assert!(check_value(&s, LiteralData::Int(10)));
+    let s = interpret_source("product([1, 2, 3, 4])");
+    assert!(check_value(&s, LiteralData::Int(24)));
+    let s = interpret_source("avg([1.5, 2.5, 3.5])");
+    assert!(check_value(&s, LiteralData::Flt(2.5)));
+
+    let s = interpret_source("{let nothing: List of Int = []; sum(nothing)}");
+    assert!(check_value(&s, LiteralData::Int(0)));
+    let s = interpret_source("{let nothing: List of Flt = []; avg(nothing)}");
+    assert!(s.is_err());
+
+    assert!(prepare_source("sum(['a', 'b'])").is_err());
+}
+
+#[test]
+fn test_any_all_short_circuit() {
+    let src = "{let calls = 0;
+        let found = any([1, 5, 2, 7], Lambda (x: Int): Bool { calls := calls + 1; x > 4 });
+        if found { calls } else { 0 }
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(2)));
+
+    let src = "{let calls = 0;
+        let every = all([1, 5, 2, 7], Lambda (x: Int): Bool { calls := calls + 1; x < 3 });
+        if every { 0 } else { calls }
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(2)));
+
+    let src = "{function positive(n: Int): Bool { n > 0 }; all([1, 2], positive)}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    let src = "any([1, 2], Lambda (x: Str): Bool { true })";
+    assert!(prepare_source(src).is_err());
+}
+
+#[test]
+fn test_map_lookups() {
+    let src = "{let ages = ['ann': 31, 'bob': 45]; contains_key(ages, 'ann')}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    let src = "{let ages = ['ann': 31, 'bob': 45]; contains_key(ages, 'cy')}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Bool(false)));
+
+    let s = interpret_source("{let ages = ['ann': 31]; get_or(ages, 'ann', 99)}");
+    assert!(check_value(&s, LiteralData::Int(31)));
+
+    let s = interpret_source("{let ages = ['ann': 31]; get_or(ages, 'cy', 99)}");
+    assert!(check_value(&s, LiteralData::Int(99)));
+
+    assert!(prepare_source("{let ages = ['ann': 31]; get_or(ages, 'cy', 'old')}").is_err());
+}
+
+#[test]
+fn test_map_insertion_order() {
+    let src = "{['pear': 3, 'apple': 1, 'fig': 7, 'kiwi': 2]}";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(
+        "{'pear': 3, 'apple': 1, 'fig': 7, 'kiwi': 2}",
+        s.to_string()
+    );
+}
+
+#[test]
+fn test_set_conversions() {
+    let s = interpret_source("{to_set([3, 1, 3, 2, 1])}").unwrap();
+    assert_eq!("{3, 1, 2}", s.to_string());
+
+    let s = interpret_source("{to_list(to_set([3, 1, 3, 2, 1]))}").unwrap();
+    assert_eq!("[3, 1, 2]", s.to_string());
+
+    let s = interpret_source("{to_list(['b': 2, 'a': 1])}").unwrap();
+    assert_eq!("['b', 'a']", s.to_string());
+
+    let src = "{let unique: Set of Int = to_set([1, 1]); sum(to_list(unique))}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(1)));
+
+    assert!(prepare_source("{to_set([1.5, 2.5])}").is_err());
+    assert!(prepare_source("{to_list([1, 2])}").is_err());
+}
+
+#[test]
+fn test_parse_int_and_flt() {
+    let s = interpret_source("{parse_int('42')}").unwrap();
+    assert_eq!(
+        Expr::RuntimeOptional(Some(Box::new(Expr::Literal(LiteralData::Int(42))))),
+        s
+    );
+
+    let s = interpret_source("{parse_flt('2.5')}").unwrap();
+    assert_eq!(
+        Expr::RuntimeOptional(Some(Box::new(Expr::Literal(LiteralData::Flt(2.5))))),
+        s
+    );
+
+    let s = interpret_source("{parse_int('forty-two')}").unwrap();
+    assert_eq!(Expr::RuntimeOptional(None), s);
+    let s = interpret_source("{parse_flt('')}").unwrap();
+    assert_eq!(Expr::RuntimeOptional(None), s);
+
+    assert!(prepare_source("{parse_int(42)}").is_err());
+}
+
+#[test]
+fn test_number_theory_builtins() {
+    let s = interpret_source("{gcd(12, 18)}");
+    assert!(check_value(&s, LiteralData::Int(6)));
+    let s = interpret_source("{lcm(4, 6)}");
+    assert!(check_value(&s, LiteralData::Int(12)));
+    let s = interpret_source("{factorial(5)}");
+    assert!(check_value(&s, LiteralData::Int(120)));
+    let s = interpret_source("{factorial(20)}");
+    assert!(check_value(&s, LiteralData::Int(2432902008176640000)));
+
+    assert!(interpret_source("{factorial(21)}").is_err());
+    assert!(prepare_source("{gcd(1.5, 3)}").is_err());
+}
+
+#[test]
+fn test_clamp_and_lerp() {
+    let s = interpret_source("{clamp(0 - 3, 0, 10)}");
+    assert!(check_value(&s, LiteralData::Int(0)));
+    let s = interpret_source("{clamp(7, 0, 10)}");
+    assert!(check_value(&s, LiteralData::Int(7)));
+    let s = interpret_source("{clamp(12.5, 0.0, 10.0)}");
+    assert!(check_value(&s, LiteralData::Flt(10.0)));
+    let s = interpret_source("{lerp(2, 4, 0.5)}");
+    assert!(check_value(&s, LiteralData::Flt(3.0)));
+
+    assert!(prepare_source("{clamp(5, 10, 0)}").is_err());
+    assert!(prepare_source("{clamp(5, 0.0, 10)}").is_err());
+    assert!(interpret_source("{let lo = 10; clamp(5, lo, 0)}").is_err());
+}
+
+#[test]
+fn test_flt_math_builtins() {
+    let s = interpret_source("{sin(0)}");
+    assert!(check_value(&s, LiteralData::Flt(0.0)));
+    let s = interpret_source("{exp(0)}");
+    assert!(check_value(&s, LiteralData::Flt(1.0)));
+    let s = interpret_source("{ln(1.0)}");
+    assert!(check_value(&s, LiteralData::Flt(0.0)));
+    let s = interpret_source("{log10(1000)}");
+    assert!(check_value(&s, LiteralData::Flt(3.0)));
+    let s = interpret_source("{log(2, 8.0)}");
+    assert!(check_value(&s, LiteralData::Flt(3.0)));
+
+    // Outside their domains they give NaN or an infinity rather than failing.
+    let LiteralData::Flt(f) = extract_value(interpret_source("{ln(0 - 1)}")) else {
+        panic!("ln() should give a Flt");
+    };
+    assert!(f.is_nan());
+    let s = interpret_source("{ln(0)}");
+    assert!(check_value(&s, LiteralData::Flt(f64::NEG_INFINITY)));
+
+    assert!(prepare_source("{cos('x')}").is_err());
+    assert!(prepare_source("{log(8.0)}").is_err());
+}
+
+#[test]
+fn test_seeded_random() {
+    let src = "{seed(7); [random_int(1, 100), random_int(1, 100), random_int(1, 100)]}";
+    let first = interpret_source(src).unwrap();
+    let second = interpret_source(src).unwrap();
+    assert_eq!(first, second);
+
+    let s = interpret_source("{seed(7); random()}");
+    let LiteralData::Flt(f) = extract_value(s) else {
+        panic!("random() should give a Flt");
+    };
+    assert!((0.0..1.0).contains(&f));
+
+    let s = interpret_source("{seed(3); random_int(5, 5)}");
+    assert!(check_value(&s, LiteralData::Int(5)));
+
+    let (root_expr, mut symbols) = prepare_source("{random()}").unwrap();
+    symbols.host.allow_system_access = false;
+    assert!(root_expr.interpret(&mut symbols, 0).is_err());
+}
+
+#[test]
+fn test_clock_builtins() {
+    let s = interpret_source("{let a = now(); let b = now(); b >= a}");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+    let s = interpret_source("{let a = monotonic(); let b = monotonic(); b >= a}");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    assert!(prepare_source("{now(1)}").is_err());
+    let (root_expr, mut symbols) = prepare_source("{now()}").unwrap();
+    symbols.host.allow_system_access = false;
+    assert!(root_expr.interpret(&mut symbols, 0).is_err());
+}
+
+#[test]
+fn test_env_builtin() {
+    std::env::set_var("LIFT_TEST_ENV_BUILTIN", "from the environment");
+    let s = interpret_source("{env('LIFT_TEST_ENV_BUILTIN')}").unwrap();
+    assert_eq!("some('from the environment')", s.to_string());
+
+    let s = interpret_source("{env('LIFT_TEST_ENV_NEVER_SET')}").unwrap();
+    assert_eq!(Expr::RuntimeOptional(None), s);
+
+    assert!(prepare_source("{env(3)}").is_err());
+}
+
+#[test]
+fn test_program_args() {
+    let (root_expr, mut symbols) = prepare_source("{args()}").unwrap();
+    symbols.host.program_args = vec!["first".to_string(), "second one".to_string()];
+    let s = root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!("['first', 'second one']", s.to_string());
+
+    let s = interpret_source("{let a: List of Str = args(); a}").unwrap();
+    assert_eq!("[]", s.to_string());
+}
+
+#[test]
+fn test_prepare_twice() {
+    let src = "{type Point = struct (x: Int, y: Int);
+        let origin = Point(x: 0, y: 0);
+        function shift(p: Point, by: Int): Int { p.x + by };
+        let total = shift(origin, 5);
+        let small: I32 = 2;
+        let scaled: I32 = small * 3 + 1;
+        total
+    }";
+    let (mut root_expr, mut symbols) = prepare_source(src).unwrap();
+    let Expr::Block { environment, .. } = root_expr else {
+        panic!("expected a block");
+    };
+    let first_indices = ["origin", "shift", "total"]
+        .map(|name| symbols.find_index_reachable_from(name, environment));
+    let first_point = symbols.find_type_reachable_from("Point", environment);
+
+    assert!(root_expr.prepare(&mut symbols).is_ok());
+    let Expr::Block {
+        environment: second_environment,
+        ..
+    } = root_expr
+    else {
+        panic!("expected a block");
+    };
+    assert_eq!(environment, second_environment);
+    let second_indices = ["origin", "shift", "total"]
+        .map(|name| symbols.find_index_reachable_from(name, environment));
+    assert_eq!(first_indices, second_indices);
+    assert_eq!(
+        first_point,
+        symbols.find_type_reachable_from("Point", environment)
+    );
+
+    // Narrowing the I32 arithmetic wraps it in a cast once, not once per pass.
+    let second_tree = root_expr.clone();
+    assert!(root_expr.prepare(&mut symbols).is_ok());
+    assert_eq!(second_tree, root_expr);
+
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Int(5)));
+}
+
+#[test]
+fn test_prepare_first_global_twice() {
+    // The first global definition takes slot (0, 0), which preparing again must reuse
+    // rather than report as defined twice.
     let parser = grammar::ProgramPartExprParser::new();
     let mut symbols = SymbolTable::new();
+    let mut root_expr = parser.parse("let a = 1").unwrap();
+    root_expr.prepare(&mut symbols).unwrap();
+    assert_eq!(Some((0, 0)), symbols.find_index_reachable_from("a", 0));
+    assert!(root_expr.prepare(&mut symbols).is_ok());
+    assert_eq!(Some((0, 0)), symbols.find_index_reachable_from("a", 0));
 
-    let mut rl = DefaultEditor::new().unwrap();
+    // A new definition of the same name still is one.
+    let mut redefined = parser.parse("let a = 2").unwrap();
+    assert!(redefined.prepare(&mut symbols).is_err());
+}
 
-    //rl.bind_sequence(KeyEvent::alt('n'), Cmd::HistorySearchForward);
-    //rl.bind_sequence(KeyEvent::alt('p'), Cmd::HistorySearchBackward);
+#[test]
+fn test_symbol_table_snapshot() {
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut symbols = SymbolTable::new();
+    let mut first = parser.parse("let a = 1").unwrap();
+    first.prepare(&mut symbols).unwrap();
+    first.interpret(&mut symbols, 0).unwrap();
 
-    #[cfg(feature = "with-file-history")]
-    if rl.load_history("history.txt").is_err() {
-        println!("No previous history.");
-    }
+    let snapshot = symbols.snapshot();
+    let mut second = parser.parse("let b = 2").unwrap();
+    second.prepare(&mut symbols).unwrap();
+    assert!(symbols.find_index_reachable_from("b", 0).is_some());
 
-    let mut count = 0;
-    loop {
-        let mut buffer: String = "".to_string();
-        let mut prompt = format!("{count} ==> ");
-        loop {
-            let readline = rl.readline(&prompt);
-            match readline {
-                Ok(ref line) => {
-                    if let Some(continuation_line) = line.trim_right().strip_suffix('\\') {
-                        buffer.push_str(continuation_line);
-                        prompt = ">>".to_string();
-                        continue;
-                    } else {
-                        buffer.push_str(line);
-                        prompt = format!("{count} ==> ");
-                    }
-                    
-                    match parser.parse(&buffer) {
-                        Ok(mut ast) => {
-                            rl.add_history_entry(buffer.as_str());
+    assert_eq!(1, symbols.let_bindings.len());
+    symbols.restore(snapshot);
+    assert!(symbols.find_index_reachable_from("b", 0).is_none());
+    // What analysis recorded about 'b' goes with it.
+    assert_eq!("a", symbols.let_bindings[0].name);
+    assert_eq!(1, symbols.let_bindings.len());
+    let a_index = symbols.find_index_reachable_from("a", 0).unwrap();
+    assert_eq!(
+        Some(Expr::Literal(LiteralData::Int(1))),
+        symbols.get_runtime_value(&a_index)
+    );
 
-                            count += 1;
-                            if let Err(errors) = ast.prepare(&mut symbols) {
-                                for e in errors {
-                                    eprintln!("{}", &e);
-                                }
-                                println!();
-                            }
-                            match ast.interpret(&mut symbols, 0) {
-                                Err(interpreter_error) => eprintln!("{}", interpreter_error),
-                                Ok(res) => println!("=> '{}'", &res),
-                            }
-                            buffer.clear();
-                        }
-                        Err(ref parse_error) => match parse_error {
-                            ParseError::UnrecognizedEof { location, expected } => {
-                                buffer.push('\n');
-                                prompt = ">>".to_string();
-                            }
-                            _ => {
-                                eprintln!("ERROR: {}", parse_error);
-                                buffer.clear();
-                            }
-                        },
-                    } //  match parse
-                } // loop
-                Err(ReadlineError::Interrupted) => {
-                    println!("CTRL-C");
-                    quit = true;
-                    break;
-                }
-                Err(ReadlineError::Eof) => {
-                    println!("CTRL-D");
-                    break;
-                }
-                Err(err) => {
-                    println!("Error: {:?}", err);
-                    break;
-                }
-            } // match
-        } // loop
-        if quit {break;}
-    } // loop
-    #[cfg(feature = "with-file-history")]
-    rl.save_history("history.txt");
+    // With 'b' gone it can be defined again.
+    let mut again = parser.parse("let b = 3").unwrap();
+    assert!(again.prepare(&mut symbols).is_ok());
 }
 
-fn interpret_code(code: &str) -> Result<(), Box<dyn error::Error>> {
+#[test]
+fn test_repl_input_rollback() {
     let parser = grammar::ProgramPartExprParser::new();
-    let mut ast = match parser.parse(&code) {
-        Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(3);
-        }
-        Ok(parsed_ast) => parsed_ast,
+    let mut symbols = SymbolTable::new();
+    let mut eval = |input: &str| {
+        let mut ast = parser.parse(input).unwrap();
+        eval_repl_input(&mut ast, input, &mut symbols)
     };
+    assert!(eval("let total = 1").is_some());
+    assert!(eval("total := total + 1").is_some());
+
+    // The assignment happens before the failure, and is undone with everything else.
+    assert!(eval("{ total := 100; unwrap(parse_int('no')) }").is_none());
+    assert_eq!(Some(Expr::Literal(LiteralData::Int(2))), eval("total"));
+
+    assert!(eval("{ total := 100; total + 'x' }").is_none());
+    assert_eq!(Some(Expr::Literal(LiteralData::Int(2))), eval("total"));
+}
+
+#[test]
+fn test_builtin_shadowing() {
+    let src = "{function sum(values: List of Int): Int { 0 }; sum([1, 2])}";
+    let (root_expr, mut symbols) = prepare_source(src).unwrap();
+    assert_eq!(1, symbols.warnings.len());
+    assert!(symbols.warnings[0].to_string().contains("'sum' shadows"));
+    // The program's own definition is the one called.
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Int(0)));
 
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut root_expr = parser.parse(src).unwrap();
     let mut symbols = SymbolTable::new();
-    if let Err(ref errors) = ast.prepare(&mut symbols) {
-        for e in errors {
-            eprintln!("{}", e);
-        }
-    }
+    symbols.deny_builtin_shadowing = true;
+    assert!(root_expr.prepare(&mut symbols).is_err());
 
-    let res = ast.interpret(&mut symbols, 0)?;
-    println!("{}", res);
-    Ok(())
+    let (_, symbols) = prepare_source("{let total = sum([1, 2]); total}").unwrap();
+    assert!(symbols.warnings.is_empty());
 }
 
-fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        repl();
-    } else {
-        let program_file = &args[1];
-        let code = fs::read_to_string(program_file)
-            .expect(&format!("File at {} unreadable.", program_file));
+#[test]
+fn test_nested_functions() {
+    let src = "{function outer(n: Int): Int {
+            function double(x: Int): Int { x * 2 };
+            function helper(x: Int): Int { double(x) + n };
+            helper(5)
+        };
+        [outer(1), outer(100)]
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("[11, 110]", s.to_string());
+
+    // Nested functions are local to the function they're defined in.
+    let src = "{function outer(n: Int): Int {
+            function helper(x: Int): Int { x + n };
+            helper(5)
+        };
+        helper(1)
+    }";
+    assert!(prepare_source(src).is_err());
+
+    let src = "{function outer(n: Int): Int {
+            function bad(x: Int): Int { 'text' };
+            bad(n)
+        };
+        outer(1)
+    }";
+    assert!(prepare_source(src).is_err());
+}
 
-        if let Err(e) = interpret_code(&code) {
-            eprintln!("Error: {}", e);
+#[test]
+fn test_recursion_keeps_caller_values() {
+    let src = "{function fib(n: Int): Int {
+            if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+        };
+        fib(10)
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(55)));
+}
+
+#[test]
+fn test_operator_precedence_and_associativity() {
+    let parser = grammar::ProgramPartExprParser::new();
+    let should_be = Expr::sub(
+        Expr::sub(*make_literal_int(10), *make_literal_int(3), (0, 6)),
+        *make_literal_int(2),
+        (0, 10),
+    );
+    assert_eq!(should_be, parser.parse("10 - 3 - 2").unwrap());
+
+    let cases = [
+        ("10 - 3 - 2", 5),
+        ("100 / 10 / 5", 2),
+        ("2 + 3 * 4", 14),
+        ("2 * 3 + 4", 10),
+        ("20 - 6 / 2", 17),
+        ("(2 + 3) * 4", 20),
+        ("10 - (3 - 2)", 9),
+        ("100 / (10 / 5)", 50),
+    ];
+    for (src, expected) in cases {
+        let s = interpret_source(src);
+        assert!(
+            check_value(&s, LiteralData::Int(expected)),
+            "{} should be {}",
+            src,
+            expected
+        );
+    }
+
+    let s = interpret_source("1 + 2 < 2 * 2 and 3 - 1 = 2");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+}
+
+#[test]
+fn test_any_type() {
+    let src = "{let a: Any = 5; let b: Int = a + 1; b}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(6)));
+
+    // An 'Any' variable can take a value of a different type; the runtime keeps
+    // whatever it was given.
+    let s = interpret_source("{let a: Any = 5; a := 'five'; a}");
+    assert!(check_value(&s, LiteralData::Str("'five'".into())));
+    let s = interpret_source("{let a: Any = [3, 1, 2]; sort(a)}").unwrap();
+    assert_eq!("[1, 2, 3]", s.to_string());
+    let s = interpret_source("{let a: Any = 2; a < 3}");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    assert!(prepare_source("{let a = 5; a := 'five'; a}").is_err());
+}
+
+#[test]
+fn test_number_widths() {
+    let s = interpret_source("{let big: I32 = 2147483647; big}");
+    assert!(check_value(&s, LiteralData::Int(2147483647)));
+    assert!(interpret_source("{let big: I32 = 2147483647; big := big + 1; big}").is_err());
+    assert!(interpret_source("{let wide: I64 = 2147483647; wide := wide + 1; wide}").is_ok());
+
+    // 0.1 isn't exact at either width, so the F32 and F64 values differ.
+    let s = interpret_source("{let narrow: F32 = 0.1; let wide: F64 = 0.1; narrow = wide}");
+    assert!(check_value(&s, LiteralData::Bool(false)));
+    let s = interpret_source("{let narrow: F32 = 0.5; narrow = 0.5}");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    // Narrow values go into wider variables but not the other way around.
+    assert!(prepare_source("{let small: I32 = 5; let wide: Int = small; wide}").is_ok());
+    assert!(prepare_source("{let wide = 5; let small: I32 = wide; small}").is_err());
+    assert!(prepare_source("{let small: I32 = 1.5; small}").is_err());
+
+    // Arithmetic and returns are narrowed too, not only stores.
+    assert!(interpret_source("{function f(x: I32): I32 { x + 1 }; f(x: 2147483647)}").is_err());
+    assert!(interpret_source("{let x: I32 = 2147483647; let y: Int = x + 1; y}").is_err());
+    let s = interpret_source("{let x: I32 = 5; x + 1}");
+    assert!(check_value(&s, LiteralData::Int(6)));
+
+    // Mixing widths takes an explicit 'as'.
+    assert!(prepare_source("{let small: I32 = 5; let wide = 5; small + wide}").is_err());
+    let s = interpret_source("{let small: I32 = 5; let wide = 5; (wide as I32) + small}");
+    assert!(check_value(&s, LiteralData::Int(10)));
+    assert!(interpret_source("{let wide = 2147483648; wide as I32}").is_err());
+}
+
+#[test]
+fn test_deep_nesting_is_an_error() {
+    let depth = symboltable::MAX_NESTING_DEPTH + 10;
+    let src = format!("{}1{}", "1 + (".repeat(depth), ")".repeat(depth));
+    let checker = std::thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || {
+            let parser = grammar::ProgramPartExprParser::new();
+            let mut root_expr = parser.parse(&src).unwrap();
+            let mut symbols = SymbolTable::new();
+            let checked = semantic_analysis::typecheck(&mut root_expr, &mut symbols, 0);
+            let interpreted = root_expr.interpret(&mut symbols, 0);
+            (checked.is_err(), interpreted.is_err())
+        })
+        .unwrap();
+    assert_eq!((true, true), checker.join().unwrap());
+}
+
+#[test]
+fn test_exported_types() {
+    let src = "{type Point = struct (x: Int, y: Int);
+        let origin = Point(x: 0, y: 0);
+        let count = 3;
+        let names = ['a', 'b'];
+        function area(w: Int, h: Int): Int { w * h }
+    }";
+    let (root_expr, symbols) = prepare_source(src).unwrap();
+    let Expr::Block { environment, .. } = root_expr else {
+        panic!("expected a block");
+    };
+    let exported = symbols
+        .exported_types(environment)
+        .into_iter()
+        .map(|(name, data_type)| format!("{}: {}", name, data_type))
+        .collect::<Vec<String>>();
+    assert_eq!(
+        // Functions are declared ahead of the rest of their block, so they come first.
+        vec![
+            "area: function(Int, Int): Int",
+            "origin: Point",
+            "count: Int",
+            "names: List of Str",
+            "Point: struct (x: Int, y: Int)",
+        ],
+        exported
+    );
+}
+
+#[test]
+fn test_parse_statements_separately() {
+    let src = "let a = {1; 2}; let = 'oops;'; function f(x: Int): Int { x }";
+    let parsed = lift_lang::statements::parse_statements(src);
+    assert_eq!(3, parsed.len());
+    assert!(matches!(parsed[0], Ok((0, Expr::Let { ref var_name, .. })) if var_name == "a"));
+    assert!(
+        matches!(parsed[2], Ok((_, Expr::DefineFunction { ref fn_name, .. })) if fn_name == "f")
+    );
+
+    // A tree's spans plus its statement's offset point into the original text.
+    let Ok((start, Expr::Let { span, .. })) = &parsed[0] else {
+        panic!("the first statement should parse");
+    };
+    assert_eq!(src.find("{1; 2}").unwrap(), start + span.0);
+    let Ok((start, _)) = &parsed[2] else {
+        panic!("the last statement should parse");
+    };
+    assert_eq!(src.find(" function").unwrap(), *start);
+
+    // The error points into the original text, at the '=' after 'let'.
+    let Err(ParseError::UnrecognizedToken { token, .. }) = &parsed[1] else {
+        panic!("the middle statement should fail to parse");
+    };
+    assert_eq!(src.find("= 'oops").unwrap(), token.0);
+}
+
+#[test]
+fn test_display_function_value() {
+    let src = "{function inc(x: Int, by: Int): Int { x + by }; inc}";
+    let value = interpret_source(src).unwrap();
+    assert_eq!("function(x: Int, by: Int): Int", value.to_string());
+
+    let src = "{let f = Lambda (names: List of Str): Bool { true }; f}";
+    let value = interpret_source(src).unwrap();
+    assert_eq!("function(names: List of Str): Bool", value.to_string());
+}
+
+#[test]
+fn test_return_function() {
+    let src = "{
+        function adder(n: Int): function(Int): Int { Lambda (x: Int): Int { x + n } };
+        let add5 = adder(5);
+        let add10 = adder(10);
+        add5(3) + add10(3)
+    }";
+    let result = interpret_source(src);
+    assert!(check_value(&result, LiteralData::Int(21)));
+
+    // A curried function returning through two levels keeps both arguments.
+    let src = "{
+        function curried(a: Int): function(Int): function(Int): Int {
+            Lambda (b: Int): function(Int): Int { Lambda (c: Int): Int { a * 100 + b * 10 + c } }
+        };
+        let with_a = curried(1);
+        let with_b = with_a(2);
+        with_b(3)
+    }";
+    let result = interpret_source(src);
+    assert!(check_value(&result, LiteralData::Int(123)));
+
+    let src = "{function adder(n: Int): function(Int): Int { Lambda (x: Str): Int { n } }; 1}";
+    assert!(prepare_source(src).is_err());
+}
+
+#[test]
+fn test_pipe_and_compose() {
+    let functions = "function double(x: Int): Int { x * 2 };
+        function inc(x: Int): Int { x + 1 };
+        function describe(x: Int): Str { 'number' };";
+
+    let src = format!("{{{} 5 |> double |> inc}}", functions);
+    let result = interpret_source(&src);
+    assert!(check_value(&result, LiteralData::Int(11)));
+
+    // Composition runs left to right, and pipes bind more loosely than it.
+    let src = format!(
+        "{{{} let double_then_inc = double >> inc; double_then_inc(5) + (2 + 3 |> inc >> double)}}",
+        functions
+    );
+    let result = interpret_source(&src);
+    assert!(check_value(&result, LiteralData::Int(23)));
+
+    // describe gives a Str, which inc can't take.
+    let src = format!("{{{} let f = describe >> inc; 1}}", functions);
+    assert!(prepare_source(&src).is_err());
+    let src = format!("{{{} 'five' |> double}}", functions);
+    assert!(prepare_source(&src).is_err());
+}
+
+#[test]
+fn test_match_exhaustiveness() {
+    let color = "type Color = (red, green, blue);";
+
+    let src = format!(
+        "{{{} let c = Color.green; match c {{ Color.red => 1, Color.green => 2, Color.blue => 3 }}}}",
+        color
+    );
+    let result = interpret_source(&src);
+    assert!(check_value(&result, LiteralData::Int(2)));
+
+    let src = format!(
+        "{{{} let c = Color.green; match c {{ Color.red => 1, Color.green => 2 }}}}",
+        color
+    );
+    let Err(err) = prepare_source(&src) else {
+        panic!("a match missing 'blue' should fail the type check");
+    };
+    assert!(err[0].to_string().contains("doesn't cover blue"));
+
+    let src = format!(
+        "{{{} let c = Color.blue; match c {{ Color.red => 1, _ => 0 }}}}",
+        color
+    );
+    let result = interpret_source(&src);
+    assert!(check_value(&result, LiteralData::Int(0)));
+
+    let src = "{match 1 < 2 { true => 'yes', false => 'no' }}";
+    let result = interpret_source(src);
+    assert!(check_value(&result, LiteralData::Str("'yes'".into())));
+    let src = "{match 1 < 2 { true => 'yes' }}";
+    assert!(prepare_source(src).is_err());
+}
+
+#[test]
+fn test_cond() {
+    let grade = "function grade(score: Int): Str {
+            cond { score >= 90 => 'A', score >= 80 => 'B', else => 'C' }
+        };";
+    let result = interpret_source(&format!("{{{} grade(85)}}", grade));
+    assert!(check_value(&result, LiteralData::Str("'B'".into())));
+    let result = interpret_source(&format!("{{{} grade(12)}}", grade));
+    assert!(check_value(&result, LiteralData::Str("'C'".into())));
+
+    assert!(prepare_source("cond { 1 => 'one', else => 'other' }").is_err());
+    assert!(prepare_source("cond { true => 'one', else => 2 }").is_err());
+    let parser = grammar::ProgramPartExprParser::new();
+    let Err(err) = parser.parse("cond { true => 1, false => 2 }") else {
+        panic!("'cond' without an 'else' shouldn't parse");
+    };
+    assert!(err.to_string().contains("needs a last 'else"));
+}
+
+#[test]
+fn test_match_guards() {
+    let describe = "type Size = (small, large);
+        function describe(s: Size, n: Int): Str {
+            match s { Size.small if n > 10 => 'many small', Size.small => 'small', Size.large => 'large' }
+        };";
+    let result = interpret_source(&format!("{{{} describe(Size.small, 20)}}", describe));
+    assert!(check_value(
+        &result,
+        LiteralData::Str("'many small'".into())
+    ));
+    // The guard fails, so the next arm gets the value.
+    let result = interpret_source(&format!("{{{} describe(Size.small, 5)}}", describe));
+    assert!(check_value(&result, LiteralData::Str("'small'".into())));
+
+    let src = "{match 3 { 3 if 1 => 'three', _ => 'other' }}";
+    assert!(prepare_source(src).is_err());
+
+    // The guarded arm for 'large' doesn't count, so 'large' is still missing.
+    let src = "{
+        type Size = (small, large);
+        let s = Size.large;
+        match s { Size.small => 1, Size.large if true => 2 }
+    }";
+    let Err(err) = prepare_source(src) else {
+        panic!("a guarded arm shouldn't make the match exhaustive");
+    };
+    assert!(err[0].to_string().contains("doesn't cover large"));
+}
+
+#[test]
+fn test_run_str_values() {
+    use lift_lang::value::Value;
+
+    assert_eq!(Value::Int(3), lift_lang::run_str("1 + 2").unwrap());
+    assert_eq!(
+        Value::Str("salt and pepper".to_string()),
+        lift_lang::run_str("format('{} and {}', 'salt', 'pepper')").unwrap()
+    );
+
+    let src = "{
+        type Point = struct (x: Int, y: Int);
+        [[Point(x: 1, y: 2)], [Point(x: 3, y: 4), Point(x: 5, y: 6)]]
+    }";
+    let point = |x, y| Value::Struct {
+        type_name: "Point".to_string(),
+        fields: vec![
+            ("x".to_string(), Value::Int(x)),
+            ("y".to_string(), Value::Int(y)),
+        ],
+    };
+    let expected = Value::List(vec![
+        Value::List(vec![point(1, 2)]),
+        Value::List(vec![point(3, 4), point(5, 6)]),
+    ]);
+    let result = lift_lang::run_str(src).unwrap();
+    assert_eq!(expected, result);
+    assert_eq!(expected.clone(), result.clone());
+    assert_ne!(Value::List(vec![Value::List(vec![point(1, 2)])]), result);
+
+    // NaN isn't equal to itself, in Lift or here.
+    let nan = lift_lang::run_str("0.0 / 0.0").unwrap();
+    assert_ne!(nan, nan.clone());
+
+    // Sets and maps are equal whatever order their entries came in.
+    let expected = Value::Set(vec![Value::Int(2), Value::Int(1)]);
+    assert_eq!(expected, lift_lang::run_str("to_set([1, 2])").unwrap());
+    assert_ne!(expected, lift_lang::run_str("to_set([1, 3])").unwrap());
+    let expected = Value::Map(vec![
+        (Value::Str("b".to_string()), Value::Int(2)),
+        (Value::Str("a".to_string()), Value::Int(1)),
+    ]);
+    assert_eq!(expected, lift_lang::run_str("['a': 1, 'b': 2]").unwrap());
+    assert_ne!(expected, lift_lang::run_str("['a': 2, 'b': 1]").unwrap());
+
+    assert!(lift_lang::run_str("1 +").is_err());
+}
+
+#[test]
+fn test_repl_completions() {
+    let mut names = vec![
+        "total".to_string(),
+        "to_celsius".to_string(),
+        "width".to_string(),
+    ];
+    names.extend(session_names(&SymbolTable::new()));
+
+    let (start, candidates) = completions("let t = to", 10, &names);
+    assert_eq!(8, start);
+    assert_eq!(vec!["to_celsius", "to_list", "to_set", "total"], candidates);
+
+    // Keywords and type names complete too, and only the word before the cursor counts.
+    let (start, candidates) = completions("wh; x", 2, &names);
+    assert_eq!(0, start);
+    assert_eq!(vec!["where", "while"], candidates);
+    let (_, candidates) = completions("let a: Fl", 9, &names);
+    assert_eq!(vec!["Flt"], candidates);
+
+    assert!(completions("1 + ", 4, &names).1.is_empty());
+}
+
+#[test]
+fn test_repl_bracket_balance() {
+    assert_eq!(
+        BracketBalance::Balanced,
+        bracket_balance("{let a = [1, (2 + 3)]; a}")
+    );
+    assert_eq!(
+        BracketBalance::Open,
+        bracket_balance("function f(x: Int): Int {")
+    );
+    assert_eq!(
+        BracketBalance::Open,
+        bracket_balance("output('an open string")
+    );
+    // Brackets in strings don't count.
+    assert_eq!(BracketBalance::Balanced, bracket_balance("output('{ :)')"));
+    assert_eq!(BracketBalance::Unexpected(')'), bracket_balance("{1 + 2)"));
+    assert_eq!(BracketBalance::Unexpected('}'), bracket_balance("}"));
+}
+
+#[test]
+fn test_repl_keyword_highlighting() {
+    assert_eq!(
+        "\x1b[1;34mlet\x1b[0m letter = 'let'",
+        highlight_keywords("let letter = 'let'")
+    );
+}
+
+#[test]
+fn test_json_diagnostics() {
+    let src = "{let a: Int = 'one'; a}";
+    let json = diagnostics(src, SymbolTable::new());
+    assert!(json.starts_with("[{\"severity\": \"error\", \"message\": \""));
+    assert!(json.contains("\"line\": 1, \"column\": 15, \"end_line\": 1, \"end_column\": 20"));
+    assert!(json.ends_with("\"error_type\": \"typecheck\", \"code\": \"E0002\"}]"));
+    assert_eq!("[]", diagnostics("{let a = 1; a}", SymbolTable::new()));
+
+    // Every error is reported, not only the first.
+    let json = diagnostics(
+        "{let a: Int = 'x';\nlet b: Int = 'y';\na}",
+        SymbolTable::new(),
+    );
+    assert_eq!(2, json.matches("\"severity\": \"error\"").count());
+    assert!(json.contains("\"line\": 2, \"column\": 14"));
+
+    // Parse errors point at the token the parser stopped on.
+    let json = diagnostics("{let a = 1;\nlet c = a +;\n}", SymbolTable::new());
+    assert!(json.contains("\"line\": 2, \"column\": 12, \"end_line\": 2, \"end_column\": 13"));
+
+    let error = CompileError::name("no \"x\"\nhere", (2, 5));
+    assert_eq!(
+        "{\"severity\": \"warning\", \"message\": \"no \\\"x\\\"\\nhere\", \"line\": 2, \"column\": 5, \"end_line\": 2, \"end_column\": 5, \"error_type\": \"name\", \"code\": \"E0001\"}",
+        error.to_json("warning")
+    );
+}
+
+#[test]
+fn test_error_spans() {
+    let src = "{
+    let a: Int = 'x' + 'y';
+    a
+}";
+    let Err(errors) = prepare_source(src) else {
+        panic!("initializing an Int with a Str should fail the type check");
+    };
+    // From the first quote to just past the last one.
+    let span = errors[0].span();
+    assert_eq!((2, 18), span.start);
+    assert_eq!((2, 27), span.end);
+    assert!(errors[0]
+        .to_string()
+        .contains(": 2, 18 to 2, 27: Can't initialize 'a'"));
+
+    let json = diagnostics(src, SymbolTable::new());
+    assert!(json.contains("\"line\": 2, \"column\": 18, \"end_line\": 2, \"end_column\": 27"));
+}
+
+#[test]
+fn test_call_and_condition_spans() {
+    // Builtin calls and conditions know where they are even outside a block.
+    let Err(errors) = prepare_source("clamp(5, 10, 0)") else {
+        panic!("a lower bound above the upper one should fail the type check");
+    };
+    assert_eq!((1, 1), errors[0].span().start);
+    assert_eq!((1, 16), errors[0].span().end);
+    let Err(errors) = prepare_source("sum(['a'])") else {
+        panic!("summing Strs should fail the type check");
+    };
+    assert_eq!((1, 1), errors[0].span().start);
+    for src in ["if 1 { 2 } else { 3 }", "cond { 2 => 2, else => 3 }"] {
+        let Err(errors) = prepare_source(src) else {
+            panic!("an Int condition should fail the type check");
+        };
+        let span = errors[0].span();
+        assert_eq!(
+            span.start.1 + 1,
+            span.end.1,
+            "{} should point at the condition",
+            src
+        );
+    }
+
+    // A builtin that fails while running is shown where it was called.
+    let src = "{let o: Optional Int = none();\n 1 + unwrap(o)}";
+    let mut err = interpret_source(src).unwrap_err();
+    locate_runtime_error(&mut err, src);
+    assert!(err
+        .to_string()
+        .ends_with("2, 6: unwrap() of none\n2 |  1 + unwrap(o)}\n  |      ^^^^^^^^^"));
+}
+
+#[test]
+fn test_literal_item_types() {
+    // Every item has to match the first, and the error points at the one that doesn't.
+    let Err(errors) = prepare_source("{let b: List of Int = [1, 'x']; b}") else {
+        panic!("a Str in a List of Int should fail the type check");
+    };
+    assert_eq!((1, 27), errors[0].span().start);
+    assert_eq!((1, 30), errors[0].span().end);
+    assert!(errors[0]
+        .to_string()
+        .contains("The items must all be the same type: expected 'Int', got 'Str'"));
+
+    let Err(errors) = prepare_source("['a': 1, 'b': 'x']") else {
+        panic!("a map whose values differ should fail the type check");
+    };
+    assert_eq!((1, 10), errors[0].span().start);
+    assert!(prepare_source("['a': 1, 2: 3]").is_err());
+
+    // A narrow number fits a list of the wider one.
+    assert!(prepare_source("{let small: I32 = 1; [5, small]}").is_ok());
+}
+
+#[test]
+fn test_string_concatenation() {
+    let s = interpret_source("'a' + 'bc'").unwrap();
+    assert_eq!("'abc'", s.to_string());
+    let src = "{ let greeting = 'hello'; let name: Str = 'world'; greeting + ', ' + name + '' }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Str("'hello, world'".into())), s);
+    // The quotes come off both before they're joined.
+    let s = interpret_source("len('a' + 'bc')").unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(3)), s);
+
+    let src = "{
+    let n = 1;
+    'count: ' + n
+}";
+    let Err(errors) = prepare_source(src) else {
+        panic!("adding an Int to a Str should fail the type check");
+    };
+    let span = errors[0].span();
+    assert_eq!((3, 5), span.start);
+    assert_eq!((3, 18), span.end);
+    assert!(errors[0]
+        .to_string()
+        .contains("Can't add 'Str' and 'Int'; '+' only joins two Strs"));
+    assert!(prepare_source("'ab' - 'b'").is_err());
+    assert!(prepare_source("2 * 'ab'").is_err());
+}
+
+#[test]
+fn test_error_spans_in_blocks() {
+    // Errors from statements that don't know their own location point at the statement.
+    let src = "{
+    let a = 1;
+    {
+        let b = 2;
+        let c = 3;
+        a := 'x'
+    }
+}";
+    let Err(errors) = prepare_source(src) else {
+        panic!("assigning a Str to an Int should fail the type check");
+    };
+    let span = errors[0].span();
+    assert_eq!((6, 9), span.start);
+    assert_eq!((6, 17), span.end);
+
+    let src = "{
+    let a = 1;
+    let b = 2;
+    output(c)
+}";
+    let Err(errors) = prepare_source(src) else {
+        panic!("'c' is undefined");
+    };
+    assert_eq!((4, 5), errors[0].span().start);
+
+    // One that does know keeps its own, narrower, location.
+    let src = "{
+    let a = 1;
+    let b = 2;
+    output(a + 1 / 0)
+}";
+    let Err(errors) = prepare_source(src) else {
+        panic!("dividing by zero should fail the type check");
+    };
+    assert_eq!((4, 16), errors[0].span().start);
+}
+
+#[test]
+fn test_source_snippets() {
+    use lift_lang::semantic_analysis::Span;
+    use lift_lang::source_map::SourceMap;
+    let src = "{
+    let a: Int = 'x' + 'y';
+    a
+}";
+    let source = SourceMap::new(src);
+    let snippet = source.snippet(Span {
+        start: (2, 18),
+        end: (2, 27),
+    });
+    assert_eq!(
+        Some("2 |     let a: Int = 'x' + 'y';\n  |                  ^^^^^^^^^".to_string()),
+        snippet
+    );
+    // A point gets one caret; a span onto later lines is marked to the end of its first.
+    assert_eq!(
+        Some("3 |     a\n  |     ^".to_string()),
+        source.snippet(Span::from((3, 5)))
+    );
+    assert_eq!(
+        Some("2 |     let a: Int = 'x' + 'y';\n  |             ^^^^^^^^^^^^^^^".to_string()),
+        source.snippet(Span {
+            start: (2, 13),
+            end: (3, 6)
+        })
+    );
+    assert_eq!(None, source.snippet(Span::default()));
+    assert_eq!(None, source.snippet(Span::from((9, 1))));
+
+    // Located errors show it under their message.
+    let Err(errors) = prepare_source(src) else {
+        panic!("initializing an Int with a Str should fail the type check");
+    };
+    assert!(errors[0]
+        .to_string()
+        .ends_with("Str'\n2 |     let a: Int = 'x' + 'y';\n  |                  ^^^^^^^^^"));
+}
+
+#[test]
+fn test_method_call_syntax() {
+    let s = interpret_source("'hi'.upper() = upper('hi')");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+    let s = interpret_source("'hi'.upper()");
+    assert_eq!("'HI'", s.unwrap().to_string());
+
+    // The receiver goes first, before any other arguments, and calls chain.
+    let s = interpret_source("[3, 1, 2].sort().reverse()");
+    assert_eq!("[3, 2, 1]", s.unwrap().to_string());
+    let s = interpret_source("[4, 6, 8].len()");
+    assert!(check_value(&s, LiteralData::Int(3)));
+    let s = interpret_source("12.gcd(18)");
+    assert!(check_value(&s, LiteralData::Int(6)));
+
+    // Program functions and field access still work alongside.
+    let src = "{type Point = struct (x: Int, y: Int);
+        function shifted(p: Point, by: Int): Int { p.x + by };
+        let p = Point(x: 1, y: 2);
+        p.shifted(10) + p.y}";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(13)));
+
+    // Typechecked as the prefix call would be.
+    let Err(err) = prepare_source("5.upper()") else {
+        panic!("upper() of an Int should fail the type check");
+    };
+    assert!(err[0]
+        .to_string()
+        .contains("upper() expects a Str, got 'Int'"));
+}
+
+#[test]
+fn test_not_equal_spellings() {
+    let parser = grammar::ProgramPartExprParser::new();
+    assert_eq!(
+        parser.parse("1 != 2").unwrap(),
+        parser.parse("1 <> 2").unwrap()
+    );
+    let s = interpret_source("1 != 2");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+    let s = interpret_source("1 <> 2");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+    let s = interpret_source("'a' != 'a'");
+    assert!(check_value(&s, LiteralData::Bool(false)));
+}
+
+#[test]
+fn test_double_equals() {
+    let parser = grammar::ProgramPartExprParser::new();
+    for src in ["x == 5", "x = 5"] {
+        let Ok(Expr::BinaryExpr { op, .. }) = parser.parse(src) else {
+            panic!("'{}' should parse as a comparison", src);
+        };
+        assert_eq!(Operator::Eq, op);
+    }
+    let s = interpret_source("{let x = 5; x == 5}");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+    let s = interpret_source("{let x = 5; (x == 4) = false}");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    // Only a single '=' binds a name.
+    assert!(parser.parse("let x == 5").is_err());
+    assert!(parser.parse("{let x: Int == 5; x}").is_err());
+}
+
+#[test]
+fn test_where_bindings() {
+    let src = "{
+        function sqrt_of(n: Int): Int { if n = 25 { 5 } else { 0 } };
+        let hypotenuse = sqrt_of(a * a + b * b) where a = 3, b = a + 1;
+        hypotenuse
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(5)));
+
+    // As a statement of its own, with a function value as the helper.
+    let src = "{
+        twice(x) + 1 where twice = Lambda (n: Int): Int { n * 2 }, x = 20
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(41)));
+
+    // The bindings are only visible to the expression before the 'where'.
+    assert!(prepare_source("{let y = a + 1 where a = 1; a}").is_err());
+    let Err(err) = prepare_source("{let y: Int = a where a = 'text'; y}") else {
+        panic!("a Str where an Int is declared should fail the type check");
+    };
+    assert!(err[0].to_string().contains("Can't initialize 'y'"));
+}
+
+#[test]
+fn test_tap() {
+    let (root_expr, mut symbols) =
+        prepare_source("{let xs = [3, 1, 2].sort().tap(); sum(tap(xs)) |> tap}").unwrap();
+    symbols.host.capture_output();
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Int(6)));
+    assert_eq!("[1, 2, 3] \n[1, 2, 3] \n6 \n", symbols.host.take_output());
+
+    // It has the type of its argument.
+    let Err(err) = prepare_source("{let n: Int = tap('five'); n}") else {
+        panic!("tap() of a Str should be a Str");
+    };
+    assert!(err[0].to_string().contains("Can't initialize 'n'"));
+
+    // output() prints the same way.
+    let (root_expr, mut symbols) = prepare_source("output(1, 'a')").unwrap();
+    symbols.host.capture_output();
+    root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!("1 'a' \n", symbols.host.take_output());
+}
+
+#[test]
+fn test_division_by_zero() {
+    let Err(err) = prepare_source("{let x = 7;\nlet y = x + x / 0;\ny}") else {
+        panic!("dividing by a literal zero should fail the type check");
+    };
+    assert!(err[0].to_string().contains("Division by zero"));
+    let span = err[0].span();
+    assert_eq!((2, 13), span.start);
+    assert_eq!((2, 18), span.end);
+
+    // A zero that's computed is a runtime error.
+    let (root_expr, mut symbols) = prepare_source("{let zero = 1 - 1; 7 / zero}").unwrap();
+    let err = root_expr.interpret(&mut symbols, 0).unwrap_err();
+    assert!(err.to_string().contains("Division by zero"));
+
+    // Floats divide by zero as IEEE says to, whichever way the zero is spelled.
+    for src in ["5.0 / 0", "5.0 / 0.0", "{let z = 0.0; 5.0 / z}"] {
+        let s = interpret_source(src);
+        assert!(check_value(&s, LiteralData::Flt(f64::INFINITY)), "{}", src);
+    }
+
+    let s = interpret_source("7 / 2");
+    assert!(check_value(&s, LiteralData::Int(3)));
+}
+
+#[test]
+fn test_expression_file() {
+    // A file that's one expression, with no ';' after it, prints the expression's value.
+    for code in ["1 + 1", "1 + 1\n", "\n1 +\n  1\n"] {
+        let mut symbols = SymbolTable::new();
+        symbols.host.capture_output();
+        interpret_code(code, &mut symbols).unwrap();
+        assert_eq!("2\n", symbols.host.take_output());
+    }
+}
+
+#[test]
+fn test_explain_error_codes() {
+    let explanation = explain("E0002").unwrap();
+    assert!(explanation.starts_with("E0002: A value's type doesn't fit"));
+    assert!(explain("E9999")
+        .unwrap_err()
+        .contains("No error has the code 'E9999'"));
+    assert!(explain("name").is_err());
+
+    // Each explanation's failing example gives the error it explains, and the code shows
+    // in the message.
+    for (code, src) in [
+        ("E0001", "{let total = count + 1; let count = 2; total}"),
+        ("E0002", "{let n: Int = 'three'; n}"),
+        ("E0003", "sort([3, 1], [2])"),
+    ] {
+        let Err(err) = prepare_source(src) else {
+            panic!("'{}' should fail analysis", src);
+        };
+        assert!(
+            err[0].to_string().contains(&format!("[{}]", code)),
+            "{}",
+            err[0]
+        );
+        assert!(explain(code).unwrap().contains(src));
+    }
+}
+
+#[test]
+fn test_constant_conditions_prune_branches() {
+    let (root_expr, mut symbols) = prepare_source("if true { 1 } else { 2 }").unwrap();
+    assert!(matches!(root_expr, Expr::Block { .. }));
+    assert_eq!(
+        "1",
+        root_expr.interpret(&mut symbols, 0).unwrap().to_string()
+    );
+
+    let (root_expr, mut symbols) = prepare_source("if false { 1 } else { 2 }").unwrap();
+    assert!(matches!(root_expr, Expr::Block { .. }));
+    assert_eq!(
+        "2",
+        root_expr.interpret(&mut symbols, 0).unwrap().to_string()
+    );
+
+    // Inside function bodies too, and conditions known only at runtime stay.
+    let src = "{
+        function pick(n: Int): Int { if false { 0 } else { if n > 1 { n } else { 1 } } };
+        pick(5) + pick(0)
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(6)));
+
+    // The branch that never runs is still checked.
+    let Err(err) = prepare_source("if true { 1 } else { let m: Int = 'a'; m }") else {
+        panic!("the dead branch should still fail the type check");
+    };
+    assert!(err[0].to_string().contains("Can't initialize 'm'"));
+    assert!(prepare_source("if true { 1 } else { missing }").is_err());
+}
+
+#[test]
+fn test_type_redefinition() {
+    let src = "{\n    type Point = struct(x: Int, y: Int);\n    type Point = Int;\n    1\n}";
+    let Err(err) = prepare_source(src) else {
+        panic!("redefining a type should be a name error");
+    };
+    assert_eq!(
+        "Name Error [E0001]: 3, 5 to 3, 21: Type already defined in this scope: Point (first defined at 2, 5)",
+        err[0].to_string().lines().next().unwrap()
+    );
+
+    let src = "{
+        type Point = struct(x: Int, y: Int);
+        type Size = struct(w: Int);
+        let p = Point(x: 1, y: 2);
+        let s = Size(w: 3);
+        p.x + s.w
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(4)));
+}
+
+#[test]
+fn test_recursive_types() {
+    let src = "{
+        type Tree = struct(value: Int, left: Optional Tree, right: Optional Tree);
+        function leaf(v: Int): Tree { Tree(value: v, left: none(), right: none()) };
+        function total(t: Optional Tree): Int {
+            if is_some(t) {
+                let node = unwrap(t);
+                node.value + total(node.left) + total(node.right)
+            } else {
+                0
+            }
+        };
+        let root = Tree(value: 1, left: some(leaf(2)), right: some(Tree(value: 3, left: some(leaf(4)), right: none())));
+        total(some(root))
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(10)));
+
+    // Through a list, and comparing such values doesn't chase the type forever.
+    let src = "{
+        type Node = struct(value: Int, children: List of Node);
+        let a = Node(value: 1, children: [Node(value: 2, children: [])]);
+        a = a
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    // Without an Optional or List in between, a value of the type could never be built.
+    let Err(errors) = prepare_source("{type N = struct(next: N); 1}") else {
+        panic!("a struct holding itself directly should be rejected");
+    };
+    assert!(errors[0].to_string().contains("'N' contains itself"));
+    let src = "{type A = struct(b: B); type B = struct(value: Int, a: A); 1}";
+    assert!(prepare_source(src).is_err());
+    let src = "{type A = struct(b: B); type B = struct(value: Int, a: Optional A); 1}";
+    assert!(prepare_source(src).is_ok());
+
+    let (root_expr, mut symbols) = prepare_source("unwrap(parse_int('x'))").unwrap();
+    let Err(err) = root_expr.interpret(&mut symbols, 0) else {
+        panic!("unwrap() of none should fail");
+    };
+    assert!(err.to_string().contains("unwrap() of none"));
+}
+
+#[test]
+fn test_printing_large_values() {
+    let int_list = |data: Vec<Expr>| Expr::RuntimeList {
+        data_type: DataType::Int,
+        data,
+    };
+    let long = int_list(
+        (0..100_000)
+            .map(|i| Expr::Literal(LiteralData::Int(i)))
+            .collect(),
+    );
+    let printed = long.to_string();
+    assert!(printed.starts_with("[0, 1, 2, "));
+    assert!(printed.ends_with(", 98, 99, ...]"));
+
+    let mut deep = int_list(vec![]);
+    for _ in 0..100 {
+        deep = int_list(vec![deep]);
+    }
+    let printed = deep.to_string();
+    assert_eq!(format!("{}...{}", "[".repeat(20), "]".repeat(20)), printed);
+
+    // Small values print in full.
+    let s = interpret_source("[[1, 2], [3]]");
+    assert_eq!("[[1, 2], [3]]", s.unwrap().to_string());
+}
+
+#[test]
+fn test_max_errors() {
+    // Every statement gets checked, so each bad one is its own error.
+    let statements: Vec<String> = (0..40).map(|i| format!("let v{}: Int = 'x'", i)).collect();
+    let src = format!("{{ {}; 1 }}", statements.join("; "));
+    let mut symbols = SymbolTable::new();
+    let mut root_expr = grammar::ProgramPartExprParser::new().parse(&src).unwrap();
+    let mut errors = root_expr.prepare(&mut symbols).unwrap_err();
+    assert_eq!(25, errors.len());
+    assert!(errors[24].to_string().contains("Can't initialize 'v24'"));
+    assert_eq!(
+        Some("... and 15 more.".to_string()),
+        semantic_analysis::omitted_errors_summary(symbols.omitted_errors)
+    );
+
+    // Errors inside nested blocks are collected too, in source order.
+    let src = "{ let a: Int = 'x'; if true { let b: Int = 'y'; let c: Int = 'z'; 1 } else { 2 }; let d: Int = 'w'; a }";
+    let Err(nested) = prepare_source(src) else {
+        panic!("four bad lets should fail the type check");
+    };
+    assert_eq!(4, nested.len());
+    for (error, name) in nested.iter().zip(["'a'", "'b'", "'c'", "'d'"]) {
+        assert!(
+            error.to_string().contains(name),
+            "{} should be about {}",
+            error,
+            name
+        );
+    }
+
+    // Under the cap nothing is left out, and a cap of zero still keeps one error.
+    assert_eq!(0, semantic_analysis::cap_errors(&mut errors, 30));
+    assert_eq!(None, semantic_analysis::omitted_errors_summary(0));
+    assert_eq!(24, semantic_analysis::cap_errors(&mut errors, 0));
+    assert_eq!(1, errors.len());
+
+    let mut symbols = SymbolTable::new();
+    symbols.max_errors = 1;
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut root_expr = parser.parse("{ let a: Int = 'x'; a }").unwrap();
+    assert_eq!(1, root_expr.prepare(&mut symbols).unwrap_err().len());
+    assert_eq!(0, symbols.omitted_errors);
+}
+
+#[test]
+fn test_alias_in_type_errors() {
+    let Err(err) = prepare_source("{ type Celsius = Int; let t: Celsius = 'hot'; t }") else {
+        panic!("a Str shouldn't initialize a Celsius");
+    };
+    assert!(err[0]
+        .to_string()
+        .contains("Can't initialize 't' of type 'Celsius' (= Int) with a value of type 'Str'"));
+
+    let src = "{
+        type Celsius = Int;
+        function warm(t: Celsius): Celsius { t };
+        warm('hot')
+    }";
+    let Err(err) = prepare_source(src) else {
+        panic!("a Str shouldn't be passed as a Celsius");
+    };
+    assert!(err[0]
+        .to_string()
+        .contains("Argument 1 of 'warm' should be 'Celsius' (= Int), got 'Str'"));
+
+    // Struct types are only named.
+    let src = "{ type Point = struct(x: Int); let p: Point = 1; p }";
+    let Err(err) = prepare_source(src) else {
+        panic!("an Int shouldn't initialize a Point");
+    };
+    assert!(err[0].to_string().contains("of type 'Point' with"));
+}
+
+#[test]
+fn test_newtypes() {
+    // A plain alias mixes freely with what it stands for.
+    let s = interpret_source("{ type Size = Int; let s: Size = 3; s + 1 }");
+    assert!(check_value(&s, LiteralData::Int(4)));
+
+    let Err(err) = prepare_source("{ type Celsius = new Int; let t = 20 as Celsius; t + 5 }")
+    else {
+        panic!("a Celsius shouldn't add to an Int");
+    };
+    assert!(err[0]
+        .to_string()
+        .contains("Can't do arithmetic on 'Celsius' and 'Int'"));
+    let Err(err) = prepare_source("{ type Celsius = new Int; let t: Celsius = 20; t }") else {
+        panic!("an Int shouldn't initialize a Celsius");
+    };
+    assert!(err[0].to_string().contains("of type 'Celsius' with"));
+    let src = "{ type Celsius = new Int; type Fahrenheit = new Int; (1 as Celsius) as Fahrenheit }";
+    assert!(prepare_source(src).is_err());
+
+    let src = "{
+        type Celsius = new Int;
+        let t: Celsius = 20 as Celsius;
+        let warmer = t + (5 as Celsius);
+        if warmer > t { warmer as Int + 1 } else { 0 }
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(26)));
+}
+
+#[test]
+fn test_ast_equality_and_hashing() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    let hash_of = |e: &Expr| {
+        let mut hasher = DefaultHasher::new();
+        e.hash(&mut hasher);
+        hasher.finish()
+    };
+    let parser = grammar::ProgramPartExprParser::new();
+
+    // Different spellings of the same operator, and spacing outside any expression.
+    let first = parser.parse("{ let a = 2.5; a != 1.0 }").unwrap();
+    let second = parser.parse("{ let a = 2.5; a <> 1.0    }").unwrap();
+    assert_eq!(first, second);
+    assert_eq!(hash_of(&first), hash_of(&second));
+
+    let different = parser.parse("{ let a = 2.5; a != 1.5 }").unwrap();
+    assert_ne!(first, different);
+    let subtrees: std::collections::HashSet<Expr> = [first, second, different].into();
+    assert_eq!(2, subtrees.len());
+
+    // Floats are compared by their bits.
+    let nan = Expr::Literal(LiteralData::Flt(f64::NAN));
+    assert_eq!(nan, nan.clone());
+    assert_ne!(
+        Expr::Literal(LiteralData::Flt(0.0)),
+        Expr::Literal(LiteralData::Flt(-0.0))
+    );
+}
+
+#[test]
+fn test_restricted_builtins() {
+    let parser = grammar::ProgramPartExprParser::new();
+    let restricted = || {
+        let mut symbols = SymbolTable::new();
+        symbols.host.allow_system_access = false;
+        symbols
+    };
+    for src in [
+        "env('HOME')",
+        "random()",
+        "{ seed(1); 2 }",
+        "now()",
+        "args()",
+    ] {
+        let mut root_expr = parser.parse(src).unwrap();
+        let err = root_expr.prepare(&mut restricted()).unwrap_err();
+        assert!(
+            err[0]
+                .to_string()
+                .contains("disabled because system access is off"),
+            "{}",
+            src
+        );
+    }
+
+    // Pure computation is unaffected, and a program's own 'now' is fine.
+    let mut symbols = restricted();
+    let mut root_expr = parser
+        .parse("{ function now(): Int { 5 }; sum([now(), 2]) }")
+        .unwrap();
+    root_expr.prepare(&mut symbols).unwrap();
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Int(7)));
+}
+
+#[test]
+fn test_lazy_sequences() {
+    let src = "{
+        function double(x: Int): Int { x * 2 };
+        take(map(range(1, 1000000000), double), 5)
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("[2, 4, 6, 8, 10]", s.to_string());
+
+    // An endless sequence is fine as long as only part of it is asked for.
+    let src = "{
+        function next(n: Int): Int { n + 1 };
+        function even(n: Int): Bool { n / 2 * 2 = n };
+        let naturals: Seq of Int = iterate(1, next);
+        naturals.filter(even).take(3)
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("[2, 4, 6]", s.to_string());
+
+    let s = interpret_source("to_list(range(3, 5))").unwrap();
+    assert_eq!("[3, 4, 5]", s.to_string());
+    assert!(prepare_source("take(5, 1)").is_err());
+    assert!(prepare_source("{ function f(s: Str): Str { s }; map([1, 2], f) }").is_err());
+}
+
+#[test]
+fn test_operator_functions() {
+    let s = interpret_source("{ let xs = [1, 2, 3, 4]; reduce(xs, 0, (+)) }");
+    assert!(check_value(&s, LiteralData::Int(10)));
+    let s = interpret_source("reduce(range(1, 5), 1, (*))");
+    assert!(check_value(&s, LiteralData::Int(120)));
+    let s = interpret_source("{ let less = (<); less(1, 2) }");
+    assert!(check_value(&s, LiteralData::Bool(true)));
+
+    let src = "{
+        function count_positive(total: Int, n: Int): Int { if n > 0 { total + 1 } else { total } };
+        reduce([3, 0 - 1, 4], 0, count_positive)
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(2)));
+    assert!(prepare_source("{ function f(s: Str): Str { s }; reduce([1, 2], 0, f) }").is_err());
+}
+
+#[test]
+fn test_while_let() {
+    let src = "{
+        function below(n: Int, limit: Int): Optional Int {
+            if n < limit { some(n) } else { none() }
+        };
+        let n = 0;
+        let total = 0;
+        while let some(x) = below(n, 5) {
+            total := total + x;
+            n := n + 1
+        };
+        total
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(10)), s);
+
+    // Nothing to bind, so the body never runs.
+    let src = "{ let hits = 0; while let some(x) = parse_int('no') { hits := hits + 1 }; hits }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(0)), s);
+
+    assert!(prepare_source("while let some(x) = 5 { x }").is_err());
+    let parser = grammar::ProgramPartExprParser::new();
+    assert!(parser
+        .parse("while let ok(x) = parse_int('1') { x }")
+        .is_err());
+}
+
+#[test]
+fn test_for_in() {
+    // 'a to b' includes both ends.
+    let src = "{ let total = 0; for i in 1 to 4 { total := total + i }; total }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(10)), s);
+
+    let src = "{
+        let words = ['a', 'bb', 'ccc'];
+        let lengths = 0;
+        for word in words { lengths := lengths + len(word) };
+        let keys = 0;
+        for key in [1: 'one', 2: 'two'] { keys := keys + key };
+        [lengths, keys]
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("[6, 3]", s.to_string());
+
+    let (root_expr, mut symbols) = prepare_source("for i in 1 to 3 { output(i) }").unwrap();
+    symbols.host.capture_output();
+    root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!("1 \n2 \n3 \n", symbols.host.take_output());
+
+    // The variable only exists inside the loop.
+    assert!(prepare_source("{ for i in [1, 2] { output(i) }; i }").is_err());
+    assert!(prepare_source("for i in 5 { output(i) }").is_err());
+    assert!(prepare_source("for s in ['a'] { let n: Int = s; output(n) }").is_err());
+}
+
+#[test]
+fn test_break_and_continue() {
+    let src = "{ let i = 0; while true { if i = 5 { break } else { i := i + 1 } }; i }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(5)), s);
+    let Err(errors) = prepare_source("{ let a = 1; while a { break }; a }") else {
+        panic!("a while on an Int should fail the type check");
+    };
+    assert!(errors[0]
+        .to_string()
+        .contains("A condition must be a Bool, got 'Int'"));
+
+    let src = "{
+        let odd = 0;
+        for i in 1 to 10 { if i % 2 = 0 { continue } else { odd := odd + i } };
+        odd
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(25)), s);
+
+    // 'continue' still counts the pass, or moves on to the next item.
+    let src = "{ let runs = 0; 4 times { runs := runs + 1; continue }; runs }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(4)), s);
+    let src = "{
+        function below(n: Int): Optional Int { if n < 5 { some(n) } else { none() } };
+        let n = 0;
+        let total = 0;
+        while let some(x) = below(n) {
+            n := n + 1;
+            if x = 2 { continue } else { total := total + x }
+        };
+        total
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(8)), s);
+
+    // Only the innermost loop stops.
+    let src = "{ let runs = 0; 3 times { 10 times { runs := runs + 1; break } }; runs }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(3)), s);
+
+    let Err(errors) = prepare_source("{ let i = 0; break; i }") else {
+        panic!()
+    };
+    assert!(errors[0]
+        .to_string()
+        .contains("'break' can only be used inside a loop"));
+    let src = "2 times { function f(): Any { continue }; f() }";
+    assert!(prepare_source(src).is_err());
+}
+
+#[test]
+fn test_times() {
+    let src = "{ let total = 0; 5 times { total := total + 2 }; total }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(10)), s);
+
+    // The count is worked out once, and nested loops keep their own counts.
+    let src = "{
+        let n = 3;
+        let pairs = 0;
+        n times { n := n + 1; 2 times { pairs := pairs + 1 } };
+        pairs
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(6)), s);
+
+    let src = "{ let runs = 0; (0 - 2) times { runs := runs + 1 }; runs }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(0)), s);
+
+    assert!(prepare_source("{ 'five' times { 1 } }").is_err());
+}
+
+#[test]
+fn test_output_buffering() {
+    let src = "{ output(1); tap(2); flush(); output(3); 4 }";
+    let (root_expr, mut symbols) = prepare_source(src).unwrap();
+    symbols.host.capture_output();
+    symbols.host.buffering = Buffering::Full;
+    // Nothing's written until flush() runs, and the rest once the program ends.
+    root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!("1 \n2 \n", symbols.host.take_output());
+    symbols.host.flush();
+    assert_eq!("3 \n", symbols.host.take_output());
+
+    let (root_expr, mut symbols) = prepare_source("{ output(1); output(2); 3 }").unwrap();
+    symbols.host.capture_output();
+    symbols.host.buffering = Buffering::Full;
+    root_expr.interpret_program(&mut symbols).unwrap();
+    assert_eq!("1 \n2 \n", symbols.host.take_output());
+
+    // A failing program still gets out what it printed before it failed.
+    let src = "{ output(1); let zero = 0; output(1 / zero) }";
+    let (root_expr, mut symbols) = prepare_source(src).unwrap();
+    symbols.host.capture_output();
+    symbols.host.buffering = Buffering::Full;
+    assert!(root_expr.interpret_program(&mut symbols).is_err());
+    assert_eq!("1 \n", symbols.host.take_output());
+
+    // Line buffering holds a partial line until it's finished.
+    let mut host = lift_lang::host::Host::new();
+    host.capture_output();
+    host.print("a");
+    assert_eq!("", host.take_output());
+    host.print("b\nc");
+    assert_eq!("ab\n", host.take_output());
+    host.flush();
+    assert_eq!("c", host.take_output());
+
+    assert!(prepare_source("flush(1)").is_err());
+}
+
+#[test]
+fn test_pretty_printing() {
+    let (root_expr, mut symbols) =
+        prepare_source("output([[1, 2], [3]], ['a': 1, 'b': 2], to_set([1, 2]))").unwrap();
+    symbols.host.capture_output();
+    root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!(
+        "[[1, 2], [3]] {'a': 1, 'b': 2} {1, 2} \n",
+        symbols.host.take_output()
+    );
+
+    // Too wide for one line, so each row gets its own.
+    let row = "[1111111111, 2222222222, 3333333333]";
+    let src = format!("[{}]", [row; 4].join(", "));
+    let s = interpret_source(&src).unwrap();
+    let expected = format!("[\n  {},\n  {},\n  {},\n  {}\n]", row, row, row, row);
+    assert_eq!(expected, s.pretty());
+
+    let src = format!(
+        "['a': [{}], 'b': [{}], 'c': [{}, {}, {}]]",
+        row, row, row, row, row
+    );
+    let s = interpret_source(&src).unwrap();
+    let expected = format!(
+        "{{\n  'a': [{}],\n  'b': [{}],\n  'c': [\n    {},\n    {},\n    {}\n  ]\n}}",
+        row, row, row, row, row
+    );
+    assert_eq!(expected, s.pretty());
+
+    // output() goes through the same formatting.
+    let (root_expr, mut symbols) = prepare_source(&format!("output({})", src)).unwrap();
+    symbols.host.capture_output();
+    root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!(format!("{} \n", expected), symbols.host.take_output());
+}
+
+#[test]
+fn test_comprehensions() {
+    let s = interpret_source("[x * 2 for x in 1 to 10 if x > 3]").unwrap();
+    assert_eq!("[8, 10, 12, 14, 16, 18, 20]", s.to_string());
+
+    let src = "{
+        function lengths(names: List of Str): Map of Str to Int {
+            {name: len(name) for name in names if name <> 'bo'}
+        };
+        lengths(['ann', 'bo', 'cyd'])
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("{'ann': 3, 'cyd': 3}", s.to_string());
+
+    // The variable is only visible inside the comprehension, where it hides any outer one.
+    let s = interpret_source("{let x = 10; let ys = [x + 1 for x in [1, 2]]; sum(ys) + x}");
+    assert!(check_value(&s, LiteralData::Int(15)));
+    assert!(prepare_source("{let ys = [x for x in [1, 2]]; x}").is_err());
+
+    let Err(err) = prepare_source("[x for x in [1, 2] if x + 1]") else {
+        panic!("a filter has to be Bool");
+    };
+    assert!(err[0].to_string().contains("must be Bool"));
+    assert!(prepare_source("{let ys: List of Str = [x * 2 for x in [1, 2]]; ys}").is_err());
+    assert!(prepare_source("[x for x in 5]").is_err());
+}
+
+#[test]
+fn test_main_function() {
+    use lift_lang::value::Value;
+
+    // main() runs after the top level has set up what it uses, and gives the result.
+    let src = "{
+        function helper(n: Int): Int { n * 2 };
+        let base = 1;
+        function main(): Int { helper(base) + 1 };
+        100
+    }";
+    assert_eq!(Value::Int(3), lift_lang::run_str(src).unwrap());
+    let (root_expr, mut symbols) = prepare_source(src).unwrap();
+    assert!(root_expr.main_function().is_some());
+    let s = root_expr.interpret_program(&mut symbols);
+    assert!(check_value(&s, LiteralData::Int(3)));
+
+    // Without one, the top level's last value is the result as before.
+    let src = "{ function helper(n: Int): Int { n * 2 }; helper(50) }";
+    assert_eq!(Value::Int(100), lift_lang::run_str(src).unwrap());
+    let (root_expr, _) = prepare_source(src).unwrap();
+    assert!(root_expr.main_function().is_none());
+
+    let Err(err) = lift_lang::run_str("{ function main(code: Int): Int { code } }") else {
+        panic!("main() can't take arguments");
+    };
+    assert!(err.to_string().contains("function main(): Int"));
+    assert!(lift_lang::run_str("{ function main(): Str { 'done' } }").is_err());
+}
+
+#[test]
+fn test_run_tests() {
+    let src = "{
+        function double(n: Int): Int { n * 2 };
+        function test_double(): Any { assert_type(double(2), Int) };
+        function test_parse(): Any { unwrap(parse_int('two')) + 1 };
+        function test_with_argument(n: Int): Int { n }
+    }";
+    let mut symbols = SymbolTable::new();
+    symbols.host.capture_output();
+    assert!(!run_tests(src, &mut symbols).unwrap());
+    let output = symbols.host.take_output();
+    assert!(output.starts_with("test test_double ... ok\ntest test_parse ... FAILED\n"));
+    assert!(output.contains("unwrap() of none"));
+    // Only functions without parameters are tests.
+    assert!(!output.contains("test_with_argument"));
+    assert!(output.ends_with("1 passed; 1 failed\n"));
+
+    let mut symbols = SymbolTable::new();
+    symbols.host.capture_output();
+    assert!(run_tests("{ function test_one(): Int { 1 } }", &mut symbols).unwrap());
+    assert_eq!(
+        "test test_one ... ok\n1 passed; 0 failed\n",
+        symbols.host.take_output()
+    );
+}
+
+#[test]
+fn test_imports() {
+    let dir = std::env::temp_dir().join(format!("lift-imports-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(
+        dir.join("lib/math.lt"),
+        "{ function square(x: Int): Int { x * x }; let unit = 1 }",
+    )
+    .unwrap();
+    fs::write(dir.join("a.lt"), "{ import 'b.lt' }").unwrap();
+    fs::write(dir.join("b.lt"), "{ import 'a.lt' }").unwrap();
+    let run = |src: &str| interpret_in_dir(&dir, src);
+
+    // Paths are relative to the importing file, and the module's names come in directly
+    // or under a namespace.
+    let s = run("{ import 'lib/math.lt'; square(3) + unit }");
+    assert_eq!(Expr::Literal(LiteralData::Int(10)), s.unwrap());
+    let s = run("{ import 'lib/math.lt' as math; math.square(4) + math.unit }");
+    assert_eq!(Expr::Literal(LiteralData::Int(17)), s.unwrap());
+
+    // Behind a namespace, they're only reachable through it.
+    let err = run("{ import 'lib/math.lt' as math; square(2) }").unwrap_err();
+    assert!(err.contains("'square'"));
+
+    let err = run("{ import 'a.lt' }").unwrap_err();
+    assert!(err.contains("Import cycle: a.lt imports b.lt imports a.lt"));
+    let err = run("{ import 'missing.lt' }").unwrap_err();
+    assert!(err.contains("Can't import 'missing.lt'"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_qualified_names() {
+    let dir = std::env::temp_dir().join(format!("lift-qualified-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("circles.lt"),
+        "{ let pi = 3; function area(r: Int): Int { pi * r * r } }",
+    )
+    .unwrap();
+    fs::write(dir.join("rough.lt"), "{ let pi = 4 }").unwrap();
+    let run = |src: &str| interpret_in_dir(&dir, src);
+    let imports = "import 'circles.lt' as circles; import 'rough.lt' as rough";
+
+    // Each module's 'pi' is its own.
+    let s = run(&format!("{{ {}; circles.pi * 10 + rough.pi }}", imports));
+    assert_eq!(Expr::Literal(LiteralData::Int(34)), s.unwrap());
+    let s = run(&format!("{{ {}; circles.area(2) }}", imports));
+    assert_eq!(Expr::Literal(LiteralData::Int(12)), s.unwrap());
+
+    let err = run(&format!("{{ {}; rough.area(2) }}", imports)).unwrap_err();
+    assert!(err.contains("Name Error"));
+    assert!(err.contains("Module 'rough' has no 'area'"));
+    let err = run(&format!("{{ {}; rough.tau }}", imports)).unwrap_err();
+    assert!(err.contains("Module 'rough' has no 'tau'"));
+    let err = run(&format!("{{ {}; exact.pi }}", imports)).unwrap_err();
+    assert!(err.contains("No variable or imported module named 'exact'"));
+    let err = run(&format!("{{ {}; exact.area(1) }}", imports)).unwrap_err();
+    assert!(err.contains("No variable or imported module named 'exact'"));
+    // Only the left side of a '.' is taken for a module.
+    let err = run(&format!("{{ {}; area(exact) }}", imports)).unwrap_err();
+    assert!(err.contains("variable 'exact'"));
+    assert!(!err.contains("module"));
+    let err = run("{ foo(bar) }").unwrap_err();
+    assert!(err.contains("variable 'bar'"));
+    assert!(!err.contains("module"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tokenize() {
+    use lift_lang::tokens::{tokenize, TokenKind};
+
+    let tokens = tokenize("let x = 1;").unwrap();
+    assert_eq!(
+        vec![
+            (0, TokenKind::Keyword, 3),
+            (4, TokenKind::Identifier, 5),
+            (6, TokenKind::Operator, 7),
+            (8, TokenKind::Int, 9),
+            (9, TokenKind::Punctuation, 10),
+        ],
+        tokens
+    );
+
+    // Source that wouldn't parse still lexes.
+    let kinds: Vec<TokenKind> = tokenize("List of Str 'a' 2.5 true )")
+        .unwrap()
+        .into_iter()
+        .map(|(_, kind, _)| kind)
+        .collect();
+    assert_eq!(
+        vec![
+            TokenKind::TypeName,
+            TokenKind::TypeName,
+            TokenKind::Str,
+            TokenKind::Flt,
+            TokenKind::Bool,
+            TokenKind::Punctuation,
+        ],
+        kinds
+    );
+
+    assert_eq!(8, tokenize("let x = #").unwrap_err().offset);
+}
+
+#[test]
+fn test_unicode_identifiers() {
+    let src = "{
+        let café = 2;
+        function größe(x: Int): Int { x * café };
+        let 名前 = größe(3);
+        名前 + 1
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Int(7)), s);
+
+    // Offsets are in bytes, so 'é' takes two.
+    use lift_lang::tokens::{tokenize, TokenKind};
+    assert_eq!(
+        vec![(0, TokenKind::Identifier, 5), (6, TokenKind::Operator, 7)],
+        tokenize("café +").unwrap()
+    );
+    // Names still can't start with a digit or a mark, only a letter.
+    assert!(tokenize("\u{301}x").is_err());
+}
+
+#[test]
+fn test_modulo() {
+    let s = interpret_source("7 % 3");
+    assert!(check_value(&s, LiteralData::Int(1)));
+    // Binds like '*', and the remainder takes the sign of the left side.
+    let s = interpret_source("1 + 7 % 3 * 2");
+    assert!(check_value(&s, LiteralData::Int(3)));
+    let s = interpret_source("(0 - 7) % 3");
+    assert!(check_value(&s, LiteralData::Int(-1)));
+    let s = interpret_source("{ function even(n: Int): Bool { n % 2 = 0 }; [even(4), even(7)] }");
+    assert_eq!("[true, false]", s.unwrap().to_string());
+
+    assert!(prepare_source("7.0 % 2.0").is_err());
+    assert!(prepare_source("7 % 2.0").is_err());
+    assert!(prepare_source("7 % 0").is_err());
+    assert!(interpret_source("{ let zero = 0; 7 % zero }").is_err());
+}
+
+#[test]
+fn test_wrapping_arithmetic() {
+    let s = interpret_source("9223372036854775807 +% 1");
+    assert!(check_value(&s, LiteralData::Int(i64::MIN)));
+    let s = interpret_source("{ let big = 4611686018427387904; big *% 4 }");
+    assert!(check_value(&s, LiteralData::Int(0)));
+    let s = interpret_source("2 +% 3 *% 4");
+    assert!(check_value(&s, LiteralData::Int(14)));
+
+    let err = interpret_source("9223372036854775807 + 1").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Integer overflow in 9223372036854775807 + 1"));
+    let err = interpret_source("{ let small = 0 - 9223372036854775807; small - 2 }").unwrap_err();
+    assert!(err.to_string().contains("Integer overflow"));
+    let err = interpret_source("4611686018427387904 * 2").unwrap_err();
+    assert!(err.to_string().contains("Integer overflow"));
+
+    assert!(prepare_source("1.5 +% 2.0").is_err());
+}
+
+#[test]
+fn test_coalesce() {
+    let src = "{
+        let found = some(3);
+        found ?: 10
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(3)));
+
+    // The fallback only runs when it's needed.
+    let src = "{
+        let calls = 0;
+        function fallback(): Int { calls := calls + 1; 10 };
+        let missing: Optional Int = none();
+        let a = some(1) ?: fallback();
+        let b = missing ?: fallback();
+        [a, b, calls]
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("[1, 10, 1]", s.pretty());
+
+    let s = interpret_source("{ let m: Optional Int = none(); m ?: none() ?: some(4) ?: 5 }");
+    assert!(check_value(&s, LiteralData::Int(4)));
+    let s = interpret_source("{ let m: Optional Int = none(); m ?: some(2) }").unwrap();
+    assert_eq!("some(2)", s.pretty());
+
+    assert!(prepare_source("3 ?: 4").is_err());
+    assert!(prepare_source("some(3) ?: 'x'").is_err());
+}
+
+#[test]
+fn test_optional_chaining() {
+    let src = "{
+        type Point = struct (x: Int, y: Int);
+        type Shape = struct (name: Str, center: Optional Point);
+        type Scene = struct (focus: Optional Shape);
+        let near = Point(x: 1, y: 2);
+        let full: Optional Scene = some(Scene(focus: some(Shape(name: 'dot', center: some(near)))));
+        let no_center: Optional Scene = some(Scene(focus: some(Shape(name: 'blank', center: none()))));
+        let no_focus: Optional Scene = some(Scene(focus: none()));
+        let nothing: Optional Scene = none();
+        [full?.focus?.center?.x, no_center?.focus?.center?.x,
+         no_focus?.focus?.center?.x, nothing?.focus?.center?.x]
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("[some(1), none, none, none]", s.pretty());
+
+    let src = "{
+        type Point = struct (x: Int, y: Int);
+        let p: Optional Point = some(Point(x: 3, y: 4));
+        p?.y ?: 0
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(4)));
+
+    let src = "{ type Point = struct (x: Int, y: Int); Point(x: 1, y: 2)?.x }";
+    assert!(prepare_source(src).is_err());
+    let src = "{ type Point = struct (x: Int, y: Int); let p = some(Point(x: 1, y: 2)); p?.z }";
+    assert!(prepare_source(src).is_err());
+}
+
+#[test]
+fn test_contextual_number_literals() {
+    let s = interpret_source("{ let x: Flt = 5; x }").unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Flt(5.0)), s);
+    let s = interpret_source("{ let x: Flt = 5; x / 2.0 }");
+    assert!(check_value(&s, LiteralData::Flt(2.5)));
+
+    let (mut root_expr, mut symbols) = prepare_source("[1.0, 2]").unwrap();
+    assert_eq!(
+        DataType::List {
+            element_type: Box::new(DataType::Flt)
+        },
+        semantic_analysis::typecheck(&mut root_expr, &mut symbols, 0).unwrap()
+    );
+    let s = interpret_source("[2, 1.5]").unwrap();
+    let Expr::RuntimeList { data, .. } = s else {
+        panic!("should be a list: {}", s);
+    };
+    assert_eq!(Expr::Literal(LiteralData::Flt(2.0)), data[0]);
+
+    let s = interpret_source("{ let xs: List of Flt = [1, 2]; xs }").unwrap();
+    let Expr::RuntimeList { data, .. } = s else {
+        panic!("should be a list: {}", s);
+    };
+    assert_eq!(Expr::Literal(LiteralData::Flt(1.0)), data[0]);
+
+    // Only literals adapt; a computed Int is still the wrong type.
+    assert!(prepare_source("{ let n = 5; let x: Flt = n; x }").is_err());
+}
+
+#[test]
+fn test_recursive_let_lambda() {
+    let src = "{
+        let factorial = Lambda (n: Int): Int { if n < 2 { 1 } else { n * factorial(n - 1) } };
+        factorial(5)
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(120)));
+
+    // Also inside a function, where the lambda is a local.
+    let src = "{
+        function count_down(from: Int): Int {
+            let steps = Lambda (n: Int): Int { if n = 0 { 0 } else { 1 + steps(n - 1) } };
+            steps(from)
+        };
+        count_down(7)
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(7)));
+
+    // Other values still can't refer to the name they're being bound to.
+    assert!(prepare_source("{ let x = x + 1; x }").is_err());
+}
+
+#[test]
+fn test_definition_order() {
+    // Functions and types can be used before they're defined in a block.
+    let src = "{
+        let a = double(4);
+        function double(n: Int): Int { n * 2 };
+        a
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(8)));
+    let src = "{
+        function is_even(n: Int): Bool { if n = 0 { true } else { is_odd(n - 1) } };
+        function is_odd(n: Int): Bool { if n = 0 { false } else { is_even(n - 1) } };
+        is_even(10)
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Bool(true)));
+    let src = "{ let p = Point(x: 1, y: 2); type Point = struct (x: Int, y: Int); p.y }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(2)));
+
+    // A 'let' is only visible after it.
+    let Err(errors) = prepare_source("{ let a = b; let b = 1; a }") else {
+        panic!("'b' should be undefined where it's used");
+    };
+    assert!(errors[0].to_string().starts_with("Name Error"));
+    assert!(errors[0].to_string().contains("'b'"));
+    // So before its own 'let' a name still means the outer one.
+    let s = interpret_source("{ let b = 5; { let a = b; let b = 1; a } }");
+    assert!(check_value(&s, LiteralData::Int(5)));
+}
+
+#[test]
+fn test_unused_expression_warning() {
+    let src = "{
+        let total = 1;
+        total + 1;
+        output(total);
+        total := total + 2;
+        total
+    }";
+    let (_, symbols) = prepare_source(src).unwrap();
+    assert_eq!(1, symbols.warnings.len());
+    assert!(symbols.warnings[0]
+        .to_string()
+        .contains("Unused expression result of type 'Int'"));
+    let json = diagnostics(src, SymbolTable::new());
+    assert!(json.contains("\"severity\": \"warning\""));
+    assert!(json.contains("\"line\": 3, \"column\": 9"));
+    let mut warning = symbols.warnings[0].clone();
+    warning.locate(src);
+    assert!(semantic_analysis::Warning(&warning)
+        .to_string()
+        .starts_with("Warning [E0003]: 3, 9 to 3, 18: Unused expression result"));
+
+    // Functions that don't give anything back are called for their effect.
+    let src = "{
+        function show(n: Int): Any { output(n) };
+        show(1);
+        output(2);
+        3
+    }";
+    let (_, symbols) = prepare_source(src).unwrap();
+    assert!(symbols.warnings.is_empty());
+}
+
+#[test]
+fn test_float_printing() {
+    use lift_lang::syntax::format_flt;
+
+    assert_eq!("0.30000000000000004", format_flt(0.1 + 0.2));
+    assert_eq!("2.0", format_flt(2.0));
+    assert_eq!("-0.5", format_flt(-0.5));
+    assert_eq!("NaN", format_flt(f64::NAN));
+    assert_eq!("0.1", format_flt(0.1_f32 as f64));
+    let s = interpret_source("{ let x: F32 = 0.1; x }");
+    assert_eq!("0.1", s.unwrap().to_string());
+
+    // Printing, pretty printing and formatting into a string all agree.
+    let (root_expr, mut symbols) =
+        prepare_source("{ let x = 0.1 + 0.2; output(x, [x, 3.0]); format('{}', x) }").unwrap();
+    symbols.host.capture_output();
+    let s = root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!(
+        "0.30000000000000004 [0.30000000000000004, 3.0] \n",
+        symbols.host.take_output()
+    );
+    assert_eq!("'0.30000000000000004'", s.pretty());
+
+    // Whole numbers print as something that reads back as a Flt.
+    let parser = grammar::ProgramPartExprParser::new();
+    let printed = LiteralData::Flt(7.0).to_string();
+    assert_eq!(
+        Ok(Expr::Literal(LiteralData::Flt(7.0))),
+        parser.parse(&printed)
+    );
+}
+
+#[test]
+fn test_uninferable_types() {
+    // Without a declared type, sum() would have to guess whether 0 is an Int or a Flt.
+    let Err(errors) = prepare_source("{ let xs = []; sum(xs) }") else {
+        panic!("an empty list with no type should fail the type check");
+    };
+    assert!(errors[0]
+        .to_string()
+        .contains("Could not infer the type of 'xs' from an empty value"));
+    assert!(errors[0].to_string().contains("'let xs: List of Int = []'"));
+    assert!(prepare_source("{ let m = [:]; m }").is_err());
+    assert!(prepare_source("{ let m = none(); m ?: 3 }").is_err());
+
+    let s = interpret_source("{ let xs: List of Flt = []; sum(xs) }");
+    assert!(check_value(&s, LiteralData::Flt(0.0)));
+    let s = interpret_source("{ let m: Optional Int = none(); m ?: 3 }");
+    assert!(check_value(&s, LiteralData::Int(3)));
+}
+
+#[test]
+fn test_trace() {
+    let (root_expr, mut symbols) = prepare_source("{ let x = 2; output(x * 3) }").unwrap();
+    symbols.host.capture_output();
+    symbols.host.trace = true;
+    root_expr.interpret(&mut symbols, 0).unwrap();
+    let trace = symbols.host.take_output();
+    let steps = [
+        "3 | Literal => 2",
+        "2 | Let x => ",
+        "4 | Variable x => 2",
+        "3 | BinaryExpr Mul => 6",
+        "6 \n",
+        "2 | Output => ",
+        "1 | Block => ",
+    ];
+    let mut rest = trace.as_str();
+    for step in steps {
+        let Some(at) = rest.find(step) else {
+            panic!("'{}' should come next in the trace:\n{}", step, trace);
+        };
+        rest = &rest[at + step.len()..];
+    }
+
+    // Off by default.
+    let (root_expr, mut symbols) = prepare_source("{ let x = 2; x }").unwrap();
+    symbols.host.capture_output();
+    root_expr.interpret(&mut symbols, 0).unwrap();
+    assert_eq!("", symbols.host.take_output());
+}
+
+#[test]
+fn test_strict_mode() {
+    use semantic_analysis::Strictness;
+
+    let prepare_with = |src: &str, strictness: Strictness| {
+        let mut root_expr = grammar::ProgramPartExprParser::new().parse(src).unwrap();
+        let mut symbols = SymbolTable::new();
+        symbols.strictness = strictness;
+        root_expr
+            .prepare(&mut symbols)
+            .map(|_| (root_expr, symbols))
+    };
+    let programs = [
+        ("{ let half = 0.5; half * 3 }", "Mixing Flt and Int"),
+        (
+            "{ let unused = 1; let used = 2; used }",
+            "'unused' is never used",
+        ),
+        ("{ let n = 1; if true { n } else { 2 } }", "is always true"),
+        ("{ let n = 1; n + 1; n }", "Unused expression result"),
+    ];
+    for (src, problem) in programs {
+        assert!(prepare_with(src, Strictness::default()).is_ok(), "{}", src);
+        let Err(errors) = prepare_with(src, Strictness::strict()) else {
+            panic!("'{}' should fail in strict mode", src);
+        };
+        assert!(errors[0].to_string().contains(problem), "{}", errors[0]);
+    }
+
+    // Leniently, Int and Flt arithmetic gives a Flt.
+    let (root_expr, mut symbols) = prepare_with(programs[0].0, Strictness::default()).unwrap();
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Flt(1.5)));
+    // Assigning to a variable isn't reading it.
+    let src = "{ let total = 1; total := 2; 3 }";
+    assert!(prepare_with(src, Strictness::strict()).is_err());
+}
+
+#[test]
+fn test_default_value() {
+    for (src, expected) in [
+        ("default(Int)", "0"),
+        ("default(Flt)", "0.0"),
+        ("default(Str)", "''"),
+        ("default(Bool)", "false"),
+    ] {
+        assert_eq!(expected, interpret_source(src).unwrap().to_string());
+    }
+
+    let src = "{
+        let xs: List of Int = default(List of Int);
+        let counts = default(Map of Str to Int);
+        [len(xs), len(counts)]
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!("[0, 0]", s.to_string());
+
+    // Each field takes its own type's default, down through nested structs.
+    let src = "{
+        type Color = (red, green);
+        type Point = struct (x: Int, y: Int);
+        type Shape = struct (name: Str, corner: Point, color: Color, parent: Optional Shape);
+        let shape: Shape = default(Shape);
+        shape
+    }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(
+        "Shape(name: '', corner: Point(x: 0, y: 0), color: Color.red, parent: none)",
+        s.to_string()
+    );
+    let src = "{ type Point = struct (x: Int, y: Int); default(Point) = Point(x: 0, y: 0) }";
+    let s = interpret_source(src).unwrap();
+    assert_eq!(Expr::Literal(LiteralData::Bool(true)), s);
+
+    assert!(prepare_source("{ let s: Str = default(Int); s }").is_err());
+    assert!(prepare_source("{ type Loop = struct (next: Loop); default(Loop) }").is_err());
+    assert!(prepare_source("default(Any)").is_err());
+}
+
+#[test]
+fn test_assert_eq() {
+    assert!(
+        interpret_source("{ assert_eq([[1], [2, 3]], [[1], [2, 3]]); assert_eq(1, 1); 2 }").is_ok()
+    );
+
+    let err = interpret_source("assert_eq([1, 2, 3, 4], [1, 2, 5, 4])").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("assert_eq(): [1, 2, 3, 4] and [1, 2, 5, 4] differ at index 2: 3 vs 5"));
+
+    // The steps lead down through nested values to the first difference.
+    let src = "{
+        type Point = struct (x: Int, y: Int);
+        assert_eq(['a': [Point(x: 1, y: 2)]], ['a': [Point(x: 1, y: 7)]])
+    }";
+    let err = interpret_source(src).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("differ at key 'a', index 0, field 'y': 2 vs 7"));
+    let err = interpret_source("assert_eq([1, 2], [1, 2, 3])").unwrap_err();
+    assert!(err.to_string().contains("differ at index 2: nothing vs 3"));
+    let err = interpret_source("assert_eq('a', 'b')").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("assert_eq(): 'a' isn't equal to 'b'"));
+
+    assert!(prepare_source("assert_eq(1, 'one')").is_err());
+}
+
+#[test]
+fn test_assert_type() {
+    let src = "{
+        let x: Any = 41;
+        assert_type(x, Int) + 1
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(42)));
+    let src = "{
+        let xs: Any = [some(1), none()];
+        len(assert_type(xs, List of Optional Int))
+    }";
+    let s = interpret_source(src);
+    assert!(check_value(&s, LiteralData::Int(2)));
+
+    // The result has the asserted type, so it's checked like any other Int.
+    assert!(prepare_source("{ let x: Any = 1; let s: Str = x; s }").is_ok());
+    assert!(prepare_source("{ let x: Any = 1; let s: Str = assert_type(x, Int); s }").is_err());
+    // A value known not to match never could.
+    assert!(prepare_source("assert_type('a', Int)").is_err());
+
+    let err = interpret_source("{ let x: Any = 'seven'; assert_type(x, Int) }").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("assert_type(): 'seven' isn't of type Int"));
+    let src = "{ let xs: Any = [1, 2]; assert_type(xs, List of Str) }";
+    assert!(interpret_source(src).is_err());
+}
+
+#[test]
+fn test_repl_options() {
+    assert_eq!("3 ==> ", format_prompt(&ReplOptions::default().prompt, 3));
+    assert_eq!("lift[12]> ", format_prompt("lift[{n}]> ", 12));
+    assert_eq!("> ", format_prompt("> ", 12));
+
+    let flags = [
+        "--prompt=({n}) ",
+        "--no-banner",
+        "--history=/tmp/lift-history",
+    ]
+    .map(String::from);
+    let options = repl_options(&flags);
+    assert_eq!("(0) ", format_prompt(&options.prompt, 0));
+    assert!(!options.banner);
+    assert_eq!(
+        Some(Path::new("/tmp/lift-history")),
+        options.history_file.as_deref()
+    );
+    let options = repl_options(&["--no-history".to_string()]);
+    assert!(options.banner);
+    assert!(options.history_file.is_none());
+}
+
+// A builtin for test_register_builtin(): doubles an Int.
+#[cfg(test)]
+struct Twice;
+
+#[cfg(test)]
+impl lift_lang::builtins::Builtin for Twice {
+    fn name(&self) -> &str {
+        "twice"
+    }
+
+    fn signature(
+        &self,
+        _args: &[Expr],
+        arg_types: &[DataType],
+        _symbols: &SymbolTable,
+        _current_scope_id: usize,
+    ) -> Result<DataType, CompileError> {
+        match arg_types {
+            [DataType::Int] => Ok(DataType::Int),
+            _ => Err(CompileError::typecheck("twice() takes one Int", (0, 0))),
+        }
+    }
+
+    fn eval(&self, args: &[Expr], _symbols: &mut SymbolTable) -> InterpreterResult {
+        match args {
+            [Expr::Literal(LiteralData::Int(i))] => Ok(Expr::Literal(LiteralData::Int(i * 2))),
+            _ => panic!("twice() got {:?}", args),
+        }
+    }
+}
+
+#[test]
+fn test_register_builtin() {
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut symbols = SymbolTable::new();
+    symbols.builtins.register(Twice);
+    let mut root_expr = parser.parse("twice(sum([1, 2])) + 1").unwrap();
+    root_expr.prepare(&mut symbols).unwrap();
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Int(7)));
+
+    // Its signature is checked like any other builtin's.
+    let mut root_expr = parser.parse("twice('a')").unwrap();
+    let err = root_expr.prepare(&mut symbols).unwrap_err();
+    assert!(err[0].to_string().contains("twice() takes one Int"));
+
+    // Without registering it, the name is unknown.
+    let mut root_expr = parser.parse("twice(1)").unwrap();
+    assert!(root_expr.prepare(&mut SymbolTable::new()).is_err());
+}
+
+#[test]
+fn test_register_native() {
+    use lift_lang::value::Value;
+    let signature = |text| grammar::DataTypeParser::new().parse(text).unwrap();
+    let mut symbols = SymbolTable::new();
+    symbols.builtins.register_native(
+        "double",
+        signature("function(Int): Int"),
+        |args| match args {
+            [Value::Int(i)] => i.checked_mul(2).map(Value::Int).ok_or("overflow".into()),
+            _ => Err(format!("unexpected arguments {:?}", args)),
+        },
+    );
+    symbols.builtins.register_native(
+        "lengths",
+        signature("function(List of Str): List of Int"),
+        |args| match args {
+            [Value::List(words)] => Ok(Value::List(
+                words
+                    .iter()
+                    .map(|w| match w {
+                        Value::Str(s) => Value::Int(s.len() as i64),
+                        _ => Value::Int(0),
+                    })
+                    .collect(),
+            )),
+            _ => Err(format!("unexpected arguments {:?}", args)),
+        },
+    );
+
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut root_expr = parser
+        .parse("double(3) + sum(lengths(['ab', 'cde']))")
+        .unwrap();
+    root_expr.prepare(&mut symbols).unwrap();
+    let s = root_expr.interpret(&mut symbols, 0);
+    assert!(check_value(&s, LiteralData::Int(11)));
+
+    // The signature is what the typechecker goes by.
+    let mut root_expr = parser.parse("double('a')").unwrap();
+    let err = root_expr.prepare(&mut symbols).unwrap_err();
+    assert!(err[0]
+        .to_string()
+        .contains("Argument 1 of 'double' should be 'Int', got 'Str'"));
+
+    // An error from the closure is a runtime error.
+    let mut root_expr = parser.parse("double(4611686018427387904)").unwrap();
+    root_expr.prepare(&mut symbols).unwrap();
+    let err = root_expr.interpret(&mut symbols, 0).unwrap_err();
+    assert!(err.to_string().contains("double(): overflow"));
+}
+
+// A test helper: parse and analyze 'src', keeping the symbol table for interpreting.
+#[cfg(test)]
+fn prepare_source(src: &str) -> Result<(Expr, SymbolTable), Vec<semantic_analysis::CompileError>> {
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut root_expr = parser.parse(src).expect("test source should parse");
+    let mut symbols = SymbolTable::new();
+    root_expr.prepare(&mut symbols).map_err(|mut errors| {
+        for e in &mut errors {
+            e.locate(src);
+        }
+        errors
+    })?;
+    Ok((root_expr, symbols))
+}
+
+// Runs a program as the file 'main.lt' in 'dir' would run, for trying out imports. Gives
+// the first error as text.
+#[cfg(test)]
+fn interpret_in_dir(dir: &Path, src: &str) -> Result<Expr, String> {
+    let mut root_expr = grammar::ProgramPartExprParser::new().parse(src).unwrap();
+    let mut symbols = SymbolTable::new();
+    symbols.modules.start_from(&dir.join("main.lt"));
+    root_expr
+        .prepare(&mut symbols)
+        .map_err(|errors| errors[0].to_string())?;
+    root_expr
+        .interpret(&mut symbols, 0)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+fn interpret_source(src: &str) -> InterpreterResult {
+    let (root_expr, mut symbols) = prepare_source(src).expect("test source should pass analysis");
+    root_expr.interpret(&mut symbols, 0)
+}
+
+// A test helper
+#[cfg(test)]
+fn check_value(s: &InterpreterResult, value: LiteralData) -> bool {
+    if let Ok(ref e) = s {
+        return e.has_value(&value);
+    }
+    false
+}
+
+#[cfg(test)]
+fn extract_value(r: InterpreterResult) -> LiteralData {
+    if let Ok(Expr::Literal(l)) = r {
+        return l;
+    }
+    panic!("Must pass an interpreter result that holds a literal data value.");
+}
+
+// How the REPL looks and where it keeps its history.
+pub struct ReplOptions {
+    // What to show before each input; "{n}" stands for the number of inputs so far.
+    pub prompt: String,
+    pub banner: bool,
+    // Where history is loaded from and saved to, if anywhere.
+    pub history_file: Option<std::path::PathBuf>,
+}
+
+impl Default for ReplOptions {
+    fn default() -> Self {
+        ReplOptions {
+            prompt: "{n} ==> ".to_string(),
+            banner: true,
+            history_file: cfg!(feature = "with-file-history").then(|| "history.txt".into()),
+        }
+    }
+}
+
+fn format_prompt(template: &str, count: usize) -> String {
+    template.replace("{n}", &count.to_string())
+}
+
+// Analyzes and runs one REPL input, reporting what goes wrong. An input that fails at
+// either step leaves the symbol table as it found it, including any variables it had
+// already changed before failing.
+fn eval_repl_input(ast: &mut Expr, input: &str, symbols: &mut SymbolTable) -> Option<Expr> {
+    let before_input = symbols.snapshot();
+    let prepared = ast.prepare(symbols);
+    report_warnings(input, symbols);
+    if let Err(errors) = prepared {
+        report_errors(errors, input, symbols);
+        println!();
+        symbols.restore(before_input);
+        return None;
+    }
+    let result = ast.interpret(symbols, 0);
+    symbols.host.flush();
+    match result {
+        Err(mut interpreter_error) => {
+            locate_runtime_error(&mut interpreter_error, input);
+            eprintln!("{}", interpreter_error);
+            symbols.restore(before_input);
+            None
+        }
+        Ok(res) => Some(res),
+    }
+}
+
+pub fn repl(mut symbols: SymbolTable, options: ReplOptions) {
+    let mut quit = false;
+    let parser = grammar::ProgramPartExprParser::new();
+
+    let mut rl: Editor<MyHelper, DefaultHistory> = Editor::new().unwrap();
+    rl.set_helper(Some(MyHelper {
+        completer: NameCompleter {
+            names: session_names(&symbols),
+        },
+        highlighter: MatchingBracketHighlighter::new(),
+        validator: BracketValidator,
+        hinter: HistoryHinter::new(),
+        colored_prompt: String::new(),
+    }));
+
+    //rl.bind_sequence(KeyEvent::alt('n'), Cmd::HistorySearchForward);
+    //rl.bind_sequence(KeyEvent::alt('p'), Cmd::HistorySearchBackward);
+
+    if let Some(ref history_file) = options.history_file {
+        if rl.load_history(history_file).is_err() {
+            println!("No previous history.");
+        }
+    }
+    if options.banner {
+        println!(
+            "Lift {}. End a line with \\ to continue it; Ctrl-D quits.",
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    let mut count = 0;
+    loop {
+        let mut buffer: String = "".to_string();
+        let mut prompt = format_prompt(&options.prompt, count);
+        loop {
+            if let Some(helper) = rl.helper_mut() {
+                helper.colored_prompt = format!("\x1b[1;32m{}\x1b[0m", prompt);
+            }
+            let readline = rl.readline(&prompt);
+            match readline {
+                Ok(ref line) => {
+                    if let Some(continuation_line) = line.trim_end().strip_suffix('\\') {
+                        buffer.push_str(continuation_line);
+                        prompt = ">>".to_string();
+                        continue;
+                    } else {
+                        buffer.push_str(line);
+                        prompt = format_prompt(&options.prompt, count);
+                    }
+
+                    match parser.parse(&buffer) {
+                        Ok(mut ast) => {
+                            let _ = rl.add_history_entry(buffer.as_str());
+
+                            count += 1;
+                            if let Some(res) = eval_repl_input(&mut ast, &buffer, &mut symbols) {
+                                println!("=> '{}'", res.pretty());
+                            }
+                            if let Some(helper) = rl.helper_mut() {
+                                helper.completer.names = session_names(&symbols);
+                            }
+                            buffer.clear();
+                        }
+                        Err(ref parse_error) => match parse_error {
+                            ParseError::UnrecognizedEof { .. } => {
+                                buffer.push('\n');
+                                prompt = ">>".to_string();
+                            }
+                            _ => {
+                                eprintln!("ERROR: {}", parse_error);
+                                buffer.clear();
+                            }
+                        },
+                    } //  match parse
+                } // loop
+                Err(ReadlineError::Interrupted) => {
+                    println!("CTRL-C");
+                    quit = true;
+                    break;
+                }
+                Err(ReadlineError::Eof) => {
+                    println!("CTRL-D");
+                    quit = true;
+                    break;
+                }
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    break;
+                }
+            } // match
+        } // loop
+        if quit {
+            break;
+        }
+    } // loop
+    if let Some(ref history_file) = options.history_file {
+        if let Err(e) = rl.save_history(history_file) {
+            eprintln!("Couldn't save history: {}", e);
+        }
+    }
+}
+
+fn diagnostics(code: &str, mut symbols: SymbolTable) -> String {
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut errors = match parser.parse(code) {
+        Err(e) => vec![parse_error(&e)],
+        Ok(mut ast) => ast.prepare(&mut symbols).err().unwrap_or_default(),
+    };
+    for e in errors.iter_mut().chain(&mut symbols.warnings) {
+        e.locate(code);
+    }
+    semantic_analysis::diagnostics_json(&errors, &symbols.warnings)
+}
+
+// A parse error as a diagnostic, located at the token the parser stopped on.
+fn parse_error<T: std::fmt::Display>(e: &ParseError<usize, T, &str>) -> CompileError {
+    let error = CompileError::structure(&e.to_string(), (0, 0));
+    match e {
+        ParseError::InvalidToken { location } | ParseError::UnrecognizedEof { location, .. } => {
+            // A spot between tokens; the character after it stands in for it.
+            error.at_offsets((*location, *location + 1))
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            ..
+        }
+        | ParseError::ExtraToken {
+            token: (start, _, end),
+        } => error.at_offsets((*start, *end)),
+        ParseError::User { .. } => error,
+    }
+}
+
+// Prints what prepare() found in 'code', followed by how many errors past the cap it left out.
+fn report_errors(errors: Vec<CompileError>, code: &str, symbols: &SymbolTable) {
+    for mut e in errors {
+        e.locate(code);
+        eprintln!("{}", e);
+    }
+    if let Some(summary) = semantic_analysis::omitted_errors_summary(symbols.omitted_errors) {
+        eprintln!("{}", summary);
+    }
+}
+
+// Prints the warnings prepare() left in 'symbols', located in 'code'.
+fn report_warnings(code: &str, symbols: &SymbolTable) {
+    for warning in &symbols.warnings {
+        let mut warning = warning.clone();
+        warning.locate(code);
+        eprintln!("{}", semantic_analysis::Warning(&warning));
+    }
+}
+
+// Runs a whole program file, printing the value it ends with.
+fn interpret_code(code: &str, symbols: &mut SymbolTable) -> Result<(), Box<dyn error::Error>> {
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut ast = match parser.parse(code) {
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(3);
+        }
+        Ok(parsed_ast) => parsed_ast,
+    };
+
+    let prepared = ast.prepare(symbols);
+    report_warnings(code, symbols);
+    if let Err(errors) = prepared {
+        report_errors(errors, code, symbols);
+        std::process::exit(1);
+    } else if let Err(error) = semantic_analysis::check_main(&ast, symbols) {
+        report_errors(vec![error], code, symbols);
+        std::process::exit(1);
+    }
+
+    let res = ast.interpret_program(symbols).map_err(|mut e| {
+        locate_runtime_error(&mut e, code);
+        e
+    })?;
+    // A program with a main() exits with what it returns instead of printing it.
+    if ast.main_function().is_some() {
+        if let Expr::Literal(LiteralData::Int(code)) = res {
+            std::process::exit(code as i32);
+        }
+    }
+    symbols.host.print(&format!("{}\n", res));
+    symbols.host.flush();
+    Ok(())
+}
+
+// Runs the test functions of a program file (see Expr::test_functions), printing how each
+// went and then the totals. Returns whether every test passed.
+fn run_tests(code: &str, symbols: &mut SymbolTable) -> Result<bool, Box<dyn error::Error>> {
+    let parser = grammar::ProgramPartExprParser::new();
+    let mut ast = parser.parse(code).map_err(|e| e.to_string())?;
+    let prepared = ast.prepare(symbols);
+    report_warnings(code, symbols);
+    if let Err(errors) = prepared {
+        report_errors(errors, code, symbols);
+        return Err("Can't run the tests of a program that doesn't compile".into());
+    }
+    let results = ast.run_tests(symbols);
+    symbols.host.flush();
+    let results = results.map_err(|mut e| {
+        locate_runtime_error(&mut e, code);
+        e
+    })?;
+    let total = results.len();
+    let mut failed = 0;
+    for (fn_name, result) in results {
+        match result {
+            Ok(_) => symbols.host.print(&format!("test {} ... ok\n", fn_name)),
+            Err(mut e) => {
+                failed += 1;
+                locate_runtime_error(&mut e, code);
+                let report = e.to_string();
+                symbols.host.print(&format!(
+                    "test {} ... FAILED\n{}\n",
+                    fn_name,
+                    report.trim_start()
+                ));
+            }
+        }
+    }
+    symbols
+        .host
+        .print(&format!("{} passed; {} failed\n", total - failed, failed));
+    symbols.host.flush();
+    Ok(failed == 0)
+}
+
+// Analysis and interpretation recurse once per level of nesting in the program, several
+// stack frames at a time; this is enough for symboltable::MAX_NESTING_DEPTH levels.
+const INTERPRETER_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+fn main() {
+    let interpreter = std::thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(run)
+        .expect("couldn't start the interpreter thread");
+    if interpreter.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+// '--prompt=<template>' (or the LIFT_PROMPT environment variable) sets the REPL prompt,
+// '--no-banner' leaves out the banner, and '--history=<file>' or '--no-history' says where
+// to keep history, if anywhere.
+fn repl_options(flags: &[String]) -> ReplOptions {
+    let mut options = ReplOptions::default();
+    if let Ok(prompt) = std::env::var("LIFT_PROMPT") {
+        options.prompt = prompt;
+    }
+    for flag in flags {
+        if let Some(prompt) = flag.strip_prefix("--prompt=") {
+            options.prompt = prompt.to_string();
+        } else if let Some(history_file) = flag.strip_prefix("--history=") {
+            options.history_file = Some(history_file.into());
+        } else if flag == "--no-history" {
+            options.history_file = None;
+        } else if flag == "--no-banner" {
+            options.banner = false;
+        }
+    }
+    options
+}
+
+// What 'lift --explain <code>' prints, or why it can't.
+fn explain(code: &str) -> Result<String, String> {
+    match semantic_analysis::explain(code) {
+        Some(explanation) => Ok(format!("{}: {}", code, explanation)),
+        None => Err(format!(
+            "No error has the code '{}'; codes look like E0001",
+            code
+        )),
+    }
+}
+
+fn run() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if let [flag, code] = args.as_slice() {
+        if flag == "--explain" {
+            match explain(code) {
+                Ok(explanation) => println!("{}", explanation),
+                Err(msg) => {
+                    eprintln!("{}", msg);
+                    std::process::exit(2);
+                }
+            }
+            return;
+        }
+    }
+    // Interpreter flags come before the program file; anything after it is for the program.
+    // '--max-errors' takes the next argument as its value.
+    let mut flag_count = 0;
+    while let Some(flag) = args.get(flag_count).filter(|a| a.starts_with("--")) {
+        flag_count += if flag == "--max-errors" { 2 } else { 1 };
+    }
+    let (flags, rest) = args.split_at(flag_count.min(args.len()));
+
+    let mut symbols = SymbolTable::new();
+    // '--restricted' (or '--sandbox') keeps programs from reaching outside the interpreter
+    // (randomness, the clock, environment variables, arguments).
+    symbols.host.allow_system_access = !flags
+        .iter()
+        .any(|a| a == "--restricted" || a == "--sandbox");
+    symbols.deny_builtin_shadowing = flags.iter().any(|a| a == "--deny-shadowing");
+    // '--strict' makes lenient checks errors (see Strictness).
+    if flags.iter().any(|a| a == "--strict") {
+        symbols.strictness = semantic_analysis::Strictness::strict();
+    }
+    // '--trace' shows each step of running the program on stderr.
+    symbols.host.trace = flags.iter().any(|a| a == "--trace");
+    // '--buffer=none', '--buffer=line' (the default) or '--buffer=full' says how much of
+    // what the program prints is held before it's written.
+    if let Some(mode) = flags.iter().find_map(|a| a.strip_prefix("--buffer=")) {
+        symbols.host.buffering = match mode {
+            "none" => Buffering::Unbuffered,
+            "line" => Buffering::Line,
+            "full" => Buffering::Full,
+            _ => {
+                eprintln!("--buffer takes 'none', 'line' or 'full'");
+                std::process::exit(2);
+            }
+        };
+    }
+    if let Some(i) = flags.iter().position(|a| a == "--max-errors") {
+        match flags.get(i + 1).map(|n| n.parse::<usize>()) {
+            Some(Ok(max)) if max > 0 => symbols.max_errors = max,
+            _ => {
+                eprintln!("--max-errors needs a number of errors greater than zero");
+                std::process::exit(2);
+            }
+        }
+    }
+    match rest.split_first() {
+        None => repl(symbols, repl_options(flags)),
+        Some((program_file, program_args)) => {
+            let code = fs::read_to_string(program_file)
+                .unwrap_or_else(|_| panic!("File at {} unreadable.", program_file));
+
+            symbols.host.program_args = program_args.to_vec();
+            symbols.modules.start_from(Path::new(program_file));
+            // '--diagnostics=json' only analyzes the program, reporting what it finds as
+            // JSON for editors.
+            if flags.iter().any(|a| a == "--diagnostics=json") {
+                println!("{}", diagnostics(&code, symbols));
+            } else if flags.iter().any(|a| a == "--run-tests") {
+                // '--run-tests' runs the program's test_ functions instead of the program.
+                match run_tests(&code, &mut symbols) {
+                    Ok(true) => {}
+                    Ok(false) => std::process::exit(1),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            } else if let Err(e) = interpret_code(&code, &mut symbols) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -0,0 +1,89 @@
+use crate::grammar;
+use crate::semantic_analysis::CompileError;
+use crate::syntax::Expr;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Finds and parses the files programs import. Each file is read and parsed once; importing
+// it again gets a copy of the same tree to analyze.
+#[derive(Default)]
+pub struct ModuleLoader {
+    parsed: HashMap<PathBuf, Expr>,
+    // The files being analyzed, outermost first: imports resolve relative to the last one's
+    // directory, and one already here is being imported by itself.
+    loading: Vec<PathBuf>,
+}
+
+impl ModuleLoader {
+    // Imports in the program at 'path' are relative to its directory rather than the
+    // working directory.
+    pub fn start_from(&mut self, path: &Path) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if let (Ok(dir), Some(name)) = (dir.canonicalize(), path.file_name()) {
+            self.loading = vec![dir.join(name)];
+        }
+    }
+
+    // The program in the file at 'path', as a block, and where that file is. Call enter()
+    // with the latter while analyzing it.
+    pub fn load(&mut self, path: &str) -> Result<(PathBuf, Expr), CompileError> {
+        let dir = match self.loading.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let file = dir.join(path).canonicalize().map_err(|e| {
+            let msg = format!("Can't import '{}': {}", path, e);
+            CompileError::name(&msg, (0, 0))
+        })?;
+        if let Some(position) = self.loading.iter().position(|f| *f == file) {
+            let cycle: Vec<String> = self.loading[position..]
+                .iter()
+                .chain([&file])
+                .map(|f| file_name(f))
+                .collect();
+            let msg = format!("Import cycle: {}", cycle.join(" imports "));
+            return Err(CompileError::structure(&msg, (0, 0)));
+        }
+        if !self.parsed.contains_key(&file) {
+            let src = fs::read_to_string(&file).map_err(|e| {
+                let msg = format!("Can't import '{}': {}", path, e);
+                CompileError::name(&msg, (0, 0))
+            })?;
+            let module = grammar::ProgramPartExprParser::new()
+                .parse(&src)
+                .map_err(|e| {
+                    let msg = format!("Can't parse '{}': {}", path, e);
+                    CompileError::structure(&msg, (0, 0))
+                })?;
+            let module = match module {
+                Expr::Block { .. } => module,
+                other => Expr::Block {
+                    body: vec![other],
+                    spans: Vec::new(),
+                    environment: 0,
+                },
+            };
+            self.parsed.insert(file.clone(), module);
+        }
+        let module = self.parsed[&file].clone();
+        Ok((file, module))
+    }
+
+    pub fn enter(&mut self, file: PathBuf) {
+        self.loading.push(file);
+    }
+
+    pub fn leave(&mut self) {
+        self.loading.pop();
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
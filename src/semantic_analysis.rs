@@ -1,8 +1,15 @@
+use crate::source_map::SourceMap;
+use crate::symboltable::LetBinding;
 use crate::symboltable::SymbolTable;
+use crate::symboltable::MAX_NESTING_DEPTH;
 use crate::syntax::DataType;
 use crate::syntax::Expr;
-use crate::syntax::Function;
+use crate::syntax::KeywordArg;
 use crate::syntax::LiteralData;
+use crate::syntax::MatchArm;
+use crate::syntax::Operator;
+use crate::syntax::UNRESOLVED;
+use indexmap::{IndexMap, IndexSet};
 
 const DEBUG: bool = true;
 
@@ -15,69 +22,559 @@ pub enum CompileErrorType {
 impl CompileErrorType {
     pub fn name(&self) -> String {
         match self {
-            CompileErrorType::TypeCheck { .. } => "Type check Error",
-            CompileErrorType::Name { .. } => "Name Error",
-            CompileErrorType::Structure { .. } => "Structure Error",
+            CompileErrorType::TypeCheck => "Type check Error",
+            CompileErrorType::Name => "Name Error",
+            CompileErrorType::Structure => "Structure Error",
         }
         .to_string()
     }
+
+    // The short form used in diagnostics for tools.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileErrorType::TypeCheck => "typecheck",
+            CompileErrorType::Name => "name",
+            CompileErrorType::Structure => "structure",
+        }
+    }
+
+    // The code 'lift --explain' looks up. These stay the same from release to release.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            CompileErrorType::Name => "E0001",
+            CompileErrorType::TypeCheck => "E0002",
+            CompileErrorType::Structure => "E0003",
+        }
+    }
+}
+
+// The longer story behind each error code, with an example of a fix.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0001",
+        "A name doesn't refer to anything that's defined where it's used, or it's defined
+twice in the same scope. Variables, functions and types have to be defined before
+they're used, and only the scope they're defined in and the scopes inside it see them.
+
+    {let total = count + 1; let count = 2; total}
+
+fails because 'count' isn't defined yet when 'total' uses it. Define it first:
+
+    {let count = 2; let total = count + 1; total}",
+    ),
+    (
+        "E0002",
+        "A value's type doesn't fit where it's used: a variable declared with one type
+initialized with another, an argument of the wrong type, or an operator applied to
+values it doesn't work on.
+
+    {let n: Int = 'three'; n}
+
+fails because 'three' is a Str. Give it a value of the declared type, or declare the
+type the value has:
+
+    {let n: Int = 3; n}",
+    ),
+    (
+        "E0003",
+        "Something is put together in a way the language doesn't allow, like calling a
+function with the wrong number of arguments or nesting expressions too deeply.
+
+    sort([3, 1], [2])
+
+fails because sort() takes one argument. Pass just the list:
+
+    sort([3, 1, 2])",
+    ),
+];
+
+// The explanation of an error code like 'E0001', if there is one.
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, explanation)| *explanation)
+}
+
+// Where an error is: the (line, column) of its first character and of the character just
+// past its end, both counting from 1. A single (line, column) gives an empty span there;
+// (0, 0) means nowhere in particular.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl From<(usize, usize)> for Span {
+    fn from(point: (usize, usize)) -> Span {
+        Span {
+            start: point,
+            end: point,
+        }
+    }
+}
+
+impl Span {
+    // The span of the bytes start..end of 'src'.
+    pub fn from_offsets(src: &str, start: usize, end: usize) -> Span {
+        Span {
+            start: line_and_column(src, start),
+            end: line_and_column(src, end),
+        }
+    }
+}
+
+fn line_and_column(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
 }
 
 impl CompileError {
-    pub fn structure(msg: &str, location: (usize, usize)) -> Self {
+    pub fn structure(msg: &str, span: impl Into<Span>) -> Self {
+        Self::new(CompileErrorType::Structure, msg, span.into())
+    }
+    pub fn name(msg: &str, span: impl Into<Span>) -> Self {
+        Self::new(CompileErrorType::Name, msg, span.into())
+    }
+    pub fn typecheck(msg: &str, span: impl Into<Span>) -> Self {
+        Self::new(CompileErrorType::TypeCheck, msg, span.into())
+    }
+
+    fn new(error_type: CompileErrorType, msg: &str, span: Span) -> Self {
         Self {
-            error_type: CompileErrorType::Structure,
-            location,
+            error_type,
+            span,
+            offsets: None,
+            previous: None,
             msg: msg.to_string(),
+            snippet: None,
         }
     }
-    pub fn name(msg: &str, location: (usize, usize)) -> Self {
-        Self {
-            error_type: CompileErrorType::Name,
-            location,
-            msg: msg.to_string(),
+
+    // Analysis only knows a node's byte offsets; whoever has the source turns them into a
+    // span with locate().
+    pub fn at_offsets(mut self, offsets: (usize, usize)) -> Self {
+        if offsets.1 > offsets.0 {
+            self.offsets = Some(offsets);
         }
+        self
     }
-    pub fn typecheck(msg: &str, location: (usize, usize)) -> Self {
-        Self {
-            error_type: CompileErrorType::TypeCheck,
-            location,
-            msg: msg.to_string(),
+
+    // Like at_offsets(), but keeps offsets already set, so an error is reported at the
+    // innermost node that knew where it was.
+    pub fn or_at_offsets(self, offsets: (usize, usize)) -> Self {
+        if self.offsets.is_some() {
+            self
+        } else {
+            self.at_offsets(offsets)
+        }
+    }
+
+    // For redefinitions: the offsets of the original definition, reported next to the
+    // message once located.
+    pub fn previously_at(mut self, offsets: (usize, usize)) -> Self {
+        if offsets.1 > offsets.0 {
+            self.previous = Some(Box::new(PreviousDefinition {
+                offsets,
+                span: None,
+            }));
+        }
+        self
+    }
+
+    pub fn locate(&mut self, src: &str) {
+        if let Some((start, end)) = self.offsets {
+            self.span = Span::from_offsets(src, start, end);
         }
+        if let Some(ref mut previous) = self.previous {
+            let (start, end) = previous.offsets;
+            previous.span = Some(Span::from_offsets(src, start, end));
+        }
+        self.snippet = SourceMap::new(src).snippet(self.span);
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // Offsets in an imported file mean nothing in the importing one, so an error there is
+    // reported at the import.
+    pub fn from_import(mut self, path: &str, offsets: (usize, usize)) -> Self {
+        self.msg = format!("In '{}': {}", path, self.msg);
+        self.offsets = None;
+        self.previous = None;
+        self.at_offsets(offsets)
     }
 }
+
+#[derive(Debug, Clone)]
+struct PreviousDefinition {
+    offsets: (usize, usize),
+    span: Option<Span>,
+}
+
 #[derive(Debug, Clone)]
 pub struct CompileError {
     error_type: CompileErrorType,
-    location: (usize, usize),
+    span: Span,
+    offsets: Option<(usize, usize)>,
+    // Where the thing this error conflicts with was defined. Boxed since few errors have one.
+    previous: Option<Box<PreviousDefinition>>,
     msg: String,
+    // The source under the span, once located.
+    snippet: Option<String>,
 }
 
 impl std::fmt::Display for CompileError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let (line, column) = self.location;
+        self.write_report(f, &self.error_type.name())
+    }
+}
+
+// A problem that doesn't stop the program, shown like an error but labelled as a warning.
+pub struct Warning<'a>(pub &'a CompileError);
+
+impl std::fmt::Display for Warning<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.write_report(f, "Warning")
+    }
+}
+
+impl CompileError {
+    fn write_report(&self, f: &mut std::fmt::Formatter<'_>, label: &str) -> std::fmt::Result {
+        let Span { start, end } = self.span;
         write!(
             f,
-            "{}: {}, {}: {}",
-            &self.error_type.name(),
-            line,
-            column,
-            self.msg
-        )
+            "{} [{}]: {}, {}",
+            label,
+            self.error_type.error_code(),
+            start.0,
+            start.1
+        )?;
+        if end != start {
+            write!(f, " to {}, {}", end.0, end.1)?;
+        }
+        write!(f, ": {}", self.msg)?;
+        if let Some(Span { start, .. }) = self.previous.as_ref().and_then(|p| p.span) {
+            write!(f, " (first defined at {}, {})", start.0, start.1)?;
+        }
+        if let Some(ref snippet) = self.snippet {
+            write!(f, "\n{}", snippet)?;
+        }
+        Ok(())
     }
 }
 impl std::error::Error for CompileError {}
 
+impl CompileError {
+    // One diagnostic as a JSON object; 'severity' is "error" or "warning".
+    pub fn to_json(&self, severity: &str) -> String {
+        let Span { start, end } = self.span;
+        format!(
+            "{{\"severity\": \"{}\", \"message\": \"{}\", \"line\": {}, \"column\": {}, \"end_line\": {}, \"end_column\": {}, \"error_type\": \"{}\", \"code\": \"{}\"}}",
+            severity,
+            json_escape(&self.msg),
+            start.0,
+            start.1,
+            end.0,
+            end.1,
+            self.error_type.code(),
+            self.error_type.error_code()
+        )
+    }
+}
+
+// How many errors get reported when '--max-errors' doesn't say.
+pub const DEFAULT_MAX_ERRORS: usize = 25;
+
+// Checks that are lenient by default and errors when turned on; '--strict' turns on all
+// of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Strictness {
+    // Int and Flt in the same arithmetic, which otherwise makes the result a Flt.
+    pub mixed_numbers: bool,
+    // A 'let' whose variable is never read.
+    pub unused_variables: bool,
+    // Branches that can't run because an 'if' or 'while' condition is a literal.
+    pub unreachable_code: bool,
+    // Statements whose value is thrown away, otherwise only a warning.
+    pub discarded_results: bool,
+}
+
+impl Strictness {
+    pub fn strict() -> Self {
+        Self {
+            mixed_numbers: true,
+            unused_variables: true,
+            unreachable_code: true,
+            discarded_results: true,
+        }
+    }
+}
+
+// Keeps the first 'max' errors so the cascade one mistake can set off doesn't bury the
+// rest of the output. Gives how many were dropped. At least one is always kept, since a
+// program with errors must not look like it has none.
+pub fn cap_errors(errors: &mut Vec<CompileError>, max: usize) -> usize {
+    let max = max.max(1);
+    let omitted = errors.len().saturating_sub(max);
+    errors.truncate(max);
+    omitted
+}
+
+// The line reported in place of errors cap_errors() dropped.
+pub fn omitted_errors_summary(omitted: usize) -> Option<String> {
+    (omitted > 0).then(|| format!("... and {} more.", omitted))
+}
+
+// A program's main() is what it exits with, so it has to take nothing and give an Int.
+pub fn check_main(program: &Expr, symbols: &SymbolTable) -> Result<(), CompileError> {
+    let Some((_, Expr::Lambda { value, .. })) = program.main_function() else {
+        return Ok(());
+    };
+    let return_type = resolve_type(&value.return_type, symbols, 0);
+    if !value.params.is_empty() || !matches!(return_type, DataType::Int) {
+        let msg = "'main' has to be 'function main(): Int', its result being the exit code";
+        return Err(CompileError::typecheck(msg, (0, 0)));
+    }
+    Ok(())
+}
+
+// Everything analysis found, as a JSON array for editors and other tools.
+pub fn diagnostics_json(errors: &[CompileError], warnings: &[CompileError]) -> String {
+    let diagnostics: Vec<String> = errors
+        .iter()
+        .map(|e| e.to_json("error"))
+        .chain(warnings.iter().map(|w| w.to_json("warning")))
+        .collect();
+    format!("[{}]", diagnostics.join(", "))
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 // This adds symbols for the current scope and the child scopes, plus updates the index (scope id, symbol id) on the expr
 // TODO make a generic traversal function that takes a "visitor" lambda or selects between some different
 // visitor type functions like "add_symbols", "type_check", "print" etc.
+// Preparing a tree that's been prepared before must not register anything twice, so a
+// definition whose index already points at its entry reuses it. The parser leaves
+// UNRESOLVED in every index, which never matches.
+fn is_prepared_index(index: &(usize, usize), scope_id: usize, symbol_id: usize) -> bool {
+    *index == (scope_id, symbol_id)
+}
+
+// Scope 0 is the global scope, so no block or function has it as its own.
+fn reuse_or_create_scope(
+    environment: &mut usize,
+    symbols: &mut SymbolTable,
+    current_scope_id: usize,
+) -> usize {
+    if *environment == 0 {
+        *environment = symbols.create_scope(Some(current_scope_id));
+    }
+    *environment
+}
+
+fn add_or_reuse_symbol(
+    name: &str,
+    value: Expr,
+    index: &(usize, usize),
+    symbols: &mut SymbolTable,
+    current_scope_id: usize,
+) -> Result<usize, CompileError> {
+    match symbols.get_index_in_scope(name, current_scope_id) {
+        Some(symbol_id) if is_prepared_index(index, current_scope_id, symbol_id) => {
+            symbols.update_compiletime_symbol_value(value, index);
+            Ok(symbol_id)
+        }
+        _ => symbols.add_symbol(name, value, current_scope_id),
+    }
+}
+
+// A definition named like a builtin function takes its place for calls in its scope,
+// which is easy to do by accident.
+fn check_builtin_shadowing(name: &str, symbols: &mut SymbolTable) -> Result<(), CompileError> {
+    if symbols.builtins.contains(name) {
+        let msg = format!("'{}' shadows the builtin function of the same name", name);
+        let problem = CompileError::name(&msg, (0, 0));
+        if symbols.deny_builtin_shadowing {
+            return Err(problem);
+        }
+        symbols.warnings.push(problem);
+    }
+    Ok(())
+}
+
 pub fn add_symbols(
     e: &mut Expr,
     symbols: &mut SymbolTable,
     current_scope_id: usize,
+) -> Result<(), CompileError> {
+    if !symbols.enter_nested() {
+        return Err(too_deeply_nested());
+    }
+    let result = add_node_symbols(e, symbols, current_scope_id);
+    symbols.leave_nested();
+    result
+}
+
+// The namespace a variable reference names, when it's one an import made and not also a
+// variable, which would hide it.
+fn namespace_named(e: &Expr, symbols: &SymbolTable, current_scope_id: usize) -> Option<String> {
+    let Expr::Variable { name, .. } = e else {
+        return None;
+    };
+    if symbols
+        .find_index_reachable_from(name, current_scope_id)
+        .is_some()
+    {
+        return None;
+    }
+    symbols
+        .find_namespace_reachable_from(name, current_scope_id)
+        .map(|_| name.clone())
+}
+
+// Whether a name means a variable, function or type where it's used.
+fn is_defined(name: &str, symbols: &SymbolTable, current_scope_id: usize) -> bool {
+    symbols
+        .find_index_reachable_from(name, current_scope_id)
+        .is_some()
+        || symbols
+            .find_type_reachable_from(name, current_scope_id)
+            .is_some()
+}
+
+fn unknown_module(name: &str) -> CompileError {
+    let msg = format!("No variable or imported module named '{}'", name);
+    CompileError::name(&msg, (0, 0))
+}
+
+// Names only get qualified once their module is known, so for one that didn't resolve
+// it's the member that's missing.
+fn missing_member(qualified_name: &str) -> Option<CompileError> {
+    let (namespace, member) = qualified_name.split_once('.')?;
+    let msg = format!("Module '{}' has no '{}'", namespace, member);
+    Some(CompileError::name(&msg, (0, 0)))
+}
+
+// Functions and types can be used anywhere in the block defining them, including before
+// their definitions, so they go into its scope before anything else; adding the
+// definitions themselves later reuses these entries. A 'let' is only visible after it.
+fn hoist_definitions(
+    body: &mut [Expr],
+    symbols: &mut SymbolTable,
+    scope_id: usize,
+) -> Result<(), CompileError> {
+    for statement in body {
+        match statement {
+            Expr::DefineFunction { fn_name, index, .. } => {
+                let symbol_id = add_or_reuse_symbol(fn_name, Expr::Unit, index, symbols, scope_id)?;
+                *index = (scope_id, symbol_id);
+            }
+            Expr::DefineType { .. } => add_symbols(statement, symbols, scope_id)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// A statement in the middle of a block whose value goes nowhere, like '1 + 1;', is most
+// likely a mistake such as a missing assignment. Statements run for their effect aren't
+// flagged, and neither are calls to functions returning Any, which is how a function
+// without a meaningful result is declared.
+fn warn_if_discarded(
+    statement: &Expr,
+    statement_type: &DataType,
+    span: (usize, usize),
+    symbols: &mut SymbolTable,
+    scope_id: usize,
+) -> Result<(), CompileError> {
+    let for_effect = matches!(
+        statement,
+        Expr::Output { .. }
+            | Expr::Let { .. }
+            | Expr::Assign { .. }
+            | Expr::MultipleAssign { .. }
+            | Expr::DefineFunction { .. }
+            | Expr::DefineType { .. }
+            | Expr::Import { .. }
+            | Expr::While { .. }
+            | Expr::ForIn { .. }
+            | Expr::Break
+            | Expr::Continue
+    );
+    if for_effect || matches!(statement_type, DataType::Unsolved | DataType::Any) {
+        return Ok(());
+    }
+    let msg = format!(
+        "Unused expression result of type {}",
+        describe_type(statement_type, symbols, scope_id)
+    );
+    let problem = CompileError::structure(&msg, (0, 0)).at_offsets(span);
+    if symbols.strictness.discarded_results {
+        return Err(problem);
+    }
+    symbols.warnings.push(problem);
+    Ok(())
+}
+
+// Variables a 'let' made that nothing reads, for strict mode.
+pub fn check_unused_variables(symbols: &SymbolTable) -> Vec<CompileError> {
+    symbols
+        .let_bindings
+        .iter()
+        .filter(|binding| !symbols.read_symbols.contains(&binding.index))
+        .map(|binding| {
+            let msg = format!("Variable '{}' is never used", binding.name);
+            CompileError::name(&msg, (0, 0)).at_offsets(binding.span)
+        })
+        .collect()
+}
+
+// In strict mode, an 'if' or 'while' whose condition is a literal has code that can't run
+// or a condition that isn't needed.
+fn check_reachable(keyword: &str, cond: &Expr, symbols: &SymbolTable) -> Result<(), CompileError> {
+    if let (true, Expr::Literal(LiteralData::Bool(value))) =
+        (symbols.strictness.unreachable_code, cond)
+    {
+        let msg = format!(
+            "The condition of this '{}' is always {}, so part of it can never run",
+            keyword, value
+        );
+        return Err(CompileError::structure(&msg, (0, 0)));
+    }
+    Ok(())
+}
+
+fn too_deeply_nested() -> CompileError {
+    let msg = format!(
+        "Expression too deeply nested (more than {} levels)",
+        MAX_NESTING_DEPTH
+    );
+    CompileError::structure(&msg, (0, 0))
+}
+
+fn add_node_symbols(
+    e: &mut Expr,
+    symbols: &mut SymbolTable,
+    current_scope_id: usize,
 ) -> Result<(), CompileError> {
     if DEBUG {
-        println!(
+        eprintln!(
             "DEBUG: adding symbols to expr '{}' at scope '{}'\n\n",
             &e, current_scope_id
         );
@@ -87,28 +584,38 @@ pub fn add_symbols(
             type_name,
             definition,
             index,
+            span,
         } => {
-            let symbol_id = symbols.add_type(&type_name, &definition, current_scope_id)?;
+            let already_added = symbols
+                .get_type_index_in_scope(type_name, current_scope_id)
+                .filter(|id| is_prepared_index(index, current_scope_id, *id));
+            if already_added.is_none() {
+                let new_type_id =
+                    symbols.add_type(type_name, definition, current_scope_id, *span)?;
+                *index = (current_scope_id, new_type_id);
+            }
         }
         Expr::Output { ref mut data } => {
-            for mut e in data {
+            for e in data {
                 add_symbols(e, symbols, current_scope_id)?;
             }
         }
         Expr::Block {
             ref mut body,
+            ref spans,
             ref mut environment,
         } => {
-            let new_scope_id = symbols.create_scope(Some(current_scope_id));
-            *environment = new_scope_id;
-            for e in body {
-                add_symbols(e, symbols, new_scope_id)?;
+            let new_scope_id = reuse_or_create_scope(environment, symbols, current_scope_id);
+            hoist_definitions(body, symbols, new_scope_id)?;
+            for (position, e) in body.iter_mut().enumerate() {
+                add_symbols(e, symbols, new_scope_id)
+                    .map_err(|error| error.or_at_offsets(statement_span(spans, position)))?;
             }
         }
         Expr::BinaryExpr {
             ref mut left,
-            ref op,
             ref mut right,
+            ..
         } => {
             add_symbols(left, symbols, current_scope_id)?;
             add_symbols(right, symbols, current_scope_id)?;
@@ -117,6 +624,7 @@ pub fn add_symbols(
             ref mut cond,
             ref mut then,
             ref mut final_else,
+            ..
         } => {
             add_symbols(cond, symbols, current_scope_id)?;
             add_symbols(then, symbols, current_scope_id)?;
@@ -125,23 +633,116 @@ pub fn add_symbols(
         Expr::While {
             ref mut cond,
             ref mut body,
+            ref mut step,
         } => {
             add_symbols(cond, symbols, current_scope_id)?;
             add_symbols(body, symbols, current_scope_id)?;
+            if let Some(step) = step {
+                add_symbols(step, symbols, current_scope_id)?;
+            }
+        }
+        Expr::Comprehension {
+            ref var_name,
+            ref mut index,
+            ref mut source,
+            ref mut key,
+            ref mut element,
+            ref mut filter,
+            ref mut environment,
+        } => {
+            add_symbols(source, symbols, current_scope_id)?;
+            let new_scope_id = reuse_or_create_scope(environment, symbols, current_scope_id);
+            check_builtin_shadowing(var_name, symbols)?;
+            let new_symbol_id =
+                add_or_reuse_symbol(var_name, Expr::Unit, index, symbols, new_scope_id)?;
+            *index = (new_scope_id, new_symbol_id);
+            for e in key.iter_mut().chain(filter.iter_mut()) {
+                add_symbols(e, symbols, new_scope_id)?;
+            }
+            add_symbols(element, symbols, new_scope_id)?;
+        }
+        Expr::ForIn {
+            ref var_name,
+            ref mut index,
+            ref mut source,
+            ref mut body,
+            ref mut environment,
+        } => {
+            add_symbols(source, symbols, current_scope_id)?;
+            let new_scope_id = reuse_or_create_scope(environment, symbols, current_scope_id);
+            check_builtin_shadowing(var_name, symbols)?;
+            let new_symbol_id =
+                add_or_reuse_symbol(var_name, Expr::Unit, index, symbols, new_scope_id)?;
+            *index = (new_scope_id, new_symbol_id);
+            add_symbols(body, symbols, new_scope_id)?;
         }
         Expr::Call {
-            ref fn_name,
+            ref mut fn_name,
             ref mut index,
             ref mut args,
+            method,
+            span,
         } => {
+            let span = *span;
+            // 'math.square(2)' parses as square(math, 2); with 'math' naming an imported
+            // module it's that module's square(2).
+            let receiver = args.first().filter(|_| *method).map(|a| &a.value);
+            if let Some(namespace) =
+                receiver.and_then(|r| namespace_named(r, symbols, current_scope_id))
+            {
+                *fn_name = format!("{}.{}", namespace, fn_name);
+                args.remove(0);
+                *method = false;
+            } else if let Some(Expr::Variable { name, .. }) = receiver {
+                // With neither the receiver nor the function known, 'name' was most likely
+                // meant as a module.
+                if !is_defined(name, symbols, current_scope_id)
+                    && !is_defined(fn_name, symbols, current_scope_id)
+                    && !symbols.builtins.contains(fn_name)
+                {
+                    return Err(unknown_module(name).or_at_offsets(span));
+                }
+            }
+            for a in args.iter_mut() {
+                if let Err(ref err) = add_symbols(&mut a.value, symbols, current_scope_id) {
+                    let new_msg = format!("Error on argument '{}': {}", a.name, err.clone());
+                    return Err(CompileError::structure(&new_msg, (0, 0)));
+                }
+            }
             if let Some(found_index) = symbols.find_index_reachable_from(fn_name, current_scope_id)
             {
                 if DEBUG {
-                    println!("DEBUG: During semantic analysis phase found index '{},{}' for '{}' function call.",
+                    eprintln!("DEBUG: During semantic analysis phase found index '{},{}' for '{}' function call.",
                     found_index.0, found_index.1,fn_name 
                 );
                 }
                 *index = found_index;
+                symbols.read_symbols.insert(found_index);
+            } else if !symbols.host.allow_system_access
+                && symbols.builtins.needs_system_access(fn_name)
+            {
+                let msg = format!(
+                    "use of undeclared or not yet declared function '{}': the builtin is \
+                     disabled because system access is off (--restricted)",
+                    fn_name
+                );
+                return Err(CompileError::name(&msg, (0, 0)).at_offsets(span));
+            } else if symbols.builtins.contains(fn_name) {
+                *e = Expr::BuiltinCall {
+                    fn_name: fn_name.clone(),
+                    args: args.iter().map(|a| a.value.clone()).collect(),
+                    span,
+                };
+            } else if let Some(type_index) =
+                symbols.find_type_reachable_from(fn_name, current_scope_id)
+            {
+                *e = Expr::StructLiteral {
+                    type_name: fn_name.clone(),
+                    index: type_index,
+                    fields: args.clone(),
+                };
+            } else if let Some(error) = missing_member(fn_name) {
+                return Err(error);
             } else {
                 let msg = format!(
                     "use of undeclared or not yet declared function '{}' at scope {}",
@@ -150,27 +751,73 @@ pub fn add_symbols(
                 if DEBUG {
                     eprintln!("{}", &msg);
                 }
-                return Err(CompileError::name(&msg, (0, 0)));
+                return Err(CompileError::name(&msg, (0, 0)).at_offsets(span));
             }
-            for a in args {
-                if let Err(ref err) = add_symbols(&mut a.value, symbols, current_scope_id) {
-                    let new_msg = format!("Error on argument '{}': {}", a.name, err.clone());
-                    return Err(CompileError::structure(&new_msg, (0, 0)));
+        }
+        Expr::ListLiteral { ref mut data, .. } => {
+            // One Flt among Int literals makes them all Flt: [1.0, 2] is a List of Flt.
+            if let Some(wanted) = data.iter().filter_map(determine_type).find(mentions_flt) {
+                for e in data.iter_mut() {
+                    adapt_literals(e, &wanted);
+                }
+            }
+            for e in data {
+                add_symbols(e, symbols, current_scope_id)?;
+            }
+        }
+        Expr::MapLiteral { ref mut data, .. } => {
+            for (_, e) in data {
+                add_symbols(e, symbols, current_scope_id)?;
+            }
+        }
+        Expr::FieldAccess {
+            ref mut target,
+            ref field,
+        } => {
+            if let Some(namespace) = namespace_named(target, symbols, current_scope_id) {
+                *e = Expr::Variable {
+                    name: format!("{}.{}", namespace, field),
+                    index: UNRESOLVED,
+                };
+                add_symbols(e, symbols, current_scope_id)?;
+            } else if let Some(enum_value) = enum_value(target, field, symbols, current_scope_id)? {
+                *e = enum_value;
+            } else if let Expr::Variable { name, .. } = target.as_ref() {
+                if !is_defined(name, symbols, current_scope_id) {
+                    return Err(unknown_module(name));
+                }
+                add_symbols(target, symbols, current_scope_id)?;
+            } else {
+                add_symbols(target, symbols, current_scope_id)?;
+            }
+        }
+        Expr::Match {
+            ref mut cond,
+            ref mut against,
+        } => {
+            add_symbols(cond, symbols, current_scope_id)?;
+            for arm in against {
+                add_symbols(&mut arm.pattern, symbols, current_scope_id)?;
+                if let Some(ref mut guard) = arm.guard {
+                    add_symbols(guard, symbols, current_scope_id)?;
                 }
+                add_symbols(&mut arm.value, symbols, current_scope_id)?;
             }
         }
         Expr::Lambda {
             ref mut value,
             ref mut environment,
+            ..
         } => {
             // The function has its own scope as well which we should create
-            let new_scope_id = symbols.create_scope(Some(current_scope_id));
-            *environment = new_scope_id;
+            let new_scope_id = reuse_or_create_scope(environment, symbols, current_scope_id);
 
             // Add params to the new environment
             for p in &mut value.params {
-                let new_symbol_id = symbols.add_symbol(&p.name, Expr::Unit, new_scope_id)?;
+                let new_symbol_id =
+                    add_or_reuse_symbol(&p.name, Expr::Unit, &p.index, symbols, new_scope_id)?;
                 p.index = (new_scope_id, new_symbol_id);
+                symbols.update_symbol_type(p.data_type.clone(), &p.index);
             }
 
             add_symbols(&mut value.body, symbols, new_scope_id)?;
@@ -181,9 +828,11 @@ pub fn add_symbols(
             ref mut value,
         } => {
             // At first just create the symbol table entry for the function  and make the value the Unit value...
-            let new_symbol_id = symbols.add_symbol(fn_name, Expr::Unit, current_scope_id)?;
+            check_builtin_shadowing(fn_name, symbols)?;
+            let new_symbol_id =
+                add_or_reuse_symbol(fn_name, Expr::Unit, index, symbols, current_scope_id)?;
             if DEBUG {
-                println!("Added symbol id {} for function {}", new_symbol_id, fn_name);
+                eprintln!("Added symbol id {} for function {}", new_symbol_id, fn_name);
             }
             // Then update the body (value) with all the right symbol indices including the function itself, to
             // support recursion...
@@ -194,6 +843,8 @@ pub fn add_symbols(
                 *value.clone(),
                 &(current_scope_id, new_symbol_id),
             );
+            // Referring to the function by name, without calling it, gives the lambda.
+            symbols.update_runtime_value(*value.clone(), &(current_scope_id, new_symbol_id));
 
             // The function is getting defined for the current scope:
             *index = (current_scope_id, new_symbol_id);
@@ -206,28 +857,133 @@ pub fn add_symbols(
         } => {
             if let Some(found_index) = symbols.find_index_reachable_from(name, current_scope_id) {
                 *index = found_index;
+                symbols.read_symbols.insert(found_index);
+            } else if let Some(error) = missing_member(name) {
+                return Err(error);
             } else {
                 let msg = format!("use of undeclared or not yet declared variable '{}'", name);
                 return Err(CompileError::name(&msg, (0, 0)));
             }
         }
 
+        Expr::Assign {
+            ref name,
+            ref mut value,
+            ref mut index,
+        } => {
+            add_symbols(value, symbols, current_scope_id)?;
+            if let Some(found_index) = symbols.find_index_reachable_from(name, current_scope_id) {
+                *index = found_index;
+            } else {
+                let msg = format!("assignment to undeclared variable '{}'", name);
+                return Err(CompileError::name(&msg, (0, 0)));
+            }
+        }
+        Expr::MultipleAssign {
+            ref mut targets,
+            ref mut values,
+        } => {
+            for value in values.iter_mut() {
+                add_symbols(value, symbols, current_scope_id)?;
+            }
+            for (name, index) in targets.iter_mut() {
+                if let Some(found_index) = symbols.find_index_reachable_from(name, current_scope_id)
+                {
+                    *index = found_index;
+                } else {
+                    let msg = format!("assignment to undeclared variable '{}'", name);
+                    return Err(CompileError::name(&msg, (0, 0)));
+                }
+            }
+        }
         Expr::Let {
             ref var_name,
             ref mut value,
             ref mut data_type,
             ref mut index,
+            span,
         } => {
             if matches!(data_type, DataType::Unsolved) {
                 if let Some(inferred_type) = determine_type(value) {
                     *data_type = inferred_type;
                 }
+            } else {
+                adapt_literals(value, data_type);
             }
-            add_symbols(value, symbols, current_scope_id)?;
-            let new_symbol_id = symbols.add_symbol(var_name, *value.clone(), current_scope_id)?;
-            *index = (current_scope_id, new_symbol_id);
+            check_builtin_shadowing(var_name, symbols)?;
+            // A lambda can call itself by the name it's bound to, so that name goes in
+            // first, the same as for 'function'. Other values can't see their own name.
+            if let Expr::Lambda { .. } = value.as_ref() {
+                let new_symbol_id =
+                    add_or_reuse_symbol(var_name, Expr::Unit, index, symbols, current_scope_id)?;
+                *index = (current_scope_id, new_symbol_id);
+                add_symbols(value, symbols, current_scope_id)?;
+                symbols.update_compiletime_symbol_value(*value.clone(), index);
+            } else {
+                add_symbols(value, symbols, current_scope_id)?;
+                let new_symbol_id = add_or_reuse_symbol(
+                    var_name,
+                    *value.clone(),
+                    index,
+                    symbols,
+                    current_scope_id,
+                )?;
+                *index = (current_scope_id, new_symbol_id);
+            }
+            symbols.let_bindings.push(LetBinding {
+                name: var_name.clone(),
+                index: *index,
+                span: *span,
+            });
         }
         Expr::Return(ref mut e) => add_symbols(e, symbols, current_scope_id)?,
+        Expr::Import {
+            ref path,
+            ref namespace,
+            ref mut module,
+            span,
+        } => {
+            let import_error = |e: CompileError| e.from_import(path, *span);
+            let newly_loaded = match module {
+                Some(_) => None,
+                None => {
+                    let (file, loaded) = symbols
+                        .modules
+                        .load(path)
+                        .map_err(|e| e.at_offsets(*span))?;
+                    *module = Some(Box::new(loaded));
+                    Some(file)
+                }
+            };
+            let Some(Expr::Block { environment, .. }) = module.as_deref_mut() else {
+                unreachable!("the module loader gives a block");
+            };
+            // A module sees only the global scope, not where it's imported.
+            let module_scope_id = reuse_or_create_scope(environment, symbols, 0);
+            // Imports inside a module just loaded resolve from its directory.
+            let entered = newly_loaded.is_some();
+            if let Some(file) = newly_loaded {
+                symbols.modules.enter(file);
+            }
+            let result = add_symbols(module.as_mut().unwrap(), symbols, 0);
+            if entered {
+                symbols.modules.leave();
+            }
+            result.map_err(import_error)?;
+            symbols.add_import(current_scope_id, namespace.as_deref(), module_scope_id);
+        }
+        Expr::Cast { ref mut value, .. } | Expr::TypeAssertion { ref mut value, .. } => {
+            add_symbols(value, symbols, current_scope_id)?
+        }
+        Expr::OptionalFieldAccess { ref mut target, .. } => {
+            add_symbols(target, symbols, current_scope_id)?
+        }
+        // Only desugared forms like 'while let' make these before analysis.
+        Expr::BuiltinCall { ref mut args, .. } => {
+            for arg in args {
+                add_symbols(arg, symbols, current_scope_id)?;
+            }
+        }
 
         _ => (),
     }
@@ -235,6 +991,14 @@ pub fn add_symbols(
 }
 // TODO  determine_type() is incomplete. Does not address all types and does not fully traverse the tree.
 pub fn determine_type(expression: &Expr) -> Option<DataType> {
+    determine_type_at_depth(expression, 0)
+}
+
+// Gives up (leaving the type to be checked later) on lists nested too deeply.
+fn determine_type_at_depth(expression: &Expr, depth: usize) -> Option<DataType> {
+    if depth >= MAX_NESTING_DEPTH {
+        return None;
+    }
     let inferred_type = match expression {
         Expr::Literal(l) => match l {
             LiteralData::Int(_) => DataType::Int,
@@ -245,12 +1009,14 @@ pub fn determine_type(expression: &Expr) -> Option<DataType> {
         Expr::ListLiteral {
             ref data_type,
             ref data,
+            ..
         } => {
             // Check first element and use that as the inferred type
             let mut element_type = data_type.clone();
             if matches!(data_type, DataType::Unsolved) {
                 if let Some(reference_expr) = data.first() {
-                    if let Some(reference_type) = determine_type(reference_expr) {
+                    if let Some(reference_type) = determine_type_at_depth(reference_expr, depth + 1)
+                    {
                         element_type = reference_type;
                     }
                 }
@@ -267,3 +1033,1519 @@ pub fn determine_type(expression: &Expr) -> Option<DataType> {
         Some(inferred_type)
     }
 }
+
+// Follows type names given with 'type' back to their definitions.
+pub fn resolve_type(
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> DataType {
+    if let DataType::TypeRef(type_name) = data_type {
+        if let Some(index) = symbols.find_type_reachable_from(type_name, current_scope_id) {
+            if let Some(definition) = symbols.get_type_definition(&index) {
+                return resolve_type(&definition, symbols, index.0);
+            }
+        }
+        return DataType::Unsolved;
+    }
+    // Nothing is known about an 'Any' value until it's running, which for checking is
+    // the same as not having solved its type.
+    if matches!(data_type, DataType::Any) {
+        return DataType::Unsolved;
+    }
+    data_type.clone()
+}
+
+// The value 'default(data_type)' gives, or None for types without an obvious one:
+// functions, sequences, ranges and Any. An enum starts at its first variant.
+pub fn default_value(
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> Option<Expr> {
+    default_value_within(data_type, symbols, current_scope_id, &mut Vec::new())
+}
+
+// 'building' names the structs whose defaults are being put together. A struct that
+// holds itself (other than through an Optional) has no default, as building one would
+// never end.
+fn default_value_within(
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+    building: &mut Vec<String>,
+) -> Option<Expr> {
+    let value = match data_type {
+        DataType::Int | DataType::I32 => Expr::Literal(LiteralData::Int(0)),
+        DataType::Flt | DataType::F32 => Expr::Literal(LiteralData::Flt(0.0)),
+        // Strings keep their quotes, as they do in source.
+        DataType::Str => Expr::Literal(LiteralData::Str("''".into())),
+        DataType::Bool => Expr::Literal(LiteralData::Bool(false)),
+        DataType::Optional(_) => Expr::RuntimeOptional(None),
+        DataType::List { element_type } => Expr::RuntimeList {
+            data_type: *element_type.clone(),
+            data: Vec::new(),
+        },
+        DataType::Map {
+            key_type,
+            value_type,
+        } => Expr::RuntimeMap {
+            key_type: *key_type.clone(),
+            value_type: *value_type.clone(),
+            data: IndexMap::new(),
+        },
+        DataType::Set(element_type) => Expr::RuntimeSet {
+            data_type: *element_type.clone(),
+            data: IndexSet::new(),
+        },
+        DataType::Newtype { base, .. } => {
+            default_value_within(base, symbols, current_scope_id, building)?
+        }
+        DataType::TypeRef(type_name) => {
+            let index = symbols.find_type_reachable_from(type_name, current_scope_id)?;
+            match symbols.get_type_definition(&index)? {
+                DataType::Struct(params) => {
+                    if building.contains(type_name) {
+                        return None;
+                    }
+                    building.push(type_name.clone());
+                    let mut fields = Vec::with_capacity(params.len());
+                    for p in params {
+                        let value = default_value_within(&p.data_type, symbols, index.0, building);
+                        fields.push((p.name, value?));
+                    }
+                    building.pop();
+                    Expr::RuntimeStruct {
+                        type_name: type_name.clone(),
+                        fields,
+                    }
+                }
+                DataType::Enum(variants) => Expr::EnumValue {
+                    type_name: type_name.clone(),
+                    variant: variants.first()?.clone(),
+                },
+                definition => default_value_within(&definition, symbols, index.0, building)?,
+            }
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+// Whether a value of type 'found' can be stored where 'expected' is wanted. Unsolved
+// types are compatible with anything since we can't say they're wrong.
+pub fn types_compatible(expected: &DataType, found: &DataType) -> bool {
+    match (expected, found) {
+        (DataType::Unsolved | DataType::Any, _) | (_, DataType::Unsolved | DataType::Any) => true,
+        (
+            DataType::List {
+                element_type: expected_element,
+            },
+            DataType::List {
+                element_type: found_element,
+            },
+        ) => types_compatible(expected_element, found_element),
+        // A narrow number always fits where a wider one is wanted.
+        (DataType::Int, DataType::I32) | (DataType::Flt, DataType::F32) => true,
+        (DataType::Optional(expected_inner), DataType::Optional(found_inner))
+        | (DataType::Set(expected_inner), DataType::Set(found_inner))
+        | (DataType::Seq(expected_inner), DataType::Seq(found_inner)) => {
+            types_compatible(expected_inner, found_inner)
+        }
+        (
+            DataType::Map {
+                key_type: expected_key,
+                value_type: expected_value,
+            },
+            DataType::Map {
+                key_type: found_key,
+                value_type: found_value,
+            },
+        ) => {
+            types_compatible(expected_key, found_key)
+                && types_compatible(expected_value, found_value)
+        }
+        (
+            DataType::Function {
+                params: expected_params,
+                return_type: expected_return,
+            },
+            DataType::Function {
+                params: found_params,
+                return_type: found_return,
+            },
+        ) => {
+            expected_params.len() == found_params.len()
+                && expected_params
+                    .iter()
+                    .zip(found_params)
+                    .all(|(e, f)| types_compatible(f, e))
+                && types_compatible(expected_return, found_return)
+        }
+        _ => expected == found,
+    }
+}
+
+// Int and I32 are the same kind of number at different widths, as are Flt and F32.
+// 'as' converts between a newtype and its base type, and between widths of the same kind
+// of number. Any other cast has to be to a type the value already fits.
+fn can_cast(from: &DataType, to: &DataType) -> bool {
+    match (from, to) {
+        (DataType::Newtype { base, .. }, other) | (other, DataType::Newtype { base, .. })
+            if types_compatible(base, other) || same_number_kind(base, other) =>
+        {
+            true
+        }
+        _ => types_compatible(to, from) || same_number_kind(from, to),
+    }
+}
+
+fn is_number_literal(e: &Expr) -> bool {
+    matches!(e, Expr::Literal(LiteralData::Int(_) | LiteralData::Flt(_)))
+}
+
+// Arithmetic whose result is an I32 or F32 gets wrapped in a cast to that type, which
+// range checks (or rounds) the result when it runs as storing it would.
+fn narrow_arithmetic(
+    e: &mut Expr,
+    result_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) {
+    let Expr::BinaryExpr { op, span, .. } = e else {
+        return;
+    };
+    let span = *span;
+    if !matches!(
+        op,
+        Operator::Add | Operator::Sub | Operator::Mul | Operator::Div
+    ) {
+        return;
+    }
+    let width = match resolve_type(result_type, symbols, current_scope_id) {
+        DataType::Newtype { base, .. } => *base,
+        resolved => resolved,
+    };
+    if matches!(width, DataType::I32 | DataType::F32) {
+        let arithmetic = std::mem::replace(e, Expr::Unit);
+        *e = Expr::Cast {
+            value: Box::new(arithmetic),
+            data_type: result_type.clone(),
+            span,
+        };
+    }
+}
+
+// Whether two resolved types can be operands of the same arithmetic operator as far as
+// newtypes go: a newtype only combines with itself.
+fn newtypes_mix(left: &DataType, right: &DataType) -> bool {
+    match (left, right) {
+        (DataType::Newtype { .. }, _) | (_, DataType::Newtype { .. }) => {
+            types_compatible(left, right)
+        }
+        _ => true,
+    }
+}
+
+fn same_number_kind(left: &DataType, right: &DataType) -> bool {
+    matches!(
+        (left, right),
+        (DataType::Int | DataType::I32, DataType::Int | DataType::I32)
+            | (DataType::Flt | DataType::F32, DataType::Flt | DataType::F32)
+    )
+}
+
+// Int literals take the type they're wanted as, so '5' where a Flt is expected is 5.0
+// rather than an Int that happens to be stored there. Only literals change; computed
+// values still have to match. List literals also learn their element type, which an
+// empty one can't show by itself.
+fn adapt_literals(value: &mut Expr, wanted: &DataType) {
+    match (value, wanted) {
+        (Expr::Literal(literal), DataType::Flt | DataType::F32) => {
+            if let LiteralData::Int(i) = *literal {
+                *literal = LiteralData::Flt(i as f64);
+            }
+        }
+        (
+            Expr::ListLiteral {
+                data, data_type, ..
+            },
+            DataType::List { element_type },
+        ) => {
+            if let DataType::Unsolved = data_type {
+                *data_type = *element_type.clone();
+            }
+            for item in data {
+                adapt_literals(item, element_type);
+            }
+        }
+        (Expr::MapLiteral { data, .. }, DataType::Map { value_type, .. }) => {
+            for (_, item) in data {
+                adapt_literals(item, value_type);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn mentions_flt(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Flt | DataType::F32 => true,
+        DataType::List { element_type } => mentions_flt(element_type),
+        DataType::Map { value_type, .. } => mentions_flt(value_type),
+        _ => false,
+    }
+}
+
+// '[]', '[:]' and 'none()' say nothing about what they'd hold. Rather than let a variable
+// start out that way and be treated as whatever is convenient later, as in sum([])
+// giving an Int, its type has to be declared.
+// Gives an example declaration for the error.
+fn empty_of_unknown_type(value: &Expr) -> Option<&'static str> {
+    match value {
+        Expr::ListLiteral { data, .. } if data.is_empty() => Some("List of Int = []"),
+        Expr::MapLiteral { data, .. } if data.is_empty() => Some("Map of Str to Int = [:]"),
+        Expr::BuiltinCall { fn_name, args, .. } if fn_name == "none" && args.is_empty() => {
+            Some("Optional Int = none()")
+        }
+        _ => None,
+    }
+}
+
+// Whether a declared type says what a collection or optional holds. 'let' fills in
+// what it can infer before type checking, so an empty value leaves the contents Unsolved.
+fn has_declared_contents(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Unsolved => false,
+        DataType::List { element_type } => !matches!(**element_type, DataType::Unsolved),
+        DataType::Set(inner) | DataType::Seq(inner) | DataType::Optional(inner) => {
+            !matches!(**inner, DataType::Unsolved)
+        }
+        DataType::Map { value_type, .. } => !matches!(**value_type, DataType::Unsolved),
+        _ => true,
+    }
+}
+
+// Whether 'value' may be stored where 'declared' is wanted. Besides compatible types,
+// number literals may go into a narrower width; they're range checked when stored.
+fn fits_declared_type(declared: &DataType, value: &Expr, value_type: &DataType) -> bool {
+    types_compatible(declared, value_type)
+        || (matches!(value, Expr::Literal(_)) && same_number_kind(declared, value_type))
+}
+
+// Types that support '=', '<>', '<', '>', '<=' and '>='. Structs and lists compare
+// field by field (element by element) so they qualify when everything inside them does.
+// Whether a value of 'data_type' always holds a 'type_name' inside it, directly or through
+// the fields of other structs. Optional and collection fields can be empty, so a type
+// referring back to itself through one of them is fine. 'enclosing' holds the named types
+// we're already inside of, so one that refers to another that refers back doesn't go on
+// forever.
+fn holds_itself(
+    type_name: &str,
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+    enclosing: &mut Vec<String>,
+) -> bool {
+    match data_type {
+        DataType::TypeRef(name) if name == type_name => true,
+        DataType::TypeRef(name) => {
+            if enclosing.contains(name) {
+                return false;
+            }
+            let Some(definition) = symbols
+                .find_type_reachable_from(name, current_scope_id)
+                .and_then(|index| symbols.get_type_definition(&index))
+            else {
+                return false;
+            };
+            enclosing.push(name.clone());
+            let holds = holds_itself(type_name, &definition, symbols, current_scope_id, enclosing);
+            enclosing.pop();
+            holds
+        }
+        DataType::Struct(params) => params.iter().any(|p| {
+            holds_itself(
+                type_name,
+                &p.data_type,
+                symbols,
+                current_scope_id,
+                enclosing,
+            )
+        }),
+        DataType::Newtype { base, .. } => {
+            holds_itself(type_name, base, symbols, current_scope_id, enclosing)
+        }
+        _ => false,
+    }
+}
+
+fn is_comparable(data_type: &DataType, symbols: &SymbolTable, current_scope_id: usize) -> bool {
+    is_comparable_within(data_type, symbols, current_scope_id, &mut Vec::new())
+}
+
+// 'enclosing' holds the named types we're already inside of. A type that refers back to
+// one of them, as a tree's list of child trees does, qualifies as far as that reference
+// goes; the rest of the enclosing type decides.
+fn is_comparable_within(
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+    enclosing: &mut Vec<String>,
+) -> bool {
+    if let DataType::TypeRef(type_name) = data_type {
+        if enclosing.contains(type_name) {
+            return true;
+        }
+        enclosing.push(type_name.clone());
+        let comparable = is_comparable_within(
+            &resolve_type(data_type, symbols, current_scope_id),
+            symbols,
+            current_scope_id,
+            enclosing,
+        );
+        enclosing.pop();
+        return comparable;
+    }
+    match resolve_type(data_type, symbols, current_scope_id) {
+        DataType::Unsolved
+        | DataType::Int
+        | DataType::Flt
+        | DataType::I32
+        | DataType::F32
+        | DataType::Str
+        | DataType::Bool
+        | DataType::Enum(_) => true,
+        DataType::List { element_type } => {
+            is_comparable_within(&element_type, symbols, current_scope_id, enclosing)
+        }
+        DataType::Struct(fields) => fields
+            .iter()
+            .all(|f| is_comparable_within(&f.data_type, symbols, current_scope_id, enclosing)),
+        DataType::Newtype { base, .. } => {
+            is_comparable_within(&base, symbols, current_scope_id, enclosing)
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn check_comparable(
+    data_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> Result<(), CompileError> {
+    if let DataType::Struct(fields) = resolve_type(data_type, symbols, current_scope_id) {
+        if let Some(field) = fields
+            .iter()
+            .find(|f| !is_comparable(&f.data_type, symbols, current_scope_id))
+        {
+            let msg = format!(
+                "Can't compare values of type '{}': field '{}' of type '{}' isn't comparable",
+                data_type, field.name, field.data_type
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+    } else if !is_comparable(data_type, symbols, current_scope_id) {
+        let msg = format!("Can't compare values of type '{}'", data_type);
+        return Err(CompileError::typecheck(&msg, (0, 0)));
+    }
+    Ok(())
+}
+
+// The parameter and return types of a function value, when it's known at compile time.
+pub(crate) fn function_signature(
+    e: &Expr,
+    symbols: &SymbolTable,
+) -> Option<(Vec<DataType>, DataType)> {
+    let lambda = match e {
+        Expr::Lambda { .. } => e.clone(),
+        Expr::Variable { index, .. } => symbols.get_compiletime_value(index)?,
+        _ => return None,
+    };
+    if let Expr::Lambda { value, .. } = lambda {
+        let param_types = value.params.iter().map(|p| p.data_type.clone()).collect();
+        Some((param_types, value.return_type))
+    } else {
+        None
+    }
+}
+
+// How a type reads in a mismatch message. An alias shows what it stands for, as in
+// "'Celsius' (= Int)"; structs, enums and newtypes are named types in their own right.
+fn describe_type(data_type: &DataType, symbols: &SymbolTable, current_scope_id: usize) -> String {
+    if let DataType::TypeRef(type_name) = data_type {
+        match resolve_type(data_type, symbols, current_scope_id) {
+            DataType::Unsolved
+            | DataType::Struct(_)
+            | DataType::Enum(_)
+            | DataType::Newtype { .. } => (),
+            resolved => return format!("'{}' (= {})", type_name, resolved),
+        }
+    }
+    format!("'{}'", data_type)
+}
+
+fn check_assignment(
+    name: &str,
+    index: &(usize, usize),
+    value: &Expr,
+    value_type: &DataType,
+    symbols: &SymbolTable,
+) -> Result<(), CompileError> {
+    let variable_type = symbols.get_symbol_type(index).unwrap_or(DataType::Unsolved);
+    if fits_declared_type(
+        &resolve_type(&variable_type, symbols, index.0),
+        value,
+        &resolve_type(value_type, symbols, index.0),
+    ) {
+        Ok(())
+    } else {
+        let msg = format!(
+            "Can't assign a value of type {} to '{}' of type {}",
+            describe_type(value_type, symbols, index.0),
+            name,
+            describe_type(&variable_type, symbols, index.0)
+        );
+        Err(CompileError::typecheck(&msg, (0, 0)))
+    }
+}
+
+// 'Color.red' parses as a field access; when 'Color' names an enum type rather than a
+// variable it's one of the enum's values instead.
+fn enum_value(
+    target: &Expr,
+    variant: &str,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> Result<Option<Expr>, CompileError> {
+    let Expr::Variable { name, .. } = target else {
+        return Ok(None);
+    };
+    if symbols
+        .find_index_reachable_from(name, current_scope_id)
+        .is_some()
+    {
+        return Ok(None);
+    }
+    let Some(type_index) = symbols.find_type_reachable_from(name, current_scope_id) else {
+        return Ok(None);
+    };
+    match symbols.get_type_definition(&type_index) {
+        Some(DataType::Enum(variants)) if variants.iter().any(|v| v == variant) => {
+            Ok(Some(Expr::EnumValue {
+                type_name: name.clone(),
+                variant: variant.to_string(),
+            }))
+        }
+        Some(DataType::Enum(_)) => {
+            let msg = format!("Enum '{}' has no variant '{}'", name, variant);
+            Err(CompileError::name(&msg, (0, 0)))
+        }
+        _ => Ok(None),
+    }
+}
+
+// A match over an enum has to cover every variant and one over a Bool both values, unless
+// it has a '_' arm. Other types can only be covered by '_', so a match on those is left
+// to fail at runtime when no arm fits. A guarded arm covers nothing since its guard may
+// not hold.
+fn check_exhaustive(
+    cond_type: &DataType,
+    against: &[MatchArm],
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> Result<(), CompileError> {
+    let unguarded = || against.iter().filter(|arm| arm.guard.is_none());
+    if unguarded().any(|arm| matches!(arm.pattern, Expr::Wildcard)) {
+        return Ok(());
+    }
+    let required = match resolve_type(cond_type, symbols, current_scope_id) {
+        DataType::Enum(variants) => variants,
+        DataType::Bool => vec!["true".to_string(), "false".to_string()],
+        _ => return Ok(()),
+    };
+    let covered: Vec<String> = unguarded()
+        .filter_map(|arm| match &arm.pattern {
+            Expr::EnumValue { variant, .. } => Some(variant.clone()),
+            Expr::Literal(LiteralData::Bool(b)) => Some(b.to_string()),
+            _ => None,
+        })
+        .collect();
+    let missing: Vec<String> = required
+        .into_iter()
+        .filter(|v| !covered.contains(v))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        let msg = format!(
+            "Match on '{}' doesn't cover {}",
+            cond_type,
+            missing.join(", ")
+        );
+        Err(CompileError::typecheck(&msg, (0, 0)))
+    }
+}
+
+// Checks a call's arguments against the parameters of the function it calls, when its
+// signature is known. Functions held in variables only have parameter types, not names.
+fn check_call_args(
+    fn_name: &str,
+    index: &(usize, usize),
+    args: &[KeywordArg],
+    arg_types: &[DataType],
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> Result<(), CompileError> {
+    let params: Vec<(String, DataType)> = match symbols.get_compiletime_value(index) {
+        Some(Expr::Lambda { value, .. }) => value
+            .params
+            .into_iter()
+            .map(|p| (p.name, p.data_type))
+            .collect(),
+        _ => match symbols.get_symbol_type(index) {
+            Some(DataType::Function { params, .. }) => {
+                params.into_iter().map(|p| (String::new(), p)).collect()
+            }
+            _ => return Ok(()),
+        },
+    };
+    if args.len() != params.len() {
+        let msg = format!(
+            "'{}' takes {} arguments, got {}",
+            fn_name,
+            params.len(),
+            args.len()
+        );
+        return Err(CompileError::structure(&msg, (0, 0)));
+    }
+    for (position, (arg, arg_type)) in args.iter().zip(arg_types).enumerate() {
+        let param = if arg.name.is_empty() {
+            params.get(position)
+        } else {
+            params.iter().find(|(name, _)| name == &arg.name)
+        };
+        let Some((_, param_type)) = param else {
+            let msg = format!("'{}' has no parameter '{}'", fn_name, arg.name);
+            return Err(CompileError::name(&msg, (0, 0)));
+        };
+        let expected = resolve_type(param_type, symbols, current_scope_id);
+        let found = resolve_type(arg_type, symbols, current_scope_id);
+        if !fits_declared_type(&expected, &arg.value, &found) {
+            let msg = format!(
+                "Argument {} of '{}' should be {}, got {}",
+                position + 1,
+                fn_name,
+                describe_type(param_type, symbols, current_scope_id),
+                describe_type(arg_type, symbols, current_scope_id)
+            );
+            return Err(CompileError::typecheck(&msg, (0, 0)));
+        }
+    }
+    Ok(())
+}
+
+// Replaces each 'if' whose condition is a literal true or false with the branch that runs.
+// This happens after typechecking, so errors in the branch that never runs are still
+// reported.
+pub fn prune_dead_branches(e: &mut Expr, symbols: &mut SymbolTable) {
+    match e {
+        Expr::If {
+            cond,
+            then,
+            final_else,
+            ..
+        } => {
+            prune_dead_branches(cond, symbols);
+            prune_dead_branches(then, symbols);
+            prune_dead_branches(final_else, symbols);
+            let kept = match cond.as_ref() {
+                Expr::Literal(LiteralData::Bool(true)) => {
+                    std::mem::replace(then.as_mut(), Expr::Unit)
+                }
+                Expr::Literal(LiteralData::Bool(false)) => {
+                    std::mem::replace(final_else.as_mut(), Expr::Unit)
+                }
+                _ => return,
+            };
+            *e = kept;
+        }
+        Expr::Program { body, .. } | Expr::Block { body, .. } => {
+            for statement in body {
+                prune_dead_branches(statement, symbols);
+            }
+        }
+        Expr::Output { data: items }
+        | Expr::ListLiteral { data: items, .. }
+        | Expr::MultipleAssign { values: items, .. }
+        | Expr::BuiltinCall { args: items, .. } => {
+            for item in items {
+                prune_dead_branches(item, symbols);
+            }
+        }
+        Expr::MapLiteral { data, .. } => {
+            for (_, value) in data {
+                prune_dead_branches(value, symbols);
+            }
+        }
+        Expr::Call { args, .. } | Expr::StructLiteral { fields: args, .. } => {
+            for arg in args {
+                prune_dead_branches(&mut arg.value, symbols);
+            }
+        }
+        Expr::BinaryExpr { left, right, .. } => {
+            prune_dead_branches(left, symbols);
+            prune_dead_branches(right, symbols);
+        }
+        Expr::While { cond, body, step } => {
+            prune_dead_branches(cond, symbols);
+            prune_dead_branches(body, symbols);
+            if let Some(step) = step {
+                prune_dead_branches(step, symbols);
+            }
+        }
+        Expr::Import {
+            module: Some(module),
+            ..
+        } => prune_dead_branches(module, symbols),
+        Expr::Comprehension {
+            source,
+            key,
+            element,
+            filter,
+            ..
+        } => {
+            prune_dead_branches(source, symbols);
+            for e in key.iter_mut().chain(filter.iter_mut()) {
+                prune_dead_branches(e, symbols);
+            }
+            prune_dead_branches(element, symbols);
+        }
+        Expr::ForIn { source, body, .. } => {
+            prune_dead_branches(source, symbols);
+            prune_dead_branches(body, symbols);
+        }
+        Expr::Match { cond, against } => {
+            prune_dead_branches(cond, symbols);
+            for arm in against {
+                if let Some(ref mut guard) = arm.guard {
+                    prune_dead_branches(guard, symbols);
+                }
+                prune_dead_branches(&mut arm.value, symbols);
+            }
+        }
+        Expr::UnaryExpr { expr: inner, .. }
+        | Expr::Assign { value: inner, .. }
+        | Expr::Let { value: inner, .. }
+        | Expr::FieldAccess { target: inner, .. }
+        | Expr::OptionalFieldAccess { target: inner, .. }
+        | Expr::Cast { value: inner, .. }
+        | Expr::TypeAssertion { value: inner, .. }
+        | Expr::Return(inner) => prune_dead_branches(inner, symbols),
+        Expr::Lambda { value, .. } => prune_dead_branches(&mut value.body, symbols),
+        // Calls run the copy of the function kept in the symbol table, so that gets
+        // the pruned body too.
+        Expr::DefineFunction { value, index, .. } => {
+            prune_dead_branches(value, symbols);
+            symbols.update_compiletime_symbol_value(*value.clone(), index);
+            symbols.update_runtime_value(*value.clone(), index);
+        }
+        _ => (),
+    }
+}
+
+// Computes the type of an expression that has already been through add_symbols(), reporting
+// mismatches as it goes. Anything not worked out yet comes back as Unsolved, which is accepted
+// everywhere; the interpreter catches what slips through at runtime.
+pub fn typecheck(
+    e: &mut Expr,
+    symbols: &mut SymbolTable,
+    current_scope_id: usize,
+) -> Result<DataType, CompileError> {
+    if !symbols.enter_nested() {
+        return Err(too_deeply_nested());
+    }
+    let result = typecheck_node(e, symbols, current_scope_id);
+    symbols.leave_nested();
+    result
+}
+
+// A loop's body is where 'break' and 'continue' may go.
+fn typecheck_loop_body(
+    body: &mut Expr,
+    symbols: &mut SymbolTable,
+    scope_id: usize,
+) -> Result<DataType, CompileError> {
+    symbols.loop_depth += 1;
+    let body_type = typecheck(body, symbols, scope_id);
+    symbols.loop_depth -= 1;
+    body_type
+}
+
+// The type of the items a 'for' or comprehension takes from a source of this type: a
+// collection's elements, or a Map's keys.
+fn iterated_type(
+    var_name: &str,
+    source_type: &DataType,
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> Result<DataType, CompileError> {
+    match resolve_type(source_type, symbols, current_scope_id) {
+        DataType::List { element_type } => Ok(*element_type),
+        DataType::Set(element_type) | DataType::Seq(element_type) => Ok(*element_type),
+        DataType::Map { key_type, .. } => Ok(*key_type),
+        DataType::Unsolved => Ok(DataType::Unsolved),
+        other => {
+            let msg = format!("Can't take '{}' from a value of type {}", var_name, other);
+            Err(CompileError::typecheck(&msg, (0, 0)))
+        }
+    }
+}
+
+// The type of a block's last statement. An error from a statement that couldn't say where
+// it happened is reported at the whole statement.
+fn typecheck_statements(
+    body: &mut [Expr],
+    spans: &[(usize, usize)],
+    symbols: &mut SymbolTable,
+    scope_id: usize,
+) -> Result<DataType, CompileError> {
+    let mut last_type = DataType::Unsolved;
+    let mut first_failure = None;
+    let statement_count = body.len();
+    for (position, item) in body.iter_mut().enumerate() {
+        let span = statement_span(spans, position);
+        let found_before = symbols.later_errors.len();
+        let checked = typecheck(item, symbols, scope_id).and_then(|checked_type| {
+            if position + 1 < statement_count {
+                warn_if_discarded(item, &checked_type, span, symbols, scope_id)?;
+            }
+            Ok(checked_type)
+        });
+        // Keep going after an error so one run reports the problems in every statement. The
+        // error a statement gives comes before any later ones found inside it.
+        match checked {
+            Ok(checked_type) => last_type = checked_type,
+            Err(e) if first_failure.is_none() => {
+                first_failure = Some(e.or_at_offsets(span));
+                last_type = DataType::Unsolved;
+            }
+            Err(e) => {
+                symbols
+                    .later_errors
+                    .insert(found_before, e.or_at_offsets(span));
+                last_type = DataType::Unsolved;
+            }
+        }
+    }
+    match first_failure {
+        Some(e) => Err(e),
+        None => Ok(last_type),
+    }
+}
+
+// The type the items of a list or map literal have so far, given one more item at 'span'.
+// Every item has to fit the type of the first, unless the first fits its type instead, as
+// an I32 does an Int.
+fn unify_items(
+    so_far: DataType,
+    item_type: DataType,
+    span: (usize, usize),
+    symbols: &SymbolTable,
+    current_scope_id: usize,
+) -> Result<DataType, CompileError> {
+    if matches!(so_far, DataType::Unsolved) {
+        Ok(item_type)
+    } else if types_compatible(&so_far, &item_type) {
+        Ok(so_far)
+    } else if types_compatible(&item_type, &so_far) {
+        Ok(item_type)
+    } else {
+        let msg = format!(
+            "The items must all be the same type: expected {}, got {}",
+            describe_type(&so_far, symbols, current_scope_id),
+            describe_type(&item_type, symbols, current_scope_id)
+        );
+        Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(span))
+    }
+}
+
+fn statement_span(spans: &[(usize, usize)], position: usize) -> (usize, usize) {
+    spans.get(position).copied().unwrap_or((0, 0))
+}
+
+fn typecheck_node(
+    e: &mut Expr,
+    symbols: &mut SymbolTable,
+    current_scope_id: usize,
+) -> Result<DataType, CompileError> {
+    let checked_type = match e {
+        Expr::Literal(_) => determine_type(e).unwrap_or(DataType::Unsolved),
+        Expr::ListLiteral { data, spans, .. } => {
+            let mut element_type = DataType::Unsolved;
+            for (item, span) in data.iter_mut().zip(spans.iter()) {
+                let item_type = typecheck(item, symbols, current_scope_id)?;
+                element_type =
+                    unify_items(element_type, item_type, *span, symbols, current_scope_id)?;
+            }
+            DataType::List {
+                element_type: Box::new(element_type),
+            }
+        }
+        Expr::MapLiteral { data, spans, .. } => {
+            let mut key_type = DataType::Unsolved;
+            let mut value_type = DataType::Unsolved;
+            for ((key, value), span) in data.iter_mut().zip(spans.iter()) {
+                let this_key_type = determine_type(&Expr::Literal(key.clone().into()))
+                    .unwrap_or(DataType::Unsolved);
+                key_type = unify_items(key_type, this_key_type, *span, symbols, current_scope_id)?;
+                let item_type = typecheck(value, symbols, current_scope_id)?;
+                value_type = unify_items(value_type, item_type, *span, symbols, current_scope_id)?;
+            }
+            DataType::Map {
+                key_type: Box::new(key_type),
+                value_type: Box::new(value_type),
+            }
+        }
+        Expr::Output { data } => {
+            for item in data {
+                typecheck(item, symbols, current_scope_id)?;
+            }
+            DataType::Unsolved
+        }
+        Expr::Program { body, environment } => {
+            typecheck_statements(body, &[], symbols, *environment)?
+        }
+        Expr::Block {
+            body,
+            spans,
+            environment,
+        } => typecheck_statements(body, spans, symbols, *environment)?,
+        Expr::Let {
+            var_name,
+            value,
+            data_type,
+            index,
+            span,
+        } => {
+            let value_type = typecheck(value, symbols, current_scope_id)?;
+            if let Some(example) =
+                empty_of_unknown_type(value).filter(|_| !has_declared_contents(data_type))
+            {
+                let msg = format!(
+                    "Could not infer the type of '{}' from an empty value; declare it, like \
+                     'let {}: {}'",
+                    var_name, var_name, example
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+            }
+            if matches!(data_type, DataType::Unsolved) {
+                symbols.update_symbol_type(value_type, index);
+            } else {
+                if !fits_declared_type(
+                    &resolve_type(data_type, symbols, current_scope_id),
+                    value,
+                    &resolve_type(&value_type, symbols, current_scope_id),
+                ) {
+                    let msg = format!(
+                        "Can't initialize '{}' of type {} with a value of type {}",
+                        var_name,
+                        describe_type(data_type, symbols, current_scope_id),
+                        describe_type(&value_type, symbols, current_scope_id)
+                    );
+                    return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                }
+                symbols.update_symbol_type(data_type.clone(), index);
+            }
+            DataType::Unsolved
+        }
+        Expr::Variable { index, .. } => {
+            symbols.get_symbol_type(index).unwrap_or(DataType::Unsolved)
+        }
+        Expr::Assign { name, value, index } => {
+            let value_type = typecheck(value, symbols, current_scope_id)?;
+            check_assignment(name, index, value, &value_type, symbols)?;
+            DataType::Unsolved
+        }
+        Expr::MultipleAssign { targets, values } => {
+            if targets.len() != values.len() {
+                let msg = format!(
+                    "Assigning {} values to {} variables",
+                    values.len(),
+                    targets.len()
+                );
+                return Err(CompileError::structure(&msg, (0, 0)));
+            }
+            for ((name, index), value) in targets.iter().zip(values) {
+                let value_type = typecheck(value, symbols, current_scope_id)?;
+                check_assignment(name, index, value, &value_type, symbols)?;
+            }
+            DataType::Unsolved
+        }
+        Expr::BinaryExpr {
+            left,
+            op,
+            right,
+            span,
+        } => {
+            let left_type = typecheck(left, symbols, current_scope_id)?;
+            let right_type = typecheck(right, symbols, current_scope_id)?;
+            let resolves_to = |operand_type: &DataType, wanted: &[DataType]| {
+                wanted.contains(&resolve_type(operand_type, symbols, current_scope_id))
+            };
+            // Float division follows IEEE, so dividing a Flt by zero gives inf or NaN however
+            // the zero is written. Dividing an Int by a computed zero is only caught when it
+            // happens.
+            if matches!(op, Operator::Div | Operator::Modulo)
+                && matches!(right.as_ref(), Expr::Literal(LiteralData::Int(0)))
+                && !resolves_to(&left_type, &[DataType::Flt, DataType::F32])
+            {
+                let error = CompileError::typecheck("Division by zero", (0, 0));
+                return Err(error.at_offsets(*span));
+            }
+            let has_str = resolves_to(&left_type, &[DataType::Str])
+                || resolves_to(&right_type, &[DataType::Str]);
+            match op {
+                Operator::Gt
+                | Operator::Lt
+                | Operator::Gte
+                | Operator::Lte
+                | Operator::Eq
+                | Operator::Neq => {
+                    let resolved_left = resolve_type(&left_type, symbols, current_scope_id);
+                    let resolved_right = resolve_type(&right_type, symbols, current_scope_id);
+                    if !types_compatible(&resolved_left, &resolved_right)
+                        && !same_number_kind(&resolved_left, &resolved_right)
+                    {
+                        let msg = format!(
+                            "Can't compare {} with {}",
+                            describe_type(&left_type, symbols, current_scope_id),
+                            describe_type(&right_type, symbols, current_scope_id)
+                        );
+                        return Err(CompileError::typecheck(&msg, (0, 0)));
+                    }
+                    check_comparable(&left_type, symbols, current_scope_id)?;
+                    check_comparable(&right_type, symbols, current_scope_id)?;
+                    DataType::Bool
+                }
+                Operator::And | Operator::Or | Operator::Not => DataType::Bool,
+                // The right side may be a fallback value or another optional to try.
+                Operator::Coalesce => {
+                    match resolve_type(&left_type, symbols, current_scope_id) {
+                        DataType::Optional(inner) => {
+                            let (fallback, fallback_is_optional) =
+                                match resolve_type(&right_type, symbols, current_scope_id) {
+                                    DataType::Optional(fallback) => (*fallback, true),
+                                    fallback => (fallback, false),
+                                };
+                            if !types_compatible(&inner, &fallback) {
+                                let msg = format!(
+                                    "The fallback for {} can't be {}",
+                                    describe_type(&left_type, symbols, current_scope_id),
+                                    describe_type(&right_type, symbols, current_scope_id)
+                                );
+                                return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                            }
+                            // 'none()' on the left says nothing about the type.
+                            let value_type = match *inner {
+                                DataType::Unsolved => fallback,
+                                inner => inner,
+                            };
+                            if fallback_is_optional {
+                                DataType::Optional(Box::new(value_type))
+                            } else {
+                                value_type
+                            }
+                        }
+                        DataType::Unsolved | DataType::Any => right_type,
+                        _ => {
+                            let msg = format!(
+                                "'?:' needs an Optional on its left, not {}",
+                                describe_type(&left_type, symbols, current_scope_id)
+                            );
+                            return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                        }
+                    }
+                }
+                Operator::WrappingAdd | Operator::WrappingMul | Operator::Modulo => {
+                    for operand_type in [&left_type, &right_type] {
+                        let resolved = resolve_type(operand_type, symbols, current_scope_id);
+                        if !matches!(resolved, DataType::Int | DataType::Unsolved | DataType::Any) {
+                            let what = if matches!(op, Operator::Modulo) {
+                                "'%'"
+                            } else {
+                                "Wrapping arithmetic"
+                            };
+                            let msg = format!(
+                                "{} is only for Int, not {}",
+                                what,
+                                describe_type(operand_type, symbols, current_scope_id)
+                            );
+                            return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                        }
+                    }
+                    DataType::Int
+                }
+                // '+' joins two Strs; no other arithmetic takes them.
+                _ if has_str => {
+                    let both_str = [&left_type, &right_type]
+                        .into_iter()
+                        .all(|t| resolves_to(t, &[DataType::Str, DataType::Unsolved]));
+                    if matches!(op, Operator::Add) && both_str {
+                        DataType::Str
+                    } else {
+                        let left = describe_type(&left_type, symbols, current_scope_id);
+                        let right = describe_type(&right_type, symbols, current_scope_id);
+                        let msg = if matches!(op, Operator::Add) {
+                            format!("Can't add {} and {}; '+' only joins two Strs", left, right)
+                        } else {
+                            format!("Can't do arithmetic on {} and {}", left, right)
+                        };
+                        return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                    }
+                }
+                // Mixing widths gives the narrower one; the result is range checked
+                // wherever it gets stored. A newtype only does arithmetic with itself.
+                _ => match (&left_type, &right_type) {
+                    _ if !newtypes_mix(
+                        &resolve_type(&left_type, symbols, current_scope_id),
+                        &resolve_type(&right_type, symbols, current_scope_id),
+                    ) =>
+                    {
+                        let msg = format!(
+                            "Can't do arithmetic on {} and {}; convert one with 'as'",
+                            describe_type(&left_type, symbols, current_scope_id),
+                            describe_type(&right_type, symbols, current_scope_id)
+                        );
+                        return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                    }
+                    (DataType::Unsolved, _) => right_type,
+                    // A number literal takes the width of the other operand.
+                    (DataType::I32, DataType::Int) if is_number_literal(right) => left_type,
+                    (DataType::Int, DataType::I32) if is_number_literal(left) => right_type,
+                    (DataType::F32, DataType::Int | DataType::Flt) if is_number_literal(right) => {
+                        left_type
+                    }
+                    (DataType::Int | DataType::Flt, DataType::F32) if is_number_literal(left) => {
+                        right_type
+                    }
+                    // Otherwise a narrow width only mixes with another through 'as'.
+                    (DataType::I32 | DataType::F32, _) | (_, DataType::I32 | DataType::F32)
+                        if left_type != right_type && right_type != DataType::Unsolved =>
+                    {
+                        let msg = format!(
+                            "Can't mix {} and {} in arithmetic; convert one with 'as'",
+                            describe_type(&left_type, symbols, current_scope_id),
+                            describe_type(&right_type, symbols, current_scope_id)
+                        );
+                        return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                    }
+                    (DataType::Int, DataType::Flt) | (DataType::Flt, DataType::Int) => {
+                        if symbols.strictness.mixed_numbers {
+                            let msg = format!(
+                                "Mixing {} and {} in arithmetic; write the Int as a Flt",
+                                left_type, right_type
+                            );
+                            return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+                        }
+                        DataType::Flt
+                    }
+                    _ => left_type,
+                },
+            }
+        }
+        Expr::If {
+            cond,
+            then,
+            final_else,
+            cond_span,
+        } => {
+            check_reachable("if", cond, symbols).map_err(|e| e.or_at_offsets(*cond_span))?;
+            let cond_type = typecheck(cond, symbols, current_scope_id)?;
+            if !types_compatible(&DataType::Bool, &cond_type) {
+                let msg = format!("A condition must be a Bool, got '{}'", cond_type);
+                return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*cond_span));
+            }
+            let then_type = typecheck(then, symbols, current_scope_id)?;
+            let else_type = typecheck(final_else, symbols, current_scope_id)?;
+            if !types_compatible(&then_type, &else_type) {
+                let msg = format!(
+                    "The branches give different types, '{}' and '{}'",
+                    then_type, else_type
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+            if matches!(then_type, DataType::Unsolved) {
+                else_type
+            } else {
+                then_type
+            }
+        }
+        Expr::While { cond, body, step } => {
+            check_reachable("while", cond, symbols)?;
+            let cond_type = typecheck(cond, symbols, current_scope_id)?;
+            if !types_compatible(&DataType::Bool, &cond_type) {
+                let msg = format!("A condition must be a Bool, got '{}'", cond_type);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+            typecheck_loop_body(body, symbols, current_scope_id)?;
+            if let Some(step) = step {
+                typecheck(step, symbols, current_scope_id)?;
+            }
+            DataType::Unsolved
+        }
+        Expr::Break | Expr::Continue => {
+            if symbols.loop_depth == 0 {
+                let keyword = if matches!(e, Expr::Break) {
+                    "break"
+                } else {
+                    "continue"
+                };
+                let msg = format!("'{}' can only be used inside a loop", keyword);
+                return Err(CompileError::structure(&msg, (0, 0)));
+            }
+            DataType::Unsolved
+        }
+        Expr::Import {
+            path,
+            module: Some(module),
+            span,
+            ..
+        } => {
+            typecheck(module, symbols, current_scope_id).map_err(|e| e.from_import(path, *span))?;
+            DataType::Unsolved
+        }
+        Expr::ForIn {
+            var_name,
+            index,
+            source,
+            body,
+            environment,
+        } => {
+            let source_type = typecheck(source, symbols, current_scope_id)?;
+            let item_type = iterated_type(var_name, &source_type, symbols, current_scope_id)?;
+            symbols.update_symbol_type(item_type, index);
+            typecheck_loop_body(body, symbols, *environment)?;
+            DataType::Unsolved
+        }
+        Expr::Comprehension {
+            var_name,
+            index,
+            source,
+            key,
+            element,
+            filter,
+            environment,
+        } => {
+            let source_type = typecheck(source, symbols, current_scope_id)?;
+            let item_type = iterated_type(var_name, &source_type, symbols, current_scope_id)?;
+            symbols.update_symbol_type(item_type, index);
+            if let Some(filter) = filter {
+                let filter_type = typecheck(filter, symbols, *environment)?;
+                if !types_compatible(&DataType::Bool, &filter_type) {
+                    let msg = format!(
+                        "A comprehension's filter must be Bool, got '{}'",
+                        filter_type
+                    );
+                    return Err(CompileError::typecheck(&msg, (0, 0)));
+                }
+            }
+            let element_type = typecheck(element, symbols, *environment)?;
+            match key {
+                None => DataType::List {
+                    element_type: Box::new(element_type),
+                },
+                Some(key) => {
+                    let key_type = typecheck(key, symbols, *environment)?;
+                    match resolve_type(&key_type, symbols, *environment) {
+                        DataType::Int | DataType::Str | DataType::Bool | DataType::Unsolved => {}
+                        other => {
+                            let msg = format!("Can't use '{}' as a map key", other);
+                            return Err(CompileError::typecheck(&msg, (0, 0)));
+                        }
+                    }
+                    DataType::Map {
+                        key_type: Box::new(key_type),
+                        value_type: Box::new(element_type),
+                    }
+                }
+            }
+        }
+        Expr::Match { cond, against } => {
+            let cond_type = typecheck(cond, symbols, current_scope_id)?;
+            let mut result_type = DataType::Unsolved;
+            for arm in against.iter_mut() {
+                if !matches!(arm.pattern, Expr::Wildcard) {
+                    let pattern_type = typecheck(&mut arm.pattern, symbols, current_scope_id)?;
+                    if !types_compatible(
+                        &resolve_type(&cond_type, symbols, current_scope_id),
+                        &resolve_type(&pattern_type, symbols, current_scope_id),
+                    ) {
+                        let msg = format!(
+                            "Can't match a '{}' value against a '{}' pattern",
+                            cond_type, pattern_type
+                        );
+                        return Err(CompileError::typecheck(&msg, (0, 0)));
+                    }
+                }
+                if let Some(guard) = &mut arm.guard {
+                    let guard_type = typecheck(guard, symbols, current_scope_id)?;
+                    if !types_compatible(&DataType::Bool, &guard_type) {
+                        let msg = format!("A match guard must be Bool, got '{}'", guard_type);
+                        return Err(CompileError::typecheck(&msg, (0, 0)));
+                    }
+                }
+                let value_type = typecheck(&mut arm.value, symbols, current_scope_id)?;
+                if result_type == DataType::Unsolved {
+                    result_type = value_type;
+                }
+            }
+            check_exhaustive(&cond_type, against, symbols, current_scope_id)?;
+            result_type
+        }
+        Expr::EnumValue { type_name, .. } => DataType::TypeRef(type_name.clone()),
+        Expr::Call {
+            fn_name,
+            args,
+            index,
+            span,
+            ..
+        } => {
+            let mut arg_types = Vec::new();
+            for a in args.iter_mut() {
+                arg_types.push(typecheck(&mut a.value, symbols, current_scope_id)?);
+            }
+            check_call_args(fn_name, index, args, &arg_types, symbols, current_scope_id)
+                .map_err(|e| e.or_at_offsets(*span))?;
+            match symbols.get_compiletime_value(index) {
+                Some(Expr::Lambda { value, .. }) => value.return_type,
+                // A variable holding a function someone returned; only its type is known.
+                _ => match symbols.get_symbol_type(index) {
+                    Some(DataType::Function { return_type, .. }) => *return_type,
+                    _ => DataType::Unsolved,
+                },
+            }
+        }
+        Expr::BuiltinCall {
+            fn_name,
+            args,
+            span,
+        } => {
+            let mut arg_types = Vec::new();
+            for a in args.iter_mut() {
+                arg_types.push(typecheck(a, symbols, current_scope_id)?);
+            }
+            match symbols.builtins.get(fn_name) {
+                Some(builtin) => builtin
+                    .signature(args, &arg_types, symbols, current_scope_id)
+                    .map_err(|e| e.or_at_offsets(*span))?,
+                None => DataType::Unsolved,
+            }
+        }
+        Expr::StructLiteral {
+            type_name,
+            index,
+            fields,
+        } => {
+            let definition = symbols.get_type_definition(index);
+            let Some(DataType::Struct(params)) = definition else {
+                let msg = format!("'{}' isn't a struct type", type_name);
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            };
+            for f in fields.iter_mut() {
+                if !params.iter().any(|p| p.name == f.name) {
+                    let msg = format!("Struct '{}' has no field '{}'", type_name, f.name);
+                    return Err(CompileError::name(&msg, (0, 0)));
+                }
+                typecheck(&mut f.value, symbols, current_scope_id)?;
+            }
+            if fields.len() != params.len() {
+                let msg = format!(
+                    "Struct '{}' has {} fields but {} were given",
+                    type_name,
+                    params.len(),
+                    fields.len()
+                );
+                return Err(CompileError::structure(&msg, (0, 0)));
+            }
+            DataType::TypeRef(type_name.clone())
+        }
+        Expr::FieldAccess { target, field } => {
+            let target_type = typecheck(target, symbols, current_scope_id)?;
+            match resolve_type(&target_type, symbols, current_scope_id) {
+                DataType::Struct(params) => match params.iter().find(|p| &p.name == field) {
+                    Some(p) => p.data_type.clone(),
+                    None => {
+                        let msg = format!("'{}' has no field '{}'", target_type, field);
+                        return Err(CompileError::name(&msg, (0, 0)));
+                    }
+                },
+                DataType::Unsolved => DataType::Unsolved,
+                other => {
+                    let msg = format!("Can't access field '{}' on type '{}'", field, other);
+                    return Err(CompileError::typecheck(&msg, (0, 0)));
+                }
+            }
+        }
+        Expr::OptionalFieldAccess { target, field } => {
+            let target_type = typecheck(target, symbols, current_scope_id)?;
+            let struct_type = match resolve_type(&target_type, symbols, current_scope_id) {
+                DataType::Optional(inner) => *inner,
+                DataType::Unsolved => return Ok(DataType::Unsolved),
+                _ => {
+                    let msg = format!(
+                        "Can't use '?.' on {}, which isn't optional; use '.'",
+                        describe_type(&target_type, symbols, current_scope_id)
+                    );
+                    return Err(CompileError::typecheck(&msg, (0, 0)));
+                }
+            };
+            let field_type = match resolve_type(&struct_type, symbols, current_scope_id) {
+                DataType::Struct(params) => match params.iter().find(|p| &p.name == field) {
+                    Some(p) => p.data_type.clone(),
+                    None => {
+                        let msg = format!("'{}' has no field '{}'", struct_type, field);
+                        return Err(CompileError::name(&msg, (0, 0)));
+                    }
+                },
+                DataType::Unsolved => DataType::Unsolved,
+                other => {
+                    let msg = format!("Can't access field '{}' on type '{}'", field, other);
+                    return Err(CompileError::typecheck(&msg, (0, 0)));
+                }
+            };
+            match resolve_type(&field_type, symbols, current_scope_id) {
+                DataType::Optional(_) => field_type,
+                _ => DataType::Optional(Box::new(field_type)),
+            }
+        }
+        Expr::Lambda {
+            value, environment, ..
+        } => {
+            // A loop around the function isn't one 'break' in its body could leave.
+            let enclosing_loops = std::mem::take(&mut symbols.loop_depth);
+            let body_type = typecheck(&mut value.body, symbols, *environment);
+            symbols.loop_depth = enclosing_loops;
+            let body_type = body_type?;
+            let return_type = resolve_type(&value.return_type, symbols, *environment);
+            if !types_compatible(
+                &return_type,
+                &resolve_type(&body_type, symbols, *environment),
+            ) {
+                let msg = format!(
+                    "Function body gives {} but the declared return type is {}",
+                    describe_type(&body_type, symbols, *environment),
+                    describe_type(&value.return_type, symbols, *environment)
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)));
+            }
+            // Only generated lambdas (see Expr::compose) leave the return type out.
+            let return_type = match value.return_type {
+                DataType::Unsolved => body_type,
+                ref declared => declared.clone(),
+            };
+            DataType::Function {
+                params: value.params.iter().map(|p| p.data_type.clone()).collect(),
+                return_type: Box::new(return_type),
+            }
+        }
+        Expr::DefineFunction { value, index, .. } => {
+            typecheck(value, symbols, current_scope_id)?;
+            if let Some((params, return_type)) = function_signature(value, symbols) {
+                let function_type = DataType::Function {
+                    params,
+                    return_type: Box::new(return_type),
+                };
+                symbols.update_symbol_type(function_type, index);
+            }
+            DataType::Unsolved
+        }
+        Expr::Return(value) => typecheck(value, symbols, current_scope_id)?,
+        Expr::Cast {
+            value,
+            data_type,
+            span,
+        } => {
+            let value_type = typecheck(value, symbols, current_scope_id)?;
+            // Checking a tree again narrows arithmetic an earlier pass already wrapped in
+            // this cast; keep just the one.
+            if matches!(value.as_ref(), Expr::Cast { data_type: inner_type, span: inner_span, .. }
+                if inner_type == data_type && inner_span == span)
+            {
+                if let Expr::Cast { value: inner, .. } =
+                    std::mem::replace(value.as_mut(), Expr::Unit)
+                {
+                    *value = inner;
+                }
+            }
+            if !can_cast(
+                &resolve_type(&value_type, symbols, current_scope_id),
+                &resolve_type(data_type, symbols, current_scope_id),
+            ) {
+                let msg = format!(
+                    "Can't cast {} to {}",
+                    describe_type(&value_type, symbols, current_scope_id),
+                    describe_type(data_type, symbols, current_scope_id)
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+            }
+            data_type.clone()
+        }
+        // Meant for values whose type isn't known until they run; one that's known not
+        // to match would always fail.
+        Expr::TypeAssertion {
+            value,
+            data_type,
+            span,
+        } => {
+            let value_type = typecheck(value, symbols, current_scope_id)?;
+            if !types_compatible(
+                &resolve_type(data_type, symbols, current_scope_id),
+                &resolve_type(&value_type, symbols, current_scope_id),
+            ) {
+                let msg = format!(
+                    "A value of type {} is never {}",
+                    describe_type(&value_type, symbols, current_scope_id),
+                    describe_type(data_type, symbols, current_scope_id)
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+            }
+            data_type.clone()
+        }
+        Expr::DefaultValue { data_type, span } => {
+            if default_value(data_type, symbols, current_scope_id).is_none() {
+                let msg = format!(
+                    "{} has no default value",
+                    describe_type(data_type, symbols, current_scope_id)
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+            }
+            data_type.clone()
+        }
+        Expr::DefineType {
+            type_name,
+            definition,
+            span,
+            ..
+        } => {
+            if holds_itself(
+                type_name,
+                definition,
+                symbols,
+                current_scope_id,
+                &mut Vec::new(),
+            ) {
+                let msg = format!(
+                    "'{}' contains itself, so a value of it would never end; make the field \
+                     an Optional {} or a List of {}",
+                    type_name, type_name, type_name
+                );
+                return Err(CompileError::typecheck(&msg, (0, 0)).at_offsets(*span));
+            }
+            DataType::Unsolved
+        }
+        _ => DataType::Unsolved,
+    };
+    narrow_arithmetic(e, &checked_type, symbols, current_scope_id);
+    Ok(checked_type)
+}
@@ -0,0 +1,145 @@
+use crate::interpreter::{call_function, InterpreterResult, RuntimeError};
+use crate::symboltable::SymbolTable;
+use crate::syntax::{Expr, LiteralData};
+use std::error::Error;
+
+// A value of a 'Seq of' type: how to work out its elements rather than the elements
+// themselves, which only get computed as something pulls them through a Cursor. That's
+// what lets a sequence be endless.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Sequence {
+    // start, start + 1, ... up to and including 'end' when there is one.
+    Count {
+        start: i64,
+        end: Option<i64>,
+    },
+    Items(Vec<Expr>),
+    // start, step(start), step(step(start)), ...
+    Iterate {
+        start: Box<Expr>,
+        step: Box<Expr>,
+    },
+    Map {
+        source: Box<Sequence>,
+        function: Box<Expr>,
+    },
+    Filter {
+        source: Box<Sequence>,
+        predicate: Box<Expr>,
+    },
+}
+
+impl Sequence {
+    pub fn cursor(&self) -> Cursor {
+        match self {
+            Sequence::Count { start, end } => Cursor::Count {
+                next: Some(*start),
+                end: *end,
+            },
+            Sequence::Items(items) => Cursor::Items {
+                items: items.clone(),
+                position: 0,
+            },
+            Sequence::Iterate { start, step } => Cursor::Iterate {
+                start: start.clone(),
+                step: step.clone(),
+                previous: None,
+            },
+            Sequence::Map { source, function } => Cursor::Map {
+                source: Box::new(source.cursor()),
+                function: function.clone(),
+            },
+            Sequence::Filter { source, predicate } => Cursor::Filter {
+                source: Box::new(source.cursor()),
+                predicate: predicate.clone(),
+            },
+        }
+    }
+}
+
+// A position in a sequence. Each next() computes just the one element it gives.
+pub enum Cursor {
+    Count {
+        next: Option<i64>,
+        end: Option<i64>,
+    },
+    Items {
+        items: Vec<Expr>,
+        position: usize,
+    },
+    Iterate {
+        start: Box<Expr>,
+        step: Box<Expr>,
+        previous: Option<Box<Expr>>,
+    },
+    Map {
+        source: Box<Cursor>,
+        function: Box<Expr>,
+    },
+    Filter {
+        source: Box<Cursor>,
+        predicate: Box<Expr>,
+    },
+}
+
+impl Cursor {
+    // The next element, or None once the sequence has run out.
+    pub fn next(&mut self, symbols: &mut SymbolTable) -> Result<Option<Expr>, Box<dyn Error>> {
+        match self {
+            Cursor::Count { next, end } => {
+                let Some(current) = *next else {
+                    return Ok(None);
+                };
+                if end.is_some_and(|end| current > end) {
+                    return Ok(None);
+                }
+                *next = current.checked_add(1);
+                Ok(Some(Expr::Literal(LiteralData::Int(current))))
+            }
+            Cursor::Items { items, position } => {
+                let item = items.get(*position).cloned();
+                *position += 1;
+                Ok(item)
+            }
+            Cursor::Iterate {
+                start,
+                step,
+                previous,
+            } => {
+                let current = match previous.take() {
+                    None => start.as_ref().clone(),
+                    Some(previous) => apply(step, *previous, symbols)?,
+                };
+                *previous = Some(Box::new(current.clone()));
+                Ok(Some(current))
+            }
+            Cursor::Map { source, function } => match source.next(symbols)? {
+                Some(item) => Ok(Some(apply(function, item, symbols)?)),
+                None => Ok(None),
+            },
+            Cursor::Filter { source, predicate } => {
+                while let Some(item) = source.next(symbols)? {
+                    if apply(predicate, item.clone(), symbols)?.has_value(&LiteralData::Bool(true))
+                    {
+                        return Ok(Some(item));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+// Calls a function value with one argument.
+fn apply(function: &Expr, arg: Expr, symbols: &mut SymbolTable) -> InterpreterResult {
+    let Expr::Lambda {
+        value,
+        environment,
+        captured,
+    } = function
+    else {
+        let msg = format!("Can't call {} as a function", function);
+        return Err(RuntimeError::new(&msg, None, None).into());
+    };
+    call_function(symbols, value, *environment, captured, vec![arg])
+}
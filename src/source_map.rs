@@ -0,0 +1,47 @@
+use crate::semantic_analysis::Span;
+
+// The source a program came from, for showing where in it an error is.
+pub struct SourceMap<'a> {
+    src: &'a str,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(src: &'a str) -> Self {
+        SourceMap { src }
+    }
+
+    // The span's first line, numbered, with carets under the columns the span covers:
+    //
+    //  2 |     let a: Int = 'x' + 'y';
+    //    |                  ^^^^^^^^^
+    //
+    // A span running onto later lines is marked to the end of its first one, and an empty
+    // span gets a single caret. None when the span isn't within the source, as for errors
+    // that were never located.
+    pub fn snippet(&self, span: Span) -> Option<String> {
+        let (line, column) = span.start;
+        if line == 0 || column == 0 {
+            return None;
+        }
+        let text = self.src.split('\n').nth(line - 1)?.trim_end_matches('\r');
+        let line_length = text.chars().count();
+        if column > line_length + 1 {
+            return None;
+        }
+        let end_column = if span.end.0 == line {
+            span.end.1.min(line_length + 1)
+        } else {
+            line_length + 1
+        };
+        let number = line.to_string();
+        let gutter = " ".repeat(number.len());
+        Some(format!(
+            "{} | {}\n{} | {}{}",
+            number,
+            text,
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(end_column.saturating_sub(column).max(1))
+        ))
+    }
+}
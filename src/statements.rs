@@ -0,0 +1,50 @@
+use crate::grammar::ProgramPartExprParser;
+use crate::syntax::Expr;
+use lalrpop_util::ParseError;
+
+// A parse error that doesn't borrow from the source. Locations are offsets into the whole
+// text given to parse_statements(), not into the one statement.
+pub type StatementParseError = ParseError<usize, String, &'static str>;
+
+// Parses ';'-separated top-level statements one at a time so a syntax error in one of them
+// doesn't stop the rest from parsing; editors and the REPL can still analyze those. Byte
+// offsets kept in a tree count from the start of its own statement, so each tree comes
+// with the offset of that statement in 'src'; adding it gives offsets on the same base as
+// the errors'.
+pub fn parse_statements(src: &str) -> Vec<Result<(usize, Expr), StatementParseError>> {
+    let parser = ProgramPartExprParser::new();
+    split_statements(src)
+        .into_iter()
+        .map(|(start, text)| {
+            parser.parse(text).map(|tree| (start, tree)).map_err(|e| {
+                e.map_token(|t| t.to_string())
+                    .map_location(|location| location + start)
+            })
+        })
+        .collect()
+}
+
+// Only a ';' outside of brackets and strings ends a statement. A stray closing bracket
+// doesn't count against the next statement.
+fn split_statements(src: &str) -> Vec<(usize, &str)> {
+    let mut statements = Vec::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut start = 0;
+    for (i, c) in src.char_indices() {
+        match c {
+            '\'' => in_string = !in_string,
+            _ if in_string => (),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = usize::saturating_sub(depth, 1),
+            ';' if depth == 0 => {
+                statements.push((start, &src[start..i]));
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    statements.push((start, &src[start..]));
+    statements.retain(|(_, text)| !text.trim().is_empty());
+    statements
+}
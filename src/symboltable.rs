@@ -1,61 +1,258 @@
-use crate::semantic_analysis::CompileError;
+use crate::builtins::BuiltinRegistry;
+use crate::host::Host;
+use crate::modules::ModuleLoader;
+use crate::semantic_analysis::{CompileError, Strictness, DEFAULT_MAX_ERRORS};
 use crate::syntax::DataType;
 use crate::syntax::Expr;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 const TRACE: bool = true;
 
+// How deep the recursive walks over a parse tree (analysis, checking, interpreting) may go
+// before giving up. Each level takes several stack frames, so the interpreter has to run
+// with a stack big enough for this many.
+pub const MAX_NESTING_DEPTH: usize = 10000;
+
 #[derive(Clone, Debug)]
 pub struct Scope {
     pub parent: Option<usize>,
     pub data: Vec<Expr>,
+    pub symbol_types: Vec<DataType>,
     pub types: Vec<DataType>,
     pub runtime_value: Vec<Expr>,
     pub name: HashMap<usize, String>,
     pub index: HashMap<String, usize>,
     pub type_name: HashMap<usize, String>,
     pub type_index: HashMap<String, usize>,
+    // Where each type was defined, for pointing back at it when its name is reused.
+    pub type_spans: Vec<(usize, usize)>,
+    // The scopes of modules imported here: those whose names can be used as if defined
+    // here, and those reached through a namespace, as in 'math.square'.
+    pub imports: Vec<usize>,
+    pub namespaces: HashMap<String, usize>,
 }
 
 impl Scope {
+    #[allow(dead_code)]
     pub fn borrow_runtime_data(&self, index: usize) -> &Expr {
         &self.runtime_value[index]
     }
 
     pub fn print_debug(&self) {
         for kv in &self.index {
-            println!("{} : {}", kv.0, kv.1);
+            eprintln!("{} : {}", kv.0, kv.1);
         }
     }
 }
 
-pub struct SymbolTable(Vec<Scope>);
+// A copy of every scope taken by SymbolTable::snapshot(), along with what analysis
+// recorded about them, for undoing whatever an input did to the table.
+#[derive(Clone, Debug)]
+pub struct ScopeSnapshot {
+    scopes: Vec<Scope>,
+    warnings: Vec<CompileError>,
+    let_bindings: Vec<LetBinding>,
+    read_symbols: HashSet<(usize, usize)>,
+}
+
+// Runtime values of a function's scope and the scopes inside it, saved around a call so
+// that a recursive call doesn't clobber its caller's parameters and locals.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Frame(Vec<(usize, Vec<Expr>)>);
+
+pub struct SymbolTable {
+    scopes: Vec<Scope>,
+    // What the running program may reach outside itself, and the state that goes with it.
+    pub host: Host,
+    // The functions programs get without defining them. Registering one here before
+    // preparing a program makes it callable from that program.
+    pub builtins: BuiltinRegistry,
+    // Problems found while preparing a program that don't stop it from running.
+    pub warnings: Vec<CompileError>,
+    // Type errors found after the first one, which typecheck() returns, so that prepare()
+    // can report them all.
+    pub later_errors: Vec<CompileError>,
+    // Report definitions named like a builtin function as errors instead of warnings.
+    pub deny_builtin_shadowing: bool,
+    pub strictness: Strictness,
+    // Each 'let' prepare() saw and the indices that were read, for finding unused
+    // variables.
+    pub let_bindings: Vec<LetBinding>,
+    pub read_symbols: HashSet<(usize, usize)>,
+    // The most errors preparing a program reports, and how many past that the last
+    // prepare() left out.
+    pub max_errors: usize,
+    pub omitted_errors: usize,
+    // Finds the files that 'import' names.
+    pub modules: ModuleLoader,
+    nesting_depth: usize,
+    // How many loops the type checker is inside of in the function it's checking, which
+    // has to be at least one for 'break' or 'continue'.
+    pub loop_depth: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct LetBinding {
+    pub name: String,
+    pub index: (usize, usize),
+    pub span: (usize, usize),
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl SymbolTable {
     pub fn new() -> Self {
-        let mut symbols = SymbolTable(Vec::new());
+        let mut symbols = SymbolTable {
+            scopes: Vec::new(),
+            host: Host::new(),
+            builtins: BuiltinRegistry::new(),
+            warnings: Vec::new(),
+            later_errors: Vec::new(),
+            deny_builtin_shadowing: false,
+            strictness: Strictness::default(),
+            let_bindings: Vec::new(),
+            read_symbols: HashSet::new(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            omitted_errors: 0,
+            modules: ModuleLoader::default(),
+            nesting_depth: 0,
+            loop_depth: 0,
+        };
         symbols.create_scope(None);
         symbols
     }
 
     pub fn print_debug(&self) {
-        for (s, scope) in self.0.iter().enumerate() {
-            println!("Scope {} ------- ", s);
+        for (s, scope) in self.scopes.iter().enumerate() {
+            eprintln!("Scope {} ------- ", s);
             scope.print_debug();
         }
     }
 
+    // Called going into a nested expression; false means it's nested too deeply to go on.
+    pub fn enter_nested(&mut self) -> bool {
+        if self.nesting_depth >= MAX_NESTING_DEPTH {
+            false
+        } else {
+            self.nesting_depth += 1;
+            true
+        }
+    }
+
+    pub fn leave_nested(&mut self) {
+        self.nesting_depth -= 1;
+    }
+
+    pub fn nesting_depth(&self) -> usize {
+        self.nesting_depth
+    }
+
+    // The names bound directly in a scope with their types, in the order they were
+    // defined: variables and functions first, then types made with 'type'. For a whole
+    // program that's its outermost block's scope (or scope 0 for REPL input).
+    pub fn exported_types(&self, scope_id: usize) -> Vec<(String, DataType)> {
+        let scope = &self.scopes[scope_id];
+        let symbols = (0..scope.symbol_types.len()).filter_map(|id| {
+            let name = scope.name.get(&id)?;
+            Some((name.clone(), scope.symbol_types[id].clone()))
+        });
+        let types = (0..scope.types.len()).filter_map(|id| {
+            let name = scope.type_name.get(&id)?;
+            Some((name.clone(), scope.types[id].clone()))
+        });
+        symbols.chain(types).collect()
+    }
+
+    pub fn snapshot(&self) -> ScopeSnapshot {
+        ScopeSnapshot {
+            scopes: self.scopes.clone(),
+            warnings: self.warnings.clone(),
+            let_bindings: self.let_bindings.clone(),
+            read_symbols: self.read_symbols.clone(),
+        }
+    }
+
+    // Puts back the scopes, and the warnings and variable uses analysis found in them, as
+    // they were at the snapshot. Host state like the random number generator isn't part
+    // of it.
+    pub fn restore(&mut self, snapshot: ScopeSnapshot) {
+        self.scopes = snapshot.scopes;
+        self.warnings = snapshot.warnings;
+        self.let_bindings = snapshot.let_bindings;
+        self.read_symbols = snapshot.read_symbols;
+    }
+
+    pub fn save_frame(&self, scope_id: usize) -> Frame {
+        // Scopes are only ever created after their parents, so anything inside this
+        // one comes later in the list.
+        let saved = (scope_id..self.scopes.len())
+            .filter(|id| self.is_within(*id, scope_id))
+            .map(|id| (id, self.scopes[id].runtime_value.clone()))
+            .collect();
+        Frame(saved)
+    }
+
+    pub fn restore_frame(&mut self, frame: Frame) {
+        for (id, runtime_value) in frame.0 {
+            self.scopes[id].runtime_value = runtime_value;
+        }
+    }
+
+    // Puts a frame's values in place, giving back the ones they replaced so the caller
+    // can restore those afterwards.
+    pub fn swap_frame(&mut self, frame: Frame) -> Frame {
+        let replaced = frame
+            .0
+            .iter()
+            .map(|(id, _)| (*id, self.scopes[*id].runtime_value.clone()))
+            .collect();
+        self.restore_frame(frame);
+        Frame(replaced)
+    }
+
+    // The values a lambda created inside 'scope_id' needs once that scope's call has
+    // returned: the current ones for everything in 'scope_id' and for the scopes the
+    // function being called had itself closed over.
+    pub fn capture_frame(&self, closed_over: &Frame, scope_id: usize) -> Frame {
+        let Frame(mut captured) = self.save_frame(scope_id);
+        for (id, _) in &closed_over.0 {
+            if !captured.iter().any(|(captured_id, _)| captured_id == id) {
+                captured.push((*id, self.scopes[*id].runtime_value.clone()));
+            }
+        }
+        Frame(captured)
+    }
+
+    pub fn is_within(&self, scope_id: usize, outer_scope_id: usize) -> bool {
+        let mut current = Some(scope_id);
+        while let Some(id) = current {
+            if id == outer_scope_id {
+                return true;
+            }
+            current = self.scopes[id].parent;
+        }
+        false
+    }
+
     pub fn create_scope(&mut self, parent: Option<usize>) -> usize {
-        self.0.push(Scope::new(parent));
+        self.scopes.push(Scope::new(parent));
         if TRACE {
-            println!("Add scope {} with parent {:?}", self.0.len() - 1, &parent);
+            eprintln!(
+                "Add scope {} with parent {:?}",
+                self.scopes.len() - 1,
+                &parent
+            );
         }
-        self.0.len() - 1
+        self.scopes.len() - 1
     }
 
     // Determine if a symbol is in the current scope or any of its parent scopes.
     pub fn get_index_in_scope(&self, symbol_name: &str, current_scope_id: usize) -> Option<usize> {
-        self.0[current_scope_id].get_index(symbol_name)
+        self.scopes[current_scope_id].get_index(symbol_name)
     }
 
     pub fn find_index_reachable_from(
@@ -64,15 +261,26 @@ impl SymbolTable {
         current_scope_id: usize,
     ) -> Option<(usize, usize)> {
         if TRACE {
-            println!(
+            eprintln!(
                 "Find  index for {} in scope {}",
                 symbol_name, current_scope_id
             )
         }
-        match self.get_index_in_scope(symbol_name, current_scope_id) {
-            Some(index) => Some((current_scope_id, index)),
+        if let Some((namespace, member)) = symbol_name.split_once('.') {
+            let module_scope_id =
+                self.find_namespace_reachable_from(namespace, current_scope_id)?;
+            return self
+                .get_index_in_scope(member, module_scope_id)
+                .map(|index| (module_scope_id, index));
+        }
+        let scope = &self.scopes[current_scope_id];
+        let found = std::iter::once(current_scope_id)
+            .chain(scope.imports.iter().copied())
+            .find_map(|id| Some((id, self.get_index_in_scope(symbol_name, id)?)));
+        match found {
+            Some(index) => Some(index),
             None => {
-                let parent_scope_id = self.0[current_scope_id].parent;
+                let parent_scope_id = self.scopes[current_scope_id].parent;
                 match parent_scope_id {
                     None => None,
                     Some(scope_id) => self.find_index_reachable_from(symbol_name, scope_id),
@@ -81,15 +289,90 @@ impl SymbolTable {
         }
     }
 
+    pub fn get_type_index_in_scope(
+        &self,
+        type_name: &str,
+        current_scope_id: usize,
+    ) -> Option<usize> {
+        self.scopes[current_scope_id]
+            .type_index
+            .get(type_name)
+            .copied()
+    }
+
+    // Like find_index_reachable_from() but for names defined with 'type'.
+    pub fn find_type_reachable_from(
+        &self,
+        type_name: &str,
+        current_scope_id: usize,
+    ) -> Option<(usize, usize)> {
+        if let Some((namespace, member)) = type_name.split_once('.') {
+            let module_scope_id =
+                self.find_namespace_reachable_from(namespace, current_scope_id)?;
+            return self
+                .get_type_index_in_scope(member, module_scope_id)
+                .map(|index| (module_scope_id, index));
+        }
+        let scope = &self.scopes[current_scope_id];
+        let found = std::iter::once(current_scope_id)
+            .chain(scope.imports.iter().copied())
+            .find_map(|id| Some((id, self.get_type_index_in_scope(type_name, id)?)));
+        match found {
+            Some(index) => Some(index),
+            None => {
+                let parent_scope_id = self.scopes[current_scope_id].parent;
+                match parent_scope_id {
+                    None => None,
+                    Some(scope_id) => self.find_type_reachable_from(type_name, scope_id),
+                }
+            }
+        }
+    }
+
+    // The scope of the module imported under 'namespace', if one is.
+    pub fn find_namespace_reachable_from(
+        &self,
+        namespace: &str,
+        current_scope_id: usize,
+    ) -> Option<usize> {
+        let scope = &self.scopes[current_scope_id];
+        match scope.namespaces.get(namespace) {
+            Some(module_scope_id) => Some(*module_scope_id),
+            None => self.find_namespace_reachable_from(namespace, scope.parent?),
+        }
+    }
+
+    // Makes what the module at 'module_scope_id' defines visible in a scope, under a
+    // namespace or directly.
+    pub fn add_import(&mut self, scope_id: usize, namespace: Option<&str>, module_scope_id: usize) {
+        let scope = &mut self.scopes[scope_id];
+        match namespace {
+            Some(namespace) => {
+                scope
+                    .namespaces
+                    .insert(namespace.to_string(), module_scope_id);
+            }
+            None if !scope.imports.contains(&module_scope_id) => {
+                scope.imports.push(module_scope_id);
+            }
+            None => (),
+        }
+    }
+
+    pub fn get_type_definition(&self, index: &(usize, usize)) -> Option<DataType> {
+        Some(self.scopes.get(index.0)?.types.get(index.1)?.clone())
+    }
+
     pub fn add_type(
         &mut self,
         name: &str,
         value: &DataType,
         scope: usize,
+        span: (usize, usize),
     ) -> Result<usize, CompileError> {
-        let added_index = self.0[scope].add_type(name, value.clone());
+        let added_index = self.scopes[scope].add_type(name, value.clone(), span);
         if TRACE {
-            println!(
+            eprintln!(
                 "Added '{}' to symbol table:scope {},  at index {:?} with value '{:?}'",
                 name,
                 &scope,
@@ -106,9 +389,9 @@ impl SymbolTable {
         value: Expr,
         scope: usize,
     ) -> Result<usize, CompileError> {
-        let added_index = self.0[scope].add(name, value.clone());
+        let added_index = self.scopes[scope].add(name, value.clone());
         if TRACE {
-            println!(
+            eprintln!(
                 "Added '{}' to symbol table:scope {},  at index {:?} with value '{:?}'",
                 name,
                 &scope,
@@ -120,23 +403,37 @@ impl SymbolTable {
     }
 
     pub fn update_compiletime_symbol_value(&mut self, value: Expr, index: &(usize, usize)) {
-        self.0[index.0].data[index.1] = value;
+        self.scopes[index.0].data[index.1] = value;
+    }
+
+    pub fn update_symbol_type(&mut self, data_type: DataType, index: &(usize, usize)) {
+        self.scopes[index.0].symbol_types[index.1] = data_type;
+    }
+
+    pub fn get_symbol_type(&self, index: &(usize, usize)) -> Option<DataType> {
+        Some(self.scopes.get(index.0)?.symbol_types.get(index.1)?.clone())
     }
 
     pub fn update_runtime_value(&mut self, value: Expr, index: &(usize, usize)) {
-        self.0[index.0].runtime_value[index.1] = value;
+        self.scopes[index.0].runtime_value[index.1] = value;
     }
 
     pub fn get_compiletime_value(&self, index: &(usize, usize)) -> Option<Expr> {
-        Some(self.0.get(index.0)?.data.get(index.1)?.clone())
+        Some(self.scopes.get(index.0)?.data.get(index.1)?.clone())
     }
 
     pub fn get_runtime_value(&self, index: &(usize, usize)) -> Option<Expr> {
-        Some(self.0.get(index.0)?.runtime_value.get(index.1)?.clone())
+        Some(
+            self.scopes
+                .get(index.0)?
+                .runtime_value
+                .get(index.1)?
+                .clone(),
+        )
     }
 
     pub fn borrow_runtime_value(&self, index: (usize, usize)) -> &Expr {
-        &self.0[index.0].runtime_value[index.1]
+        &self.scopes[index.0].runtime_value[index.1]
     }
 }
 
@@ -145,12 +442,16 @@ impl Scope {
         Self {
             parent,
             data: Vec::new(),
+            symbol_types: Vec::new(),
             types: Vec::new(),
             runtime_value: Vec::new(),
             name: HashMap::new(),
             type_name: HashMap::new(),
             index: HashMap::new(),
             type_index: HashMap::new(),
+            type_spans: Vec::new(),
+            imports: Vec::new(),
+            namespaces: HashMap::new(),
         }
     }
 
@@ -166,6 +467,7 @@ impl Scope {
             ))
         } else {
             self.data.push(value.clone());
+            self.symbol_types.push(DataType::Unsolved);
             self.runtime_value.push(value.copy_to_runtime_data());
             let new_index = self.data.len() - 1;
             self.index.insert(name.to_string(), new_index);
@@ -174,14 +476,22 @@ impl Scope {
         }
     }
 
-    pub fn add_type(&mut self, name: &str, value: DataType) -> Result<usize, CompileError> {
-        if self.type_index.contains_key(name) {
+    pub fn add_type(
+        &mut self,
+        name: &str,
+        value: DataType,
+        span: (usize, usize),
+    ) -> Result<usize, CompileError> {
+        if let Some(existing) = self.type_index.get(name) {
             Err(CompileError::name(
                 &format!("Type already defined in this scope: {}", name),
                 (0, 0),
-            ))
+            )
+            .at_offsets(span)
+            .previously_at(self.type_spans[*existing]))
         } else {
             self.types.push(value.clone());
+            self.type_spans.push(span);
             let new_index = self.types.len() - 1;
             self.type_index.insert(name.to_string(), new_index);
             self.type_name.insert(new_index, name.to_string());
@@ -18,16 +18,27 @@ arg-list := EPSILON
 */
 #![allow(unused_variables)]
 
-use std::collections::HashMap;
+use crate::sequence::Sequence;
+use crate::symboltable::Frame;
+use indexmap::{IndexMap, IndexSet};
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Operator {
     Div,
+    // '%': the remainder of Int division, with the sign of the left side.
+    Modulo,
     Mul,
     Add,
     Sub,
+    // '+%' and '*%': Int arithmetic that wraps around instead of overflowing.
+    WrappingAdd,
+    WrappingMul,
+    // 'a ?: b' is a's value if it's some, otherwise b. b is only evaluated when needed.
+    Coalesce,
     Gt,
     Lt,
     Gte,
@@ -39,7 +50,11 @@ pub enum Operator {
     Not,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// The index the parser gives every name until semantic analysis resolves it. Unlike
+// (0, 0), the first symbol of the global scope, it's never a real slot.
+pub const UNRESOLVED: (usize, usize) = (usize::MAX, usize::MAX);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Param {
     pub name: String,
     pub data_type: DataType,
@@ -47,14 +62,21 @@ pub struct Param {
     pub index: (usize, usize),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum DataType {
     Unsolved,
+    // Written in source to opt out of type checking for a value; unlike Unsolved it's a
+    // deliberate choice, not something the checker couldn't work out.
+    Any,
     Optional(Box<DataType>),
     Range(Box<Expr>),
     Str,
+    // Int and Flt are 64 bits wide (and can be written I64 and F64); the narrower
+    // widths are their own types.
     Int,
     Flt,
+    I32,
+    F32,
     Bool,
     Map {
         key_type: Box<DataType>,
@@ -64,17 +86,89 @@ pub enum DataType {
         element_type: Box<DataType>,
     },
     Set(Box<DataType>),
+    // Elements worked out only as they're needed; see sequence.rs.
+    Seq(Box<DataType>),
     Enum(Vec<String>),
     Struct(Vec<Param>),
+    // A reference by name to a type defined with 'type'; resolved through the symbol table.
+    TypeRef(String),
+    // Defined with 'type Celsius = new Int': values are stored like the base type's but
+    // don't mix with it, or with other newtypes, without an 'as' cast.
+    Newtype {
+        name: String,
+        base: Box<DataType>,
+    },
+    Function {
+        params: Vec<DataType>,
+        return_type: Box<DataType>,
+    },
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::Unsolved => write!(f, "Unsolved"),
+            DataType::Any => write!(f, "Any"),
+            DataType::Optional(inner) => write!(f, "Optional {}", inner),
+            DataType::Range(_) => write!(f, "Range"),
+            DataType::Str => write!(f, "Str"),
+            DataType::Int => write!(f, "Int"),
+            DataType::Flt => write!(f, "Flt"),
+            DataType::I32 => write!(f, "I32"),
+            DataType::F32 => write!(f, "F32"),
+            DataType::Bool => write!(f, "Bool"),
+            DataType::Map {
+                key_type,
+                value_type,
+            } => write!(f, "Map of {} to {}", key_type, value_type),
+            DataType::List { element_type } => write!(f, "List of {}", element_type),
+            DataType::Set(element_type) => write!(f, "Set of {}", element_type),
+            DataType::Seq(element_type) => write!(f, "Seq of {}", element_type),
+            DataType::Enum(variants) => write!(f, "({})", variants.join(", ")),
+            DataType::Struct(fields) => {
+                let printed_fields = fields
+                    .iter()
+                    .map(|p| format!("{}: {}", p.name, p.data_type))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "struct ({})", printed_fields)
+            }
+            DataType::TypeRef(name) | DataType::Newtype { name, .. } => write!(f, "{}", name),
+            DataType::Function {
+                params,
+                return_type,
+            } => {
+                let printed_params = params
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "function({}): {}", printed_params, return_type)
+            }
+        }
+    }
+}
+
+// Positional arguments (as used by builtins) are stored with an empty name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct KeywordArg {
     pub name: String,
     pub value: Expr,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// One arm of a match: 'pattern if guard => value'. The pattern is a literal, an enum
+// value or '_'; the guard, if any, only runs once the pattern has matched.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MatchArm {
+    pub pattern: Expr,
+    pub guard: Option<Expr>,
+    pub value: Expr,
+}
+
+// Floats compare and hash by their bits so that trees holding them can be map keys: NaN
+// equals itself here and 0.0 doesn't equal -0.0. Lift's own '=' goes through
+// compare_values() instead.
+#[derive(Clone, Debug)]
 pub enum LiteralData {
     Int(i64),
     Flt(f64),
@@ -82,6 +176,32 @@ pub enum LiteralData {
     Bool(bool),
 }
 
+impl PartialEq for LiteralData {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LiteralData::Int(l), LiteralData::Int(r)) => l == r,
+            (LiteralData::Flt(l), LiteralData::Flt(r)) => l.to_bits() == r.to_bits(),
+            (LiteralData::Str(l), LiteralData::Str(r)) => l == r,
+            (LiteralData::Bool(l), LiteralData::Bool(r)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for LiteralData {}
+
+impl Hash for LiteralData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            LiteralData::Int(i) => i.hash(state),
+            LiteralData::Flt(f) => f.to_bits().hash(state),
+            LiteralData::Str(s) => s.hash(state),
+            LiteralData::Bool(b) => b.hash(state),
+        }
+    }
+}
+
 impl From<KeyData> for LiteralData {
     fn from(data: KeyData) -> LiteralData {
         match data {
@@ -153,25 +273,59 @@ impl From<Expr> for LiteralData {
     }
 }
 
+// The shortest text that reads back as the same Flt, which is what Rust's Display gives,
+// except that whole numbers keep a '.0' so they don't read back as an Int. F32 values are
+// held as an f64 rounded to f32 precision, so a value an f32 holds exactly is printed as
+// that f32; an F32 0.1 prints as 0.1 rather than 0.10000000149011612. Everything that
+// prints a Flt goes through here, so output is the same on every platform.
+pub fn format_flt(value: f64) -> String {
+    let narrow = value as f32;
+    let text = if narrow as f64 == value {
+        narrow.to_string()
+    } else {
+        value.to_string()
+    };
+    if value.is_finite() && !text.contains('.') {
+        text + ".0"
+    } else {
+        text
+    }
+}
+
 impl std::fmt::Display for LiteralData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LiteralData::Int(i) => write!(f, "{}", i),
-            LiteralData::Flt(fl) => write!(f, "{}", fl),
+            LiteralData::Flt(fl) => write!(f, "{}", format_flt(*fl)),
             LiteralData::Bool(b) => write!(f, "{}", b),
             LiteralData::Str(s) => write!(f, "{}", &s),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Function {
     pub params: Vec<Param>,
     pub return_type: DataType,
     pub body: Box<Expr>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Function values print as their signature, like "function(x: Int): Int".
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let printed_params = self
+            .params
+            .iter()
+            .map(|p| format!("{}: {}", p.name, p.data_type))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        write!(f, "function({}): {}", printed_params, self.return_type)
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Expr {
     Program {
         body: Vec<Expr>,
@@ -179,6 +333,9 @@ pub enum Expr {
     },
     Block {
         body: Vec<Expr>,
+        // Byte offsets of each statement in the body, where it came from source. Blocks
+        // made up by desugaring can leave them out or use (0, 0).
+        spans: Vec<(usize, usize)>,
         environment: usize,
     },
     Output {
@@ -192,10 +349,14 @@ pub enum Expr {
         key_type: DataType,
         value_type: DataType,
         data: Vec<(KeyData, Expr)>,
+        // Where each entry is in the source, for type errors.
+        spans: Vec<(usize, usize)>,
     },
     ListLiteral {
         data_type: DataType,
         data: Vec<Expr>,
+        // Where each item is in the source, for type errors.
+        spans: Vec<(usize, usize)>,
     },
     Range(LiteralData, LiteralData),
 
@@ -207,16 +368,34 @@ pub enum Expr {
         data_type: DataType,
         data: Vec<Expr>,
     },
+    // Entries are kept in insertion order so anything iterating over a map
+    // (printing, conversions) gives reproducible output.
     RuntimeMap {
         key_type: DataType,
         value_type: DataType,
-        data: HashMap<KeyData, Expr>,
+        data: IndexMap<KeyData, Expr>,
+    },
+    // Like map keys, set elements are literals other than floats; they're kept in
+    // insertion order.
+    RuntimeSet {
+        data_type: DataType,
+        data: IndexSet<KeyData>,
+    },
+    // A value of an Optional type: None when there's nothing there.
+    RuntimeOptional(Option<Box<Expr>>),
+    RuntimeSeq(Sequence),
+    // Fields are kept in the order of the struct's type definition.
+    RuntimeStruct {
+        type_name: String,
+        fields: Vec<(String, Expr)>,
     },
 
     BinaryExpr {
         left: Box<Expr>,
         op: Operator,
         right: Box<Expr>,
+        // Byte offsets of the whole expression in its source.
+        span: (usize, usize),
     },
     UnaryExpr {
         op: Operator,
@@ -227,6 +406,11 @@ pub enum Expr {
         value: Box<Expr>,
         index: (usize, usize),
     },
+    // 'a, b := b, a' evaluates every value before updating any of the variables.
+    MultipleAssign {
+        targets: Vec<(String, (usize, usize))>,
+        values: Vec<Expr>,
+    },
     Variable {
         name: String,
         index: (usize, usize),
@@ -235,6 +419,50 @@ pub enum Expr {
         fn_name: String,
         index: (usize, usize),
         args: Vec<KeywordArg>,
+        // Written as 'x.f(a)', which passes x as the first argument.
+        method: bool,
+        span: (usize, usize),
+    },
+    // A call to a function supplied by the interpreter; semantic analysis rewrites
+    // a Call into this when the name isn't defined by the program.
+    BuiltinCall {
+        fn_name: String,
+        args: Vec<Expr>,
+        span: (usize, usize),
+    },
+    // Semantic analysis rewrites a Call into this when the name refers to a struct type.
+    StructLiteral {
+        type_name: String,
+        index: (usize, usize),
+        fields: Vec<KeywordArg>,
+    },
+    FieldAccess {
+        target: Box<Expr>,
+        field: String,
+    },
+    // 'target?.field': none when the target is none, otherwise some of the field. A field
+    // that's already optional isn't wrapped again, so chains stay one level deep.
+    OptionalFieldAccess {
+        target: Box<Expr>,
+        field: String,
+    },
+    // 'value as Celsius': the value unchanged, typed as a newtype or the newtype's base.
+    Cast {
+        value: Box<Expr>,
+        data_type: DataType,
+        span: (usize, usize),
+    },
+    // 'assert_type(value, Int)': the value typed as the given type, checked when it runs.
+    TypeAssertion {
+        value: Box<Expr>,
+        data_type: DataType,
+        span: (usize, usize),
+    },
+    // 'default(Int)': the type's zero value, such as 0, '', an empty list or a struct
+    // with each field at its own default.
+    DefaultValue {
+        data_type: DataType,
+        span: (usize, usize),
     },
     DefineFunction {
         fn_name: String,
@@ -244,61 +472,562 @@ pub enum Expr {
     Lambda {
         value: Function,
         environment: usize,
+        // Values of the enclosing function's scopes, taken when the lambda is returned
+        // out of the call that made it; empty until then.
+        captured: Frame,
     },
     Let {
         var_name: String,
         index: (usize, usize),
         data_type: DataType,
         value: Box<Expr>,
+        // Byte offsets of the value in the source, for pointing errors at it.
+        span: (usize, usize),
     },
     DefineType {
         type_name: String,
         definition: DataType,
         index: (usize, usize),
+        span: (usize, usize),
     },
     If {
         cond: Box<Expr>,
         then: Box<Expr>,
         final_else: Box<Expr>,
+        // Where the condition is in the source.
+        cond_span: (usize, usize),
     },
+    // Arms are tried in order and the first that matches gives the result.
     Match {
         cond: Box<Expr>,
-        against: Vec<(Expr, Expr)>,
+        against: Vec<MatchArm>,
+    },
+    // The '_' pattern, which matches anything.
+    Wildcard,
+    // A variant of an enum type, written 'Color.red'. Semantic analysis rewrites a
+    // FieldAccess on an enum type's name into this.
+    EnumValue {
+        type_name: String,
+        variant: String,
     },
+    // 'step' runs after every pass through the body, including one cut short by
+    // 'continue'. Only desugared loops have one.
     While {
         cond: Box<Expr>,
         body: Box<Expr>,
+        step: Option<Box<Expr>>,
+    },
+    // Leave the innermost loop, or skip to its next pass.
+    Break,
+    Continue,
+    // [element for var_name in source if filter], or with a key {key: element for ...},
+    // which makes a Map. The variable belongs to the comprehension's own scope,
+    // 'environment', where the key, element and filter get evaluated.
+    Comprehension {
+        var_name: String,
+        index: (usize, usize),
+        source: Box<Expr>,
+        key: Option<Box<Expr>>,
+        element: Box<Expr>,
+        filter: Option<Box<Expr>>,
+        environment: usize,
+    },
+    // for var_name in source { body }, over the same sources as a comprehension. The
+    // variable belongs to the loop's own scope, 'environment', where the body runs.
+    ForIn {
+        var_name: String,
+        index: (usize, usize),
+        source: Box<Expr>,
+        body: Box<Expr>,
+        environment: usize,
+    },
+    // import 'path' [as namespace]. Analysis loads the file into 'module', a block whose
+    // scope the importing one can then see into.
+    Import {
+        path: String,
+        namespace: Option<String>,
+        module: Option<Box<Expr>>,
+        span: (usize, usize),
     },
     Return(Box<Expr>),
     Unit,
 }
+// Hashes agree with '==', so equal trees can key a cache. Maps and sets are equal whatever
+// order their entries went in, so those hash their entries in a way that ignores order.
+impl Hash for Expr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Expr::Program { body, environment } => {
+                body.hash(state);
+                environment.hash(state);
+            }
+            Expr::Block {
+                body,
+                spans,
+                environment,
+            } => {
+                body.hash(state);
+                spans.hash(state);
+                environment.hash(state);
+            }
+            Expr::Output { data } => data.hash(state),
+            Expr::Literal(d) | Expr::RuntimeData(d) => d.hash(state),
+            Expr::MapLiteral {
+                key_type,
+                value_type,
+                data,
+                ..
+            } => {
+                key_type.hash(state);
+                value_type.hash(state);
+                data.hash(state);
+            }
+            Expr::ListLiteral {
+                data_type, data, ..
+            }
+            | Expr::RuntimeList { data_type, data } => {
+                data_type.hash(state);
+                data.hash(state);
+            }
+            Expr::Range(start, end) => {
+                start.hash(state);
+                end.hash(state);
+            }
+            Expr::RuntimeMap {
+                key_type,
+                value_type,
+                data,
+            } => {
+                key_type.hash(state);
+                value_type.hash(state);
+                hash_unordered(data.iter(), state);
+            }
+            Expr::RuntimeSet { data_type, data } => {
+                data_type.hash(state);
+                hash_unordered(data.iter(), state);
+            }
+            Expr::RuntimeOptional(value) => value.hash(state),
+            Expr::RuntimeSeq(sequence) => sequence.hash(state),
+            Expr::RuntimeStruct { type_name, fields } => {
+                type_name.hash(state);
+                fields.hash(state);
+            }
+            Expr::BinaryExpr {
+                left,
+                op,
+                right,
+                span,
+            } => {
+                left.hash(state);
+                op.hash(state);
+                right.hash(state);
+                span.hash(state);
+            }
+            Expr::UnaryExpr { op, expr } => {
+                op.hash(state);
+                expr.hash(state);
+            }
+            Expr::Assign { name, value, index } => {
+                name.hash(state);
+                value.hash(state);
+                index.hash(state);
+            }
+            Expr::MultipleAssign { targets, values } => {
+                targets.hash(state);
+                values.hash(state);
+            }
+            Expr::Variable { name, index } => {
+                name.hash(state);
+                index.hash(state);
+            }
+            Expr::Call {
+                fn_name,
+                index,
+                args,
+                method,
+                span,
+            } => {
+                fn_name.hash(state);
+                index.hash(state);
+                args.hash(state);
+                method.hash(state);
+                span.hash(state);
+            }
+            Expr::BuiltinCall {
+                fn_name,
+                args,
+                span,
+            } => {
+                fn_name.hash(state);
+                args.hash(state);
+                span.hash(state);
+            }
+            Expr::StructLiteral {
+                type_name,
+                index,
+                fields,
+            } => {
+                type_name.hash(state);
+                index.hash(state);
+                fields.hash(state);
+            }
+            Expr::FieldAccess { target, field } | Expr::OptionalFieldAccess { target, field } => {
+                target.hash(state);
+                field.hash(state);
+            }
+            Expr::Cast {
+                value,
+                data_type,
+                span,
+            }
+            | Expr::TypeAssertion {
+                value,
+                data_type,
+                span,
+            } => {
+                value.hash(state);
+                data_type.hash(state);
+                span.hash(state);
+            }
+            Expr::DefaultValue { data_type, span } => {
+                data_type.hash(state);
+                span.hash(state);
+            }
+            Expr::DefineFunction {
+                fn_name,
+                index,
+                value,
+            } => {
+                fn_name.hash(state);
+                index.hash(state);
+                value.hash(state);
+            }
+            Expr::Lambda {
+                value,
+                environment,
+                captured,
+            } => {
+                value.hash(state);
+                environment.hash(state);
+                captured.hash(state);
+            }
+            Expr::Let {
+                var_name,
+                index,
+                data_type,
+                value,
+                span,
+            } => {
+                var_name.hash(state);
+                index.hash(state);
+                data_type.hash(state);
+                value.hash(state);
+                span.hash(state);
+            }
+            Expr::DefineType {
+                type_name,
+                definition,
+                index,
+                span,
+            } => {
+                type_name.hash(state);
+                definition.hash(state);
+                index.hash(state);
+                span.hash(state);
+            }
+            Expr::If {
+                cond,
+                then,
+                final_else,
+                cond_span,
+            } => {
+                cond.hash(state);
+                then.hash(state);
+                final_else.hash(state);
+                cond_span.hash(state);
+            }
+            Expr::Match { cond, against } => {
+                cond.hash(state);
+                against.hash(state);
+            }
+            Expr::EnumValue { type_name, variant } => {
+                type_name.hash(state);
+                variant.hash(state);
+            }
+            Expr::While { cond, body, step } => {
+                cond.hash(state);
+                body.hash(state);
+                step.hash(state);
+            }
+            Expr::Comprehension {
+                var_name,
+                index,
+                source,
+                key,
+                element,
+                filter,
+                environment,
+            } => {
+                var_name.hash(state);
+                index.hash(state);
+                source.hash(state);
+                key.hash(state);
+                element.hash(state);
+                filter.hash(state);
+                environment.hash(state);
+            }
+            Expr::ForIn {
+                var_name,
+                index,
+                source,
+                body,
+                environment,
+            } => {
+                var_name.hash(state);
+                index.hash(state);
+                source.hash(state);
+                body.hash(state);
+                environment.hash(state);
+            }
+            Expr::Import {
+                path,
+                namespace,
+                module,
+                span,
+            } => {
+                path.hash(state);
+                namespace.hash(state);
+                module.hash(state);
+                span.hash(state);
+            }
+            Expr::Return(value) => value.hash(state),
+            Expr::Wildcard | Expr::Unit | Expr::Break | Expr::Continue => (),
+        }
+    }
+}
+
+// Combines the items' own hashes with an operation that doesn't care about their order.
+fn hash_unordered<T: Hash, H: Hasher>(items: impl ExactSizeIterator<Item = T>, state: &mut H) {
+    items.len().hash(state);
+    let combined = items.fold(0u64, |combined, item| {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        combined.wrapping_add(hasher.finish())
+    });
+    combined.hash(state);
+}
+
 impl std::fmt::Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Expr::Literal(d) => {
                 write!(f, "{}", d)
             }
-            Expr::ListLiteral { data_type, data } => {
+            Expr::ListLiteral {
+                data_type, data, ..
+            } => {
                 let printed_items = data
                     .iter()
                     .map(|i| i.to_string())
                     .collect::<Vec<String>>()
                     .join(",");
 
-                write!(f, "[{}", printed_items)
+                write!(f, "[{}]", printed_items)
             }
+            Expr::RuntimeList { .. }
+            | Expr::RuntimeMap { .. }
+            | Expr::RuntimeSet { .. }
+            | Expr::RuntimeOptional(_)
+            | Expr::RuntimeSeq(_)
+            | Expr::RuntimeStruct { .. } => self.fmt_value(f, 0),
             Expr::MapLiteral {
                 key_type,
                 value_type,
                 data,
+                ..
             } => {
                 write!(f, "{:?}", &data)
             }
+            Expr::Lambda { value, .. } => write!(f, "{}", value),
+            Expr::EnumValue { type_name, variant } => write!(f, "{}.{}", type_name, variant),
             _ => write!(f, "{:?}", &self),
         }
     }
 }
 
+// Values print at most this many levels deep and this many items per list, map or set, so
+// the REPL never hangs on a huge or deeply nested value. Whatever is cut off shows as '...'.
+const MAX_PRINT_DEPTH: usize = 20;
+const MAX_PRINT_ITEMS: usize = 100;
+
+impl Expr {
+    // 'depth' counts the values this one is inside of.
+    fn fmt_value(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let nested = matches!(
+            self,
+            Expr::RuntimeList { .. }
+                | Expr::RuntimeMap { .. }
+                | Expr::RuntimeSet { .. }
+                | Expr::RuntimeOptional(Some(_))
+                | Expr::RuntimeStruct { .. }
+        );
+        if nested && depth >= MAX_PRINT_DEPTH {
+            return write!(f, "...");
+        }
+        match self {
+            Expr::RuntimeList { data, .. } => {
+                write!(f, "[")?;
+                write_items(f, data, |f, i| i.fmt_value(f, depth + 1))?;
+                write!(f, "]")
+            }
+            Expr::RuntimeMap { data, .. } => {
+                write!(f, "{{")?;
+                write_items(f, data, |f, (k, v)| {
+                    write!(f, "{}: ", LiteralData::from(k.clone()))?;
+                    v.fmt_value(f, depth + 1)
+                })?;
+                write!(f, "}}")
+            }
+            Expr::RuntimeSet { data, .. } => {
+                write!(f, "{{")?;
+                write_items(f, data, |f, k| {
+                    write!(f, "{}", LiteralData::from(k.clone()))
+                })?;
+                write!(f, "}}")
+            }
+            Expr::RuntimeOptional(Some(value)) => {
+                write!(f, "some(")?;
+                value.fmt_value(f, depth + 1)?;
+                write!(f, ")")
+            }
+            Expr::RuntimeOptional(None) => write!(f, "none"),
+            Expr::RuntimeSeq(_) => write!(f, "<sequence>"),
+            Expr::RuntimeStruct { type_name, fields } => {
+                write!(f, "{}(", type_name)?;
+                write_items(f, fields, |f, (name, value)| {
+                    write!(f, "{}: ", name)?;
+                    value.fmt_value(f, depth + 1)
+                })?;
+                write!(f, ")")
+            }
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+// A value's one-line form as it would print 'depth' values down.
+struct AtDepth<'a>(&'a Expr, usize);
+
+impl std::fmt::Display for AtDepth<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_value(f, self.1)
+    }
+}
+
+// Past this many columns a list, map, set or struct goes onto several lines.
+const PRETTY_WIDTH: usize = 80;
+
+impl Expr {
+    // How output() and the REPL show a value. That's its one-line form when it fits in
+    // PRETTY_WIDTH, otherwise each item of the outermost collection gets a line of its own,
+    // indented two spaces, and the same goes for any of those too wide in turn:
+    //
+    //   [
+    //     [1, 2, 3],
+    //     {'a': 1, 'b': 2}
+    //   ]
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0, 0, 0);
+        out
+    }
+
+    // 'indent' is that of the line the value starts on and 'column' where on it the value
+    // starts, after any key; 'depth' is as for fmt_value().
+    fn write_pretty(&self, out: &mut String, indent: usize, column: usize, depth: usize) {
+        let one_line = AtDepth(self, depth).to_string();
+        if column + one_line.chars().count() <= PRETTY_WIDTH || depth >= MAX_PRINT_DEPTH {
+            out.push_str(&one_line);
+            return;
+        }
+        // Each item is some text and then, for those that can nest, a value.
+        let key = |k: &KeyData| LiteralData::from(k.clone()).to_string();
+        let (open, close, items): (String, &str, Vec<(String, Option<&Expr>)>) = match self {
+            Expr::RuntimeList { data, .. } => (
+                "[".to_string(),
+                "]",
+                data.iter().map(|v| (String::new(), Some(v))).collect(),
+            ),
+            Expr::RuntimeMap { data, .. } => (
+                "{".to_string(),
+                "}",
+                data.iter()
+                    .map(|(k, v)| (format!("{}: ", key(k)), Some(v)))
+                    .collect(),
+            ),
+            Expr::RuntimeSet { data, .. } => (
+                "{".to_string(),
+                "}",
+                data.iter().map(|k| (key(k), None)).collect(),
+            ),
+            Expr::RuntimeStruct { type_name, fields } => (
+                format!("{}(", type_name),
+                ")",
+                fields
+                    .iter()
+                    .map(|(name, v)| (format!("{}: ", name), Some(v)))
+                    .collect(),
+            ),
+            _ => {
+                out.push_str(&one_line);
+                return;
+            }
+        };
+        let inner = " ".repeat(indent + 2);
+        out.push_str(&open);
+        out.push('\n');
+        for (i, (text, value)) in items.iter().take(MAX_PRINT_ITEMS).enumerate() {
+            out.push_str(&inner);
+            out.push_str(text);
+            if let Some(value) = value {
+                let column = indent + 2 + text.chars().count();
+                value.write_pretty(out, indent + 2, column, depth + 1);
+            }
+            if i + 1 < items.len() {
+                out.push(',');
+            }
+            out.push('\n');
+        }
+        if items.len() > MAX_PRINT_ITEMS {
+            out.push_str(&inner);
+            out.push_str("...\n");
+        }
+        out.push_str(&" ".repeat(indent));
+        out.push_str(close);
+    }
+}
+
+// Writes items separated by ', ', stopping with '...' after MAX_PRINT_ITEMS of them.
+fn write_items<T>(
+    f: &mut std::fmt::Formatter<'_>,
+    items: impl IntoIterator<Item = T>,
+    mut write_item: impl FnMut(&mut std::fmt::Formatter<'_>, T) -> std::fmt::Result,
+) -> std::fmt::Result {
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        if i == MAX_PRINT_ITEMS {
+            return write!(f, "...");
+        }
+        write_item(f, item)?;
+    }
+    Ok(())
+}
+
+// add(), sub() etc. build tree nodes; they aren't the arithmetic traits.
+#[allow(clippy::should_implement_trait)]
 impl Expr {
     // Makes copies of the initial data emitted by the parser for use at runtime.
     // Only happens once when starting the interpreter, so maximum performance isn't too
@@ -309,9 +1038,10 @@ impl Expr {
             Expr::ListLiteral {
                 ref data_type,
                 ref data,
+                ..
             } => {
                 let upgraded_items = data
-                    .into_iter()
+                    .iter()
                     .map(|i| i.copy_to_runtime_data())
                     .collect::<Vec<Expr>>();
                 Expr::RuntimeList {
@@ -323,17 +1053,19 @@ impl Expr {
                 key_type,
                 value_type,
                 data,
+                ..
             } => {
                 let upgraded_values = data
-                    .into_iter()
+                    .iter()
                     .map(|item| (item.0.clone(), item.1.copy_to_runtime_data()))
-                    .collect::<HashMap<KeyData, Expr>>();
+                    .collect::<IndexMap<KeyData, Expr>>();
                 Expr::RuntimeMap {
                     key_type: key_type.clone(),
                     value_type: value_type.clone(),
                     data: upgraded_values,
                 }
             }
+            Expr::EnumValue { .. } => self.clone(),
             _ => Expr::Unit,
             //_ => panic!("Error converting compiled data into runtime representation:\n -->  '{:?}' \nProbably this is an accidentally unsupported data structure -- a compiler bug.", &self),
         }
@@ -341,53 +1073,464 @@ impl Expr {
 
     pub fn has_value(&self, value: &LiteralData) -> bool {
         if let (Expr::Literal(l), r) = (self, value) {
-            return l == r;
+            l == r
         } else {
             false
         }
     }
 
     pub fn is_data(&self) -> bool {
-        match self {
-            Expr::Literal(_) | Expr::MapLiteral { .. } | Expr::ListLiteral { .. } => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Expr::Literal(_) | Expr::MapLiteral { .. } | Expr::ListLiteral { .. }
+        )
     }
 
-    pub fn equal(l: Expr, r: Expr) -> Expr {
+    pub fn equal(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
         Expr::BinaryExpr {
             left: Box::new(l),
             right: Box::new(r),
             op: Operator::Eq,
+            span,
         }
     }
-    pub fn add(l: Expr, r: Expr) -> Expr {
+    pub fn add(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
         Expr::BinaryExpr {
             left: Box::new(l),
             right: Box::new(r),
             op: Operator::Add,
+            span,
         }
     }
 
-    pub fn sub(l: Expr, r: Expr) -> Expr {
+    pub fn sub(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
         Expr::BinaryExpr {
             left: Box::new(l),
             right: Box::new(r),
             op: Operator::Sub,
+            span,
         }
     }
-    pub fn mul(l: Expr, r: Expr) -> Expr {
+    pub fn mul(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
         Expr::BinaryExpr {
             left: Box::new(l),
             right: Box::new(r),
             op: Operator::Mul,
+            span,
+        }
+    }
+    pub fn wrapping_add(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(l),
+            right: Box::new(r),
+            op: Operator::WrappingAdd,
+            span,
+        }
+    }
+    pub fn wrapping_mul(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(l),
+            right: Box::new(r),
+            op: Operator::WrappingMul,
+            span,
         }
     }
-    pub fn div(l: Expr, r: Expr) -> Expr {
+    pub fn div(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
         Expr::BinaryExpr {
             left: Box::new(l),
             right: Box::new(r),
             op: Operator::Div,
+            span,
+        }
+    }
+    pub fn modulo(l: Expr, r: Expr, span: (usize, usize)) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(l),
+            right: Box::new(r),
+            op: Operator::Modulo,
+            span,
+        }
+    }
+
+    // x |> f is just f(x). Calls go by name, so any other function expression gets
+    // bound to one first.
+    pub fn pipe(value: Expr, function: Expr, span: (usize, usize)) -> Expr {
+        match function {
+            Expr::Variable { name, .. } => Expr::call(&name, value, span),
+            _ => Expr::Block {
+                body: vec![
+                    Expr::Let {
+                        var_name: "right side of |>".to_string(),
+                        index: UNRESOLVED,
+                        data_type: DataType::Unsolved,
+                        value: Box::new(function),
+                        span: (0, 0),
+                    },
+                    Expr::call("right side of |>", value, span),
+                ],
+                spans: Vec::new(),
+                environment: 0,
+            },
+        }
+    }
+
+    // f >> g is a function passing its argument to f and f's result to g. Both sides are
+    // evaluated once, into names no program can write, and the lambda calls them by those.
+    pub fn compose(first: Expr, second: Expr) -> Expr {
+        let bind = |var_name: &str, value: Expr| Expr::Let {
+            var_name: var_name.to_string(),
+            index: UNRESOLVED,
+            data_type: DataType::Unsolved,
+            value: Box::new(value),
+            span: (0, 0),
+        };
+        let param = Expr::Variable {
+            name: "value".to_string(),
+            index: UNRESOLVED,
+        };
+        let body = Expr::call(
+            "right side of >>",
+            Expr::call("left side of >>", param, (0, 0)),
+            (0, 0),
+        );
+        let composed = Expr::Lambda {
+            value: Function {
+                params: vec![Param {
+                    name: "value".to_string(),
+                    data_type: DataType::Unsolved,
+                    default: None,
+                    index: UNRESOLVED,
+                }],
+                return_type: DataType::Unsolved,
+                body: Box::new(Expr::Block {
+                    body: vec![body],
+                    spans: Vec::new(),
+                    environment: 0,
+                }),
+            },
+            environment: 0,
+            captured: Frame::default(),
+        };
+        Expr::Block {
+            body: vec![
+                bind("left side of >>", first),
+                bind("right side of >>", second),
+                composed,
+            ],
+            spans: Vec::new(),
+            environment: 0,
+        }
+    }
+
+    // A block parsed from source, from its statements and their offsets.
+    pub fn list_literal(items: Vec<(Expr, (usize, usize))>) -> Expr {
+        let (data, spans) = items.into_iter().unzip();
+        Expr::ListLiteral {
+            data_type: DataType::Unsolved,
+            data,
+            spans,
+        }
+    }
+
+    pub fn map_literal(entries: Vec<((KeyData, Expr), (usize, usize))>) -> Expr {
+        let (data, spans) = entries.into_iter().unzip();
+        Expr::MapLiteral {
+            key_type: DataType::Unsolved,
+            value_type: DataType::Unsolved,
+            data,
+            spans,
+        }
+    }
+
+    pub fn block(statements: Vec<(Expr, (usize, usize))>) -> Expr {
+        let (body, spans) = statements.into_iter().unzip();
+        Expr::Block {
+            body,
+            spans,
+            environment: 0,
+        }
+    }
+
+    // The branches of a 'cond' as nested ifs, the first whose condition holds giving the
+    // value and 'otherwise' the one after them all.
+    pub fn cond(branches: Vec<(Expr, (usize, usize), Expr)>, otherwise: Expr) -> Expr {
+        branches
+            .into_iter()
+            .rev()
+            .fold(otherwise, |rest, (cond, cond_span, value)| Expr::If {
+                cond: Box::new(cond),
+                then: Box::new(value),
+                final_else: Box::new(rest),
+                cond_span,
+            })
+    }
+
+    // '(+)' and the like: a lambda applying the operator to its two parameters, which take
+    // their types from where it's used.
+    pub fn operator_function(op: Operator) -> Expr {
+        let param = |name: &str| Param {
+            name: name.to_string(),
+            data_type: DataType::Unsolved,
+            default: None,
+            index: UNRESOLVED,
+        };
+        let operand = |name: &str| {
+            Box::new(Expr::Variable {
+                name: name.to_string(),
+                index: UNRESOLVED,
+            })
+        };
+        let body = Expr::BinaryExpr {
+            left: operand("left operand"),
+            op,
+            right: operand("right operand"),
+            span: (0, 0),
+        };
+        Expr::Lambda {
+            value: Function {
+                params: vec![param("left operand"), param("right operand")],
+                return_type: DataType::Unsolved,
+                body: Box::new(Expr::Block {
+                    body: vec![body],
+                    spans: Vec::new(),
+                    environment: 0,
+                }),
+            },
+            environment: 0,
+            captured: Frame::default(),
+        }
+    }
+
+    // The bindings of a 'where' become lets in a block of their own ahead of the value, so
+    // nothing outside it sees them. Each comes with the byte offsets of its value.
+    pub fn with_bindings(value: Expr, bindings: Vec<(String, Expr, (usize, usize))>) -> Expr {
+        let mut body: Vec<Expr> = bindings
+            .into_iter()
+            .map(|(var_name, binding, span)| Expr::Let {
+                var_name,
+                index: UNRESOLVED,
+                data_type: DataType::Unsolved,
+                value: Box::new(binding),
+                span,
+            })
+            .collect();
+        body.push(value);
+        Expr::Block {
+            body,
+            spans: Vec::new(),
+            environment: 0,
+        }
+    }
+
+    // The index and function value of a 'function main()' at the top level of a program.
+    pub fn main_function(&self) -> Option<((usize, usize), &Expr)> {
+        let (Expr::Block { body, .. } | Expr::Program { body, .. }) = self else {
+            return None;
+        };
+        body.iter().find_map(|e| match e {
+            Expr::DefineFunction {
+                fn_name,
+                index,
+                value,
+            } if fn_name == "main" => Some((*index, value.as_ref())),
+            _ => None,
+        })
+    }
+
+    // The names and indexes of the top-level functions that '--run-tests' runs: those
+    // named test_<something> that take no arguments, in the order they're defined.
+    pub fn test_functions(&self) -> Vec<(String, (usize, usize))> {
+        let (Expr::Block { body, .. } | Expr::Program { body, .. }) = self else {
+            return Vec::new();
+        };
+        body.iter()
+            .filter_map(|e| match e {
+                Expr::DefineFunction {
+                    fn_name,
+                    index,
+                    value,
+                } if fn_name.starts_with("test_") => match value.as_ref() {
+                    Expr::Lambda { value, .. } if value.params.is_empty() => {
+                        Some((fn_name.clone(), *index))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The parser gives the 'for ... in ... if ...' part as the variable, source and filter.
+    pub fn comprehension(
+        key: Option<Expr>,
+        element: Expr,
+        (var_name, source, filter): (String, Expr, Option<Expr>),
+    ) -> Expr {
+        Expr::Comprehension {
+            var_name,
+            index: UNRESOLVED,
+            source: Box::new(source),
+            key: key.map(Box::new),
+            element: Box::new(element),
+            filter: filter.map(Box::new),
+            environment: 0,
+        }
+    }
+
+    // 'while let some(x) = next() { body }' keeps the optional in a variable no program can
+    // name and loops while it holds something, binding x to that at the top of the body
+    // and working out the optional again as the loop's step. It comes out as
+    //
+    //   { let <optional> = next(); while is_some(<optional>) {
+    //       let x = unwrap(<optional>); body } step <optional> := next() }
+    //
+    // where is_some() and unwrap() are always the builtins. 'span' is the optional's.
+    pub fn while_let(
+        var_name: String,
+        value: Expr,
+        statements: Vec<(Expr, (usize, usize))>,
+        span: (usize, usize),
+    ) -> Expr {
+        let (mut body, mut spans): (Vec<Expr>, Vec<(usize, usize)>) =
+            statements.into_iter().unzip();
+        spans.insert(0, (0, 0));
+        let optional_name = format!("{} optional", var_name);
+        let optional = || Expr::Variable {
+            name: optional_name.clone(),
+            index: UNRESOLVED,
+        };
+        let builtin = |fn_name: &str| Expr::BuiltinCall {
+            fn_name: fn_name.to_string(),
+            args: vec![optional()],
+            span,
+        };
+        body.insert(
+            0,
+            Expr::Let {
+                var_name,
+                index: UNRESOLVED,
+                data_type: DataType::Unsolved,
+                value: Box::new(builtin("unwrap")),
+                span,
+            },
+        );
+        let next = Expr::Assign {
+            name: optional_name.clone(),
+            value: Box::new(value.clone()),
+            index: UNRESOLVED,
+        };
+        let first = Expr::Let {
+            var_name: optional_name.clone(),
+            index: UNRESOLVED,
+            data_type: DataType::Unsolved,
+            value: Box::new(value),
+            span,
+        };
+        let repeat = Expr::While {
+            cond: Box::new(builtin("is_some")),
+            body: Box::new(Expr::Block {
+                body,
+                spans,
+                environment: 0,
+            }),
+            step: Some(Box::new(next)),
+        };
+        Expr::Block {
+            body: vec![first, repeat],
+            spans: Vec::new(),
+            environment: 0,
+        }
+    }
+
+    // 'n times { body }' runs the body n times, or not at all when n isn't positive. Like
+    // 'while let' it counts in variables no program can name:
+    //
+    //   { let <count>: Int = n; let <done> = 0; while <done> < <count> {
+    //       body } step <done> := <done> + 1 }
+    //
+    // so n is worked out once, before the first run. 'span' is n's.
+    pub fn times(
+        count: Expr,
+        statements: Vec<(Expr, (usize, usize))>,
+        span: (usize, usize),
+    ) -> Expr {
+        let (body, spans): (Vec<Expr>, Vec<(usize, usize)>) = statements.into_iter().unzip();
+        let variable = |name: &str| Expr::Variable {
+            name: name.to_string(),
+            index: UNRESOLVED,
+        };
+        let one = Expr::Literal(LiteralData::Int(1));
+        let increment = Expr::Assign {
+            name: "times done".to_string(),
+            value: Box::new(Expr::add(variable("times done"), one, span)),
+            index: UNRESOLVED,
+        };
+        let count = Expr::Let {
+            var_name: "times count".to_string(),
+            index: UNRESOLVED,
+            data_type: DataType::Int,
+            value: Box::new(count),
+            span,
+        };
+        let done = Expr::Let {
+            var_name: "times done".to_string(),
+            index: UNRESOLVED,
+            data_type: DataType::Int,
+            value: Box::new(Expr::Literal(LiteralData::Int(0))),
+            span,
+        };
+        let repeat = Expr::While {
+            cond: Box::new(Expr::BinaryExpr {
+                left: Box::new(variable("times done")),
+                op: Operator::Lt,
+                right: Box::new(variable("times count")),
+                span,
+            }),
+            body: Box::new(Expr::Block {
+                body,
+                spans,
+                environment: 0,
+            }),
+            step: Some(Box::new(increment)),
+        };
+        Expr::Block {
+            body: vec![count, done, repeat],
+            spans: Vec::new(),
+            environment: 0,
+        }
+    }
+
+    // x.f(a, b) is f(x, a, b), so any function, builtin or not, chains like a method.
+    pub fn method_call(
+        receiver: Expr,
+        fn_name: String,
+        args: Vec<KeywordArg>,
+        span: (usize, usize),
+    ) -> Expr {
+        let mut all_args = vec![KeywordArg {
+            name: String::new(),
+            value: receiver,
+        }];
+        all_args.extend(args);
+        Expr::Call {
+            fn_name,
+            index: UNRESOLVED,
+            args: all_args,
+            method: true,
+            span,
+        }
+    }
+
+    fn call(fn_name: &str, arg: Expr, span: (usize, usize)) -> Expr {
+        Expr::Call {
+            fn_name: fn_name.to_string(),
+            index: UNRESOLVED,
+            args: vec![KeywordArg {
+                name: String::new(),
+                value: arg,
+            }],
+            method: false,
+            span,
         }
     }
 }
@@ -0,0 +1,50 @@
+use crate::grammar;
+use lalrpop_util::ParseError;
+
+// What a token is, at the level syntax highlighting cares about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Keyword,
+    // Built-in type names like 'Int' and 'List of'.
+    TypeName,
+    Identifier,
+    Int,
+    Flt,
+    Str,
+    Bool,
+    Operator,
+    Punctuation,
+}
+
+// The source has something at 'offset' that isn't the start of any token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LexError {
+    pub offset: usize,
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unrecognized token at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+// The tokens of 'src' with their byte offsets, start and end, as the parser sees them.
+// Whitespace is skipped. Works on source that doesn't parse, as long as it lexes.
+pub fn tokenize(src: &str) -> Result<Vec<(usize, TokenKind, usize)>, LexError> {
+    grammar::TokensParser::new()
+        .parse(src)
+        .map_err(|e| match e {
+            ParseError::InvalidToken { location }
+            | ParseError::UnrecognizedEof { location, .. } => LexError { offset: location },
+            ParseError::UnrecognizedToken {
+                token: (location, ..),
+                ..
+            }
+            | ParseError::ExtraToken {
+                token: (location, ..),
+            } => LexError { offset: location },
+            ParseError::User { .. } => LexError { offset: 0 },
+        })
+}
@@ -0,0 +1,248 @@
+use crate::interpreter::RuntimeError;
+use crate::syntax::{DataType, Expr, KeyData, LiteralData};
+use indexmap::{IndexMap, IndexSet};
+
+// The result of running Lift code as plain Rust data, for programs embedding the
+// interpreter and their tests. Equality is the language's '=': lists and structs are
+// equal when everything in them is, and NaN equals nothing. Maps and sets keep the
+// order their entries were added in, but that order doesn't matter to equality.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Int(i64),
+    Flt(f64),
+    Str(String),
+    Bool(bool),
+    List(Vec<Value>),
+    Set(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Optional(Option<Box<Value>>),
+    Struct {
+        type_name: String,
+        fields: Vec<(String, Value)>,
+    },
+    Enum {
+        type_name: String,
+        variant: String,
+    },
+    // Functions only come out as their signature, like "function(x: Int): Int".
+    Function(String),
+    Unit,
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Flt(a), Value::Flt(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Set(a), Value::Set(b)) => {
+                a.iter().all(|item| b.contains(item)) && b.iter().all(|item| a.contains(item))
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                let has_all = |some: &[(Value, Value)], others: &[(Value, Value)]| {
+                    some.iter().all(|entry| others.contains(entry))
+                };
+                has_all(a, b) && has_all(b, a)
+            }
+            (Value::Optional(a), Value::Optional(b)) => a == b,
+            (
+                Value::Struct {
+                    type_name: a_type,
+                    fields: a_fields,
+                },
+                Value::Struct {
+                    type_name: b_type,
+                    fields: b_fields,
+                },
+            ) => a_type == b_type && a_fields == b_fields,
+            (
+                Value::Enum {
+                    type_name: a_type,
+                    variant: a_variant,
+                },
+                Value::Enum {
+                    type_name: b_type,
+                    variant: b_variant,
+                },
+            ) => a_type == b_type && a_variant == b_variant,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Unit, Value::Unit) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<LiteralData> for Value {
+    fn from(data: LiteralData) -> Value {
+        match data {
+            LiteralData::Int(i) => Value::Int(i),
+            LiteralData::Flt(f) => Value::Flt(f),
+            LiteralData::Str(s) => {
+                let text = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
+                Value::Str(text.unwrap_or(&s).to_string())
+            }
+            LiteralData::Bool(b) => Value::Bool(b),
+        }
+    }
+}
+
+impl TryFrom<&Expr> for Value {
+    type Error = RuntimeError;
+
+    fn try_from(e: &Expr) -> Result<Value, RuntimeError> {
+        let value = match e {
+            Expr::Literal(d) | Expr::RuntimeData(d) => Value::from(d.clone()),
+            Expr::RuntimeList { data, .. } => Value::List(to_values(data)?),
+            Expr::RuntimeSet { data, .. } => Value::Set(
+                data.iter()
+                    .map(|k| Value::from(LiteralData::from(k.clone())))
+                    .collect(),
+            ),
+            Expr::RuntimeMap { data, .. } => {
+                let mut entries = Vec::new();
+                for (k, v) in data {
+                    entries.push((Value::from(LiteralData::from(k.clone())), v.try_into()?));
+                }
+                Value::Map(entries)
+            }
+            Expr::RuntimeOptional(inner) => match inner {
+                Some(inner) => Value::Optional(Some(Box::new(inner.as_ref().try_into()?))),
+                None => Value::Optional(None),
+            },
+            Expr::RuntimeStruct { type_name, fields } => {
+                let mut values = Vec::new();
+                for (name, field) in fields {
+                    values.push((name.clone(), field.try_into()?));
+                }
+                Value::Struct {
+                    type_name: type_name.clone(),
+                    fields: values,
+                }
+            }
+            Expr::EnumValue { type_name, variant } => Value::Enum {
+                type_name: type_name.clone(),
+                variant: variant.clone(),
+            },
+            Expr::Lambda { value, .. } => Value::Function(value.to_string()),
+            Expr::Unit => Value::Unit,
+            _ => {
+                let msg = format!("'{}' isn't a value the interpreter produces", e);
+                return Err(RuntimeError::new(&msg, None, None));
+            }
+        };
+        Ok(value)
+    }
+}
+
+impl Value {
+    // The interpreter's form of this value, for a place expecting 'data_type'. Collections
+    // take their element types from it, as far as it says.
+    pub(crate) fn to_runtime(&self, data_type: &DataType) -> Result<Expr, RuntimeError> {
+        let value = match self {
+            Value::Int(i) => Expr::Literal(LiteralData::Int(*i)),
+            Value::Flt(f) => Expr::Literal(LiteralData::Flt(*f)),
+            Value::Str(s) => Expr::Literal(LiteralData::Str(format!("'{}'", s).into())),
+            Value::Bool(b) => Expr::Literal(LiteralData::Bool(*b)),
+            Value::List(items) => {
+                let element_type = match data_type {
+                    DataType::List { element_type } => element_type.as_ref().clone(),
+                    _ => DataType::Unsolved,
+                };
+                let mut data = Vec::with_capacity(items.len());
+                for item in items {
+                    data.push(item.to_runtime(&element_type)?);
+                }
+                Expr::RuntimeList {
+                    data_type: element_type,
+                    data,
+                }
+            }
+            Value::Set(items) => {
+                let element_type = match data_type {
+                    DataType::Set(element_type) => element_type.as_ref().clone(),
+                    _ => DataType::Unsolved,
+                };
+                let mut data = IndexSet::with_capacity(items.len());
+                for item in items {
+                    data.insert(item.to_key()?);
+                }
+                Expr::RuntimeSet {
+                    data_type: element_type,
+                    data,
+                }
+            }
+            Value::Map(entries) => {
+                let (key_type, value_type) = match data_type {
+                    DataType::Map {
+                        key_type,
+                        value_type,
+                    } => (key_type.as_ref().clone(), value_type.as_ref().clone()),
+                    _ => (DataType::Unsolved, DataType::Unsolved),
+                };
+                let mut data = IndexMap::with_capacity(entries.len());
+                for (k, v) in entries {
+                    data.insert(k.to_key()?, v.to_runtime(&value_type)?);
+                }
+                Expr::RuntimeMap {
+                    key_type,
+                    value_type,
+                    data,
+                }
+            }
+            Value::Optional(inner) => {
+                let inner_type = match data_type {
+                    DataType::Optional(inner_type) => inner_type.as_ref().clone(),
+                    _ => DataType::Unsolved,
+                };
+                match inner {
+                    Some(inner) => {
+                        Expr::RuntimeOptional(Some(Box::new(inner.to_runtime(&inner_type)?)))
+                    }
+                    None => Expr::RuntimeOptional(None),
+                }
+            }
+            Value::Struct { type_name, fields } => {
+                let mut runtime_fields = Vec::with_capacity(fields.len());
+                for (name, field) in fields {
+                    runtime_fields.push((name.clone(), field.to_runtime(&DataType::Unsolved)?));
+                }
+                Expr::RuntimeStruct {
+                    type_name: type_name.clone(),
+                    fields: runtime_fields,
+                }
+            }
+            Value::Enum { type_name, variant } => Expr::EnumValue {
+                type_name: type_name.clone(),
+                variant: variant.clone(),
+            },
+            Value::Unit => Expr::Unit,
+            Value::Function(signature) => {
+                let msg = format!(
+                    "A function ({}) can't be passed back in as a value",
+                    signature
+                );
+                return Err(RuntimeError::new(&msg, None, None));
+            }
+        };
+        Ok(value)
+    }
+
+    // Map keys and set elements are Ints, Strs and Bools.
+    fn to_key(&self) -> Result<KeyData, RuntimeError> {
+        match self {
+            Value::Int(i) => Ok(KeyData::Int(*i)),
+            Value::Str(s) => Ok(KeyData::Str(format!("'{}'", s).into())),
+            Value::Bool(b) => Ok(KeyData::Bool(*b)),
+            _ => {
+                let msg = format!("{:?} can't be used as a map key", self);
+                Err(RuntimeError::new(&msg, None, None))
+            }
+        }
+    }
+}
+
+fn to_values(data: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+    data.iter().map(Value::try_from).collect()
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use std::process::Command;
+
+// Runs the lift-lang binary on a program file holding 'src', giving its exit code and
+// what it wrote to stderr.
+fn run_program(name: &str, src: &str) -> (Option<i32>, String) {
+    let path = std::env::temp_dir().join(format!("lift-cli-{}-{}.lt", name, std::process::id()));
+    fs::write(&path, src).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lift-lang"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn test_exit_codes() {
+    let (code, _) = run_program("main", "{ function main(): Int { 3 } }");
+    assert_eq!(Some(3), code);
+
+    // A runtime error in main() is a failure, not a normal exit.
+    let src = "{ function main(): Int { let zero = 0; 7 / zero } }";
+    let (code, stderr) = run_program("failing-main", src);
+    assert_eq!(Some(1), code);
+    assert!(stderr.contains("Division by zero"));
+
+    // Likewise outside of main().
+    let (code, _) = run_program("failing", "{ let zero = 0; 7 / zero }");
+    assert_eq!(Some(1), code);
+}